@@ -0,0 +1,294 @@
+use bavytest::plugins::bot::{BotPlugin, BotRoute};
+use bavytest::plugins::menu::GameState;
+use bavytest::plugins::network::config::{NetworkConfig, command_line};
+use bavytest::plugins::network::connection::ConnectionState;
+use bavytest::plugins::network::identity::PlayerIdentity;
+use bavytest::plugins::network::resource::{NetworkStats, Rejected};
+use bavytest::plugins::network::token::{self, JoinClaims};
+use bavytest::plugins::network::{MultiplayerPlugin, WebSocketTransport};
+use bevy::gltf::Gltf;
+use bevy::mesh::MeshPlugin;
+use bevy::prelude::*;
+use bevy::scene::ScenePlugin;
+use bevy::state::app::StatesPlugin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::runtime::Builder;
+
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+const TOKEN_SLACK: u64 = 60; // seconds a bot's token outlives the run
+
+// everything else on the command line is for `NetworkConfig` and `MapSettings`
+struct BotsConfig {
+    bots: usize,
+    duration: Duration,
+    fps: f64,
+    route: BotRoute,
+    // signs each bot its own join token, for servers that want one
+    token_secret: Option<String>,
+}
+
+impl BotsConfig {
+    fn from_args() -> Self {
+        let mut config = Self {
+            bots: 10,
+            duration: Duration::from_secs(60),
+            fps: 60.0,
+            route: BotRoute::default(),
+            token_secret: std::env::var("SERVER_TOKEN_SECRET").ok(),
+        };
+
+        for (key, value) in command_line() {
+            match key.as_str() {
+                "bots" => match value.parse() {
+                    Ok(bots) => config.bots = bots,
+                    Err(_) => eprintln!("--bots expects a number"),
+                },
+                "duration" => match value.parse() {
+                    Ok(secs) if secs > 0.0 => config.duration = Duration::from_secs_f64(secs),
+                    _ => eprintln!("--duration expects seconds"),
+                },
+                "fps" => match value.parse() {
+                    Ok(fps) if fps > 0.0 => config.fps = fps,
+                    _ => eprintln!("--fps expects frames per second"),
+                },
+                "route" => match BotRoute::parse(&value) {
+                    Some(route) => config.route = route,
+                    None => eprintln!("--route expects wander or course"),
+                },
+                "token_secret" => config.token_secret = Some(value),
+                _ => {}
+            }
+        }
+
+        config
+    }
+}
+
+fn bot_app(index: usize, transport: &WebSocketTransport, config: &BotsConfig) -> App {
+    let identity = PlayerIdentity {
+        id: rand::random_range(1..i64::MAX),
+        name: format!("bot{}", index),
+    };
+    let mut network = NetworkConfig::load();
+    if let Some(secret) = &config.token_secret {
+        let claims = JoinClaims {
+            id: identity.id,
+            name: identity.name.clone(),
+            expires: token::unix_now() + config.duration.as_secs() + TOKEN_SLACK,
+        };
+        network.token = token::issue(&claims, secret.as_bytes());
+    }
+
+    let mut app = App::new();
+    app.add_plugins((
+        MinimalPlugins,
+        StatesPlugin,
+        // remote players still get a model, even if nobody looks at it
+        AssetPlugin {
+            watch_for_changes_override: Some(false),
+            ..default()
+        },
+        MeshPlugin,
+        ScenePlugin,
+    ))
+    .init_asset::<Gltf>()
+    .insert_resource(identity)
+    .insert_resource(network)
+    .add_plugins((
+        MultiplayerPlugin::new(transport.clone()),
+        BotPlugin {
+            route: config.route,
+        },
+    ))
+    .insert_state(GameState::Playing);
+    app
+}
+
+/////////////////////////////////////////////////////////
+//////////////////////// Stats //////////////////////////
+/////////////////////////////////////////////////////////
+#[derive(Default)]
+struct Report {
+    // every connected bot's round trip, once a second, in seconds
+    round_trips: Vec<f64>,
+    jitter: Vec<f64>,
+    packet_loss: Vec<f32>,
+    frame_times: Vec<f64>,
+    frames: u64,
+}
+
+fn percentile(sorted: &[f64], share: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    sorted[((sorted.len() - 1) as f64 * share).round() as usize]
+}
+
+fn mean<T: Copy + Into<f64>>(values: &[T]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.iter().map(|value| (*value).into()).sum::<f64>() / values.len() as f64
+}
+
+fn is_connected(app: &App) -> bool {
+    app.world()
+        .get_resource::<State<ConnectionState>>()
+        .is_some_and(|state| *state.get() == ConnectionState::Connected)
+}
+
+fn stats(app: &App) -> Option<&NetworkStats> {
+    app.world().get_resource::<NetworkStats>()
+}
+
+fn totals(apps: &[App]) -> (u64, u64) {
+    apps.iter()
+        .filter_map(stats)
+        .fold((0, 0), |(out, into), stats| {
+            (out + stats.bytes_out, into + stats.bytes_in)
+        })
+}
+
+fn kib_per_sec(bytes: u64, secs: f64) -> f64 {
+    bytes as f64 / 1024.0 / secs.max(f64::EPSILON)
+}
+
+fn main() {
+    let config = BotsConfig::from_args();
+
+    // one runtime for every bot's socket, like a single client would have
+    let runtime = Arc::new(
+        Builder::new_multi_thread()
+            .worker_threads(2)
+            .thread_name("bot-workers")
+            .enable_all()
+            .build()
+            .expect("Failed to create Tokio runtime for bots"),
+    );
+    let transport = WebSocketTransport::with_runtime(runtime);
+
+    println!(
+        "Starting {} bots ({:?}) for {:?}",
+        config.bots, config.route, config.duration
+    );
+    let mut apps: Vec<App> = (0..config.bots)
+        .map(|index| bot_app(index, &transport, &config))
+        .collect();
+
+    let frame = Duration::from_secs_f64(1.0 / config.fps);
+    let started = Instant::now();
+    let mut last_sample = started;
+    let mut last_report = (started, 0, 0);
+    let mut report = Report::default();
+
+    while started.elapsed() < config.duration {
+        let frame_start = Instant::now();
+        for app in &mut apps {
+            app.update();
+        }
+        // turned away for good, no use keeping them around
+        apps.retain(|app| match app.world().get_resource::<Rejected>() {
+            Some(Rejected(reason)) => {
+                eprintln!("A bot was rejected: {}", reason);
+                false
+            }
+            None => true,
+        });
+        let frame_time = frame_start.elapsed();
+        report.frame_times.push(frame_time.as_secs_f64());
+        report.frames += 1;
+
+        if last_sample.elapsed() >= SAMPLE_INTERVAL {
+            last_sample = Instant::now();
+            // no pong yet means no round trip to speak of
+            for stats in apps
+                .iter()
+                .filter_map(stats)
+                .filter(|s| !s.round_trips.is_empty())
+            {
+                report.round_trips.push(stats.rtt);
+                report.jitter.push(stats.jitter);
+                report.packet_loss.push(stats.packet_loss);
+            }
+        }
+
+        if last_report.0.elapsed() >= REPORT_INTERVAL {
+            let (out, into) = totals(&apps);
+            let secs = last_report.0.elapsed().as_secs_f64();
+            let rtt = apps
+                .iter()
+                .filter_map(stats)
+                .filter(|s| !s.round_trips.is_empty())
+                .map(|s| s.rtt)
+                .collect::<Vec<_>>();
+            println!(
+                "[{:>4.0}s] {}/{} connected, out {:.1} KiB/s, in {:.1} KiB/s, rtt {:.0} ms",
+                started.elapsed().as_secs_f64(),
+                apps.iter().filter(|app| is_connected(app)).count(),
+                config.bots,
+                kib_per_sec(out - last_report.1, secs),
+                kib_per_sec(into - last_report.2, secs),
+                mean(&rtt) * 1000.0,
+            );
+            last_report = (Instant::now(), out, into);
+        }
+
+        // one frame for all of them, if they don't fit the frame time shows it
+        if let Some(rest) = frame.checked_sub(frame_time) {
+            std::thread::sleep(rest);
+        }
+    }
+
+    let secs = started.elapsed().as_secs_f64();
+    let connected = apps.iter().filter(|app| is_connected(app)).count();
+    let (out, into) = totals(&apps);
+    let (dropped, coalesced) = apps.iter().filter_map(stats).fold((0, 0), |(d, c), stats| {
+        (d + stats.dropped, c + stats.coalesced)
+    });
+
+    let mut round_trips = report.round_trips;
+    round_trips.sort_by(f64::total_cmp);
+    let mut frame_times = report.frame_times;
+    frame_times.sort_by(f64::total_cmp);
+    let per_bot = config.bots.max(1) as f64;
+
+    println!();
+    println!(
+        "{} bots for {:.0}s, {} connected at the end",
+        config.bots, secs, connected
+    );
+    println!(
+        "sent     {:.1} KiB/s total, {:.2} KiB/s per bot",
+        kib_per_sec(out, secs),
+        kib_per_sec(out, secs) / per_bot
+    );
+    println!(
+        "received {:.1} KiB/s total, {:.2} KiB/s per bot",
+        kib_per_sec(into, secs),
+        kib_per_sec(into, secs) / per_bot
+    );
+    println!(
+        "rtt      p50 {:.1} ms, p95 {:.1} ms, p99 {:.1} ms, max {:.1} ms ({} samples)",
+        percentile(&round_trips, 0.5) * 1000.0,
+        percentile(&round_trips, 0.95) * 1000.0,
+        percentile(&round_trips, 0.99) * 1000.0,
+        round_trips.last().copied().unwrap_or_default() * 1000.0,
+        round_trips.len()
+    );
+    println!(
+        "jitter   {:.1} ms, packet loss {:.1}%",
+        mean(&report.jitter) * 1000.0,
+        mean(&report.packet_loss) * 100.0
+    );
+    println!("queues   {} dropped, {} coalesced", dropped, coalesced);
+    // round trips include waiting for the next frame, slow frames mean the bots are the bottleneck
+    println!(
+        "frames   {} at {:.1} ms on average, p95 {:.1} ms (budget {:.1} ms)",
+        report.frames,
+        mean(&frame_times) * 1000.0,
+        percentile(&frame_times, 0.95) * 1000.0,
+        frame.as_secs_f64() * 1000.0
+    );
+}
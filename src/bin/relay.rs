@@ -0,0 +1,25 @@
+use bavytest::plugins::network::config::command_line;
+use bavytest::relay;
+use tokio::runtime::Builder;
+
+fn main() {
+    let mut bind = std::env::var("RELAY_BIND").unwrap_or_else(|_| relay::DEFAULT_BIND.to_string());
+
+    for (key, value) in command_line() {
+        match key.as_str() {
+            "bind" => bind = value,
+            _ => eprintln!("Unknown relay argument: --{}", key.replace('_', "-")),
+        }
+    }
+
+    let runtime = Builder::new_multi_thread()
+        .worker_threads(2)
+        .thread_name("relay-workers")
+        .enable_all()
+        .build()
+        .expect("Failed to create Tokio runtime for relay");
+
+    if let Err(e) = runtime.block_on(relay::run(&bind)) {
+        eprintln!("Relay stopped: {:?}", e);
+    }
+}
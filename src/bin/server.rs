@@ -0,0 +1,40 @@
+use avian3d::PhysicsPlugins;
+use bavytest::plugins::map::{MapPlugin, MapSettings};
+use bavytest::plugins::menu::GameState;
+use bavytest::plugins::server::ServerPlugin;
+use bavytest::plugins::server::resource::ServerConfig;
+use bevy::app::ScheduleRunnerPlugin;
+use bevy::log::LogPlugin;
+use bevy::mesh::MeshPlugin;
+use bevy::prelude::*;
+use bevy::scene::ScenePlugin;
+use bevy::state::app::StatesPlugin;
+use std::time::Duration;
+
+fn main() {
+    let config = ServerConfig::from_args();
+    let tick = Duration::from_secs_f64(1.0 / config.tick_rate);
+    let map = MapSettings {
+        seed: config.seed.unwrap_or_else(rand::random),
+        ..default()
+    };
+
+    App::new()
+        .insert_resource(map)
+        .add_plugins((
+            MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(tick)),
+            LogPlugin::default(),
+            StatesPlugin,
+            TransformPlugin,
+            // the physics collider backend expects mesh and scene assets to exist
+            AssetPlugin::default(),
+            MeshPlugin,
+            ScenePlugin,
+            PhysicsPlugins::default(),
+            MapPlugin,
+            ServerPlugin,
+        ))
+        .insert_resource(config)
+        .insert_state(GameState::Playing)
+        .run();
+}
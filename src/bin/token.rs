@@ -0,0 +1,55 @@
+use bavytest::plugins::network::config::command_line;
+use bavytest::plugins::network::identity::sanitize_name;
+use bavytest::plugins::network::token::{self, JoinClaims};
+
+const DEFAULT_TTL: u64 = 24 * 60 * 60; // a day
+
+// prints a join token for the server started with the same secret
+fn main() {
+    let mut secret = std::env::var("SERVER_TOKEN_SECRET").ok();
+    let mut id = None;
+    let mut name = String::new();
+    let mut ttl = DEFAULT_TTL;
+
+    for (key, value) in command_line() {
+        match key.as_str() {
+            "secret" => secret = Some(value),
+            "id" => match value.parse::<i64>() {
+                Ok(given) if given > 0 => id = Some(given),
+                _ => fail("--id expects a positive number"),
+            },
+            "name" => name = value,
+            "ttl" => match value.parse() {
+                Ok(seconds) => ttl = seconds,
+                Err(_) => fail("--ttl expects seconds"),
+            },
+            _ => fail(&format!(
+                "Unknown token argument: --{}",
+                key.replace('_', "-")
+            )),
+        }
+    }
+
+    let Some(secret) = secret.filter(|secret| !secret.is_empty()) else {
+        fail("no secret, pass --secret or set SERVER_TOKEN_SECRET");
+    };
+    // positive like everyone else's, the server is 0
+    let id = id.unwrap_or_else(|| rand::random_range(1..i64::MAX));
+    let claims = JoinClaims {
+        id,
+        name: sanitize_name(&name, id),
+        expires: token::unix_now() + ttl,
+    };
+
+    eprintln!(
+        "Token for {} ({}), valid for {}s",
+        claims.name, claims.id, ttl
+    );
+    println!("{}", token::issue(&claims, secret.as_bytes()));
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    eprintln!("usage: token --secret <secret> [--id <n>] [--name <name>] [--ttl <seconds>]");
+    std::process::exit(1);
+}
@@ -1,4 +1,5 @@
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
 #[derive(Component)]
 pub struct Player;
@@ -12,3 +13,7 @@ pub struct PlayerAnimation;
 #[derive(Component)]
 pub struct LocalPlayer;
 
+/// The name shown above a player
+#[derive(Component, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DisplayName(pub String);
+
@@ -1,18 +1,19 @@
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Component)]
+#[derive(Component, Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Health {
     pub current: f32,
     pub max: f32,
 }
 
-#[derive(Component)]
+#[derive(Component, Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Hunger {
     pub current: f32,
     pub max: f32,
 }
 
-#[derive(Component)]
+#[derive(Component, Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Stamina {
     pub current: f32,
     pub max: f32,
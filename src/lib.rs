@@ -0,0 +1,4 @@
+pub mod components;
+pub mod plugins;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod relay;
@@ -1,18 +1,21 @@
-mod plugins;
-mod components;
-
 use avian3d::PhysicsPlugins;
-use plugins::map::MapPlugin;
-use plugins::menu::{MenuPlugin, GameState};
-use plugins::player::PlayerPlugin;
+use bavytest::plugins::chat::ChatPlugin;
+use bavytest::plugins::lobby::LobbyPlugin;
+use bavytest::plugins::map::MapPlugin;
+use bavytest::plugins::menu::{MenuPlugin, GameState};
+use bavytest::plugins::nameplate::NameplatePlugin;
+use bavytest::plugins::player::PlayerPlugin;
 use bevy::prelude::*;
 use bevy::window::CursorGrabMode;
 use bevy::window::CursorOptions;
-use plugins::network::MultiplayerPlugin;
+use bavytest::plugins::network::MultiplayerPlugin;
+#[cfg(not(target_arch = "wasm32"))]
+use bavytest::plugins::network::recording::PlaybackTransport;
 
 
 fn main() {
-    App::new()
+    let mut app = App::new();
+    app
         .add_plugins((
             DefaultPlugins.set(WindowPlugin {
                 primary_window: Some(Window {
@@ -27,11 +30,34 @@ fn main() {
             MenuPlugin,
             PlayerPlugin,
             MapPlugin,
-            MultiplayerPlugin,
+            NameplatePlugin,
+            ChatPlugin,
+            LobbyPlugin,
         ))
         .add_systems(Startup, setup)
-        .add_systems(Update, grab_mouse.run_if(in_state(GameState::Playing)))
-        .run();
+        .add_systems(Update, grab_mouse.run_if(in_state(GameState::Playing)));
+    add_multiplayer(&mut app);
+    app.run();
+}
+
+// `--playback <file>` replays a recorded session instead of going online
+#[cfg(not(target_arch = "wasm32"))]
+fn add_multiplayer(app: &mut App) {
+    let playback = bavytest::plugins::network::config::command_line_value("playback")
+        .filter(|path| !path.is_empty());
+    match playback.map(|path| (PlaybackTransport::load(&path), path)) {
+        Some((Ok(transport), _)) => app.add_plugins(MultiplayerPlugin::new(transport)),
+        Some((Err(e), path)) => {
+            eprintln!("Can't play back {}: {}", path, e);
+            app.add_plugins(MultiplayerPlugin::default())
+        }
+        None => app.add_plugins(MultiplayerPlugin::default()),
+    };
+}
+
+#[cfg(target_arch = "wasm32")]
+fn add_multiplayer(app: &mut App) {
+    app.add_plugins(MultiplayerPlugin::default());
 }
 
 fn setup(
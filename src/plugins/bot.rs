@@ -0,0 +1,219 @@
+//////////////////////////////////////////////////////////
+///////////////////////// bot ////////////////////////////
+//////////////////////////////////////////////////////////
+//
+// A player nobody controls, for load testing. It hops from platform to
+// platform of the generated course and goes through the same prediction
+// and syncing as a real player, only its movement is made up here
+// instead of coming from a keyboard and the physics.
+use crate::components::entities::{DisplayName, Player, PlayerBody};
+use crate::components::vitals::{Health, Movement, Stamina};
+use crate::plugins::map::{MapSettings, Platform};
+use crate::plugins::menu::GameState;
+use crate::plugins::network::identity::PlayerIdentity;
+use crate::plugins::network::prediction::PredictionHistory;
+use crate::plugins::network::replication::Replicate;
+use crate::plugins::network::synchronizer::Synchronizer;
+use crate::plugins::player::PLAYER_SCALE;
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+const WALK_SPEED: f32 = 5.0;
+const GRAVITY: f32 = 9.81;
+const STAND_HEIGHT: f32 = 1.4 * PLAYER_SCALE.y; // from the ground to the middle of the player
+const ARRIVE_DISTANCE: f32 = 0.4;
+const WANDER_REACH: usize = 2; // platforms either way a wandering bot picks from
+
+/// Where bots go next once they reach a platform
+#[derive(Resource, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BotRoute {
+    // a random platform close by along the course
+    #[default]
+    Wander,
+    // the course start to finish and back again
+    Course,
+}
+
+impl BotRoute {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "wander" => Some(Self::Wander),
+            "course" => Some(Self::Course),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Component, Debug)]
+pub struct Bot {
+    // platform we are heading for
+    target: usize,
+    // on the course route, whether we are on the way back
+    returning: bool,
+    rng: StdRng,
+}
+
+/// Drives the local player of a headless app, see `bin/bots.rs`
+pub struct BotPlugin {
+    pub route: BotRoute,
+}
+
+impl Plugin for BotPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.route)
+            .add_systems(OnEnter(GameState::Playing), spawn_bot)
+            .add_systems(Update, move_bot.run_if(in_state(GameState::Playing)));
+    }
+}
+
+fn spawn_bot(mut commands: Commands, identity: Res<PlayerIdentity>, settings: Res<MapSettings>) {
+    let mut rng = StdRng::seed_from_u64(identity.id as u64);
+    let layout = settings.layout();
+    // spread out over the course, not all on the first platform
+    let start = rng.random_range(0..layout.len().max(1));
+    let pos = layout
+        .get(start)
+        .map_or(Vec3::Y * STAND_HEIGHT, standing_on);
+
+    commands.spawn((
+        Name::new("Bot"),
+        Bot {
+            target: start,
+            returning: false,
+            rng,
+        },
+        Synchronizer {
+            id: identity.id,
+            ..default()
+        },
+        PredictionHistory::default(),
+        Replicate,
+        Transform::from_translation(pos),
+        LinearVelocity::default(),
+        // same stats as a real player, the server checks them
+        Movement {
+            speed: 100.0,
+            sprint_aplifier: 3.0,
+            jump_strength: 6.0,
+            is_grounded: true,
+            extra_jumps: 2,
+            current_jumps: 0,
+        },
+        Health {
+            current: 100.0,
+            max: 100.0,
+        },
+        Stamina {
+            current: 100.0,
+            max: 100.0,
+        },
+        Player,
+        PlayerBody,
+        DisplayName(identity.name.clone()),
+    ));
+}
+
+fn standing_on(platform: &Platform) -> Vec3 {
+    platform.pos + Vec3::Y * (platform.size.y / 2.0 + STAND_HEIGHT)
+}
+
+// highest ground under `pos`, the floor if there is no platform
+fn ground_below(layout: &[Platform], pos: Vec3) -> f32 {
+    layout
+        .iter()
+        .filter(|platform| {
+            let offset = (pos - platform.pos).abs();
+            offset.x <= platform.size.x / 2.0 && offset.z <= platform.size.z / 2.0
+        })
+        .map(|platform| standing_on(platform).y)
+        .filter(|height| *height <= pos.y + ARRIVE_DISTANCE)
+        .fold(STAND_HEIGHT, f32::max)
+}
+
+impl Bot {
+    fn next_target(&mut self, route: BotRoute, platforms: usize) {
+        let last = platforms.saturating_sub(1);
+        self.target = match route {
+            BotRoute::Wander => {
+                let low = self.target.saturating_sub(WANDER_REACH);
+                let high = (self.target + WANDER_REACH).min(last);
+                self.rng.random_range(low..=high)
+            }
+            BotRoute::Course => {
+                if self.target >= last {
+                    self.returning = true;
+                } else if self.target == 0 {
+                    self.returning = false;
+                }
+                if self.returning {
+                    self.target.saturating_sub(1)
+                } else {
+                    (self.target + 1).min(last)
+                }
+            }
+        };
+    }
+}
+
+fn move_bot(
+    query: Single<(
+        &mut Bot,
+        &mut Transform,
+        &mut LinearVelocity,
+        &mut Movement,
+        &mut PredictionHistory,
+        &mut Synchronizer,
+    )>,
+    route: Res<BotRoute>,
+    settings: Res<MapSettings>,
+    mut layout: Local<Vec<Platform>>,
+    time: Res<Time>,
+) {
+    // the room host may hand us another map at any time
+    if settings.is_changed() {
+        *layout = settings.layout();
+    }
+    let (mut bot, mut transform, mut velocity, mut movement, mut history, mut synchronizer) =
+        query.into_inner();
+    let Some(target) = layout.get(bot.target).copied() else {
+        bot.target = 0;
+        return;
+    };
+    let dt = time.delta_secs();
+
+    // last frame's input moves us first, like physics does for a real player
+    velocity.y -= GRAVITY * dt;
+    transform.translation += velocity.0 * dt;
+    let ground = ground_below(&layout, transform.translation);
+    if transform.translation.y <= ground && velocity.y <= 0.0 {
+        transform.translation.y = ground;
+        velocity.y = 0.0;
+        movement.is_grounded = true;
+    }
+
+    let goal = standing_on(&target);
+    let mut to_goal = goal - transform.translation;
+    to_goal.y = 0.0;
+    if to_goal.length() < ARRIVE_DISTANCE && movement.is_grounded {
+        bot.next_target(*route, layout.len());
+        to_goal = Vec3::ZERO;
+    }
+
+    let horizontal = to_goal.normalize_or_zero() * WALK_SPEED;
+    velocity.x = horizontal.x;
+    velocity.z = horizontal.z;
+    if horizontal != Vec3::ZERO {
+        transform.rotation = Quat::from_rotation_y(f32::atan2(-horizontal.x, -horizontal.z));
+    }
+
+    // hop up to anything higher than where we stand
+    let jumped = movement.is_grounded && goal.y > transform.translation.y + 0.1;
+    if jumped {
+        velocity.y = movement.jump_strength;
+        movement.is_grounded = false;
+    }
+
+    synchronizer.jump = jumped;
+    synchronizer.input_seq = history.record(velocity.0, jumped, transform.translation);
+}
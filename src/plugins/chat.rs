@@ -0,0 +1,311 @@
+//////////////////////////////////////////////////////
+///////////////////////// chat ///////////////////////
+//////////////////////////////////////////////////////
+//
+// Enter opens the chat box, Enter again sends and Escape throws the line
+// away. While the box is open it has the keyboard to itself, so typing
+// doesn't walk the player around or open the menu.
+use crate::components::entities::DisplayName;
+use crate::plugins::menu::GameState;
+use crate::plugins::network::Recieved;
+use crate::plugins::network::chat::{
+    ChatLimiter, ChatReceived, MAX_CHAT_LEN, clamp_chat, send_chat,
+};
+use crate::plugins::network::identity::{PlayerIdentity, sanitize_name};
+use crate::plugins::network::resource::WSMessageChannels;
+use crate::plugins::network::synchronizer::Synchronizer;
+use bevy::input::keyboard::{Key, KeyboardInput};
+use bevy::input::{ButtonState, InputSystems};
+use bevy::prelude::*;
+use std::collections::{HashMap, VecDeque};
+
+const MAX_LOG_LINES: usize = 50;
+const VISIBLE_LINES: usize = 8;
+const RECENT_FOR: f64 = 10.0; // seconds a line stays up with the box closed
+
+const LOG_COLOR: Color = Color::srgb(0.9, 0.9, 0.9);
+const INPUT_COLOR: Color = Color::srgb(0.9, 0.75, 0.3);
+const BOX_COLOR: Color = Color::srgba(0.1, 0.1, 0.12, 0.6);
+
+////////////////////////////////////////////////////////
+//////////////////////// Define ////////////////////////
+////////////////////////////////////////////////////////
+#[derive(Debug, Clone)]
+pub struct ChatLine {
+    // empty for notices from the game itself
+    pub name: String,
+    pub text: String,
+    // elapsed seconds when it came in
+    pub at: f64,
+}
+
+impl ChatLine {
+    fn timestamp(&self) -> String {
+        let seconds = self.at as u64;
+        format!("{:02}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct ChatLog {
+    pub lines: VecDeque<ChatLine>,
+    // how fast everyone else is talking, a relay won't stop them
+    limiters: HashMap<i64, ChatLimiter>,
+}
+
+impl ChatLog {
+    pub fn push(&mut self, name: String, text: String, at: f64) {
+        self.lines.push_back(ChatLine { name, text, at });
+        while self.lines.len() > MAX_LOG_LINES {
+            self.lines.pop_front();
+        }
+    }
+}
+
+/// The line being typed, `open` while the chat box has the keyboard
+#[derive(Resource, Debug, Default)]
+pub struct ChatInput {
+    pub open: bool,
+    pub text: String,
+    limiter: ChatLimiter,
+}
+
+#[derive(Component)]
+struct ChatUI;
+
+#[derive(Component)]
+struct ChatLogText;
+
+#[derive(Component)]
+struct ChatInputText;
+
+pub struct ChatPlugin;
+
+impl Plugin for ChatPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ChatLog>()
+            .init_resource::<ChatInput>()
+            .add_systems(OnEnter(GameState::Playing), spawn_chat_ui)
+            .add_systems(OnExit(GameState::Playing), despawn_chat_ui)
+            // right after the keyboard is read, so nothing else sees what we type
+            .add_systems(
+                PreUpdate,
+                type_chat
+                    .after(InputSystems)
+                    .run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(
+                Update,
+                (receive_chat, update_chat_ui)
+                    .chain()
+                    .run_if(in_state(GameState::Playing)),
+            );
+    }
+}
+
+////////////////////////////////////////////////////////
+//////////////////////// Systems ///////////////////////
+////////////////////////////////////////////////////////
+#[allow(clippy::too_many_arguments)]
+fn type_chat(
+    mut keys: MessageReader<KeyboardInput>,
+    mut keyboard: ResMut<ButtonInput<KeyCode>>,
+    mut input: ResMut<ChatInput>,
+    mut log: ResMut<ChatLog>,
+    channels: Option<Res<WSMessageChannels>>,
+    local: Query<&Synchronizer, Without<Recieved>>,
+    identity: Option<Res<PlayerIdentity>>,
+    time: Res<Time>,
+) {
+    let was_open = input.open;
+
+    for key in keys.read() {
+        if key.state != ButtonState::Pressed {
+            continue;
+        }
+        if !input.open {
+            if key.logical_key == Key::Enter {
+                input.open = true;
+                input.text.clear();
+            }
+            continue;
+        }
+
+        match &key.logical_key {
+            Key::Enter => {
+                let now = time.elapsed_secs_f64();
+                let Some(text) = clamp_chat(&input.text) else {
+                    input.open = false;
+                    continue;
+                };
+                // keep the line so it can go out once they calm down
+                if !input.limiter.allow(now) {
+                    log.push(
+                        "".into(),
+                        "Slow down, you are chatting too fast".into(),
+                        now,
+                    );
+                    continue;
+                }
+
+                if let Some(channels) = &channels
+                    && let Ok(local) = local.single()
+                {
+                    send_chat(channels, local.id, text.clone());
+                }
+                let name = identity
+                    .as_ref()
+                    .map_or_else(|| "You".to_string(), |identity| identity.name.clone());
+                log.push(name, text, now);
+                input.open = false;
+            }
+            Key::Escape => {
+                input.open = false;
+            }
+            Key::Backspace => {
+                input.text.pop();
+            }
+            _ => {
+                if let Some(text) = &key.text {
+                    for c in text.chars().filter(|c| !c.is_control()) {
+                        if input.text.chars().count() < MAX_CHAT_LEN {
+                            input.text.push(c);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // the key that closed the box shouldn't reach the game either
+    if was_open || input.open {
+        keyboard.reset_all();
+    }
+}
+
+fn receive_chat(
+    mut received: MessageReader<ChatReceived>,
+    mut log: ResMut<ChatLog>,
+    players: Query<(&Synchronizer, &DisplayName), With<Recieved>>,
+    time: Res<Time>,
+) {
+    let now = time.elapsed_secs_f64();
+
+    for ChatReceived(message) in received.read() {
+        if !log.limiters.entry(message.id).or_default().allow(now) {
+            continue;
+        }
+        let Some(text) = clamp_chat(&message.text) else {
+            continue;
+        };
+
+        let name = players
+            .iter()
+            .find(|(synchronizer, _)| synchronizer.id == message.id)
+            .map_or_else(|| sanitize_name("", message.id), |(_, name)| name.0.clone());
+        log.push(name, text, now);
+    }
+}
+
+fn spawn_chat_ui(mut commands: Commands) {
+    commands
+        .spawn((
+            ChatUI,
+            Node {
+                position_type: PositionType::Absolute,
+                left: Val::Px(12.0),
+                bottom: Val::Px(12.0),
+                width: Val::Px(420.0),
+                flex_direction: FlexDirection::Column,
+                padding: UiRect::all(Val::Px(6.0)),
+                ..default()
+            },
+            BackgroundColor(Color::NONE),
+            BorderRadius::all(Val::Px(6.0)),
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                ChatLogText,
+                Text::new(""),
+                TextFont {
+                    font_size: 16.0,
+                    ..default()
+                },
+                TextColor(LOG_COLOR),
+                TextShadow::default(),
+            ));
+            parent.spawn((
+                ChatInputText,
+                Text::new(""),
+                TextFont {
+                    font_size: 16.0,
+                    ..default()
+                },
+                TextColor(INPUT_COLOR),
+                Visibility::Hidden,
+            ));
+        });
+}
+
+fn despawn_chat_ui(
+    mut commands: Commands,
+    ui: Query<Entity, With<ChatUI>>,
+    mut input: ResMut<ChatInput>,
+) {
+    for entity in &ui {
+        commands.entity(entity).despawn();
+    }
+    input.open = false;
+}
+
+#[allow(clippy::type_complexity)]
+fn update_chat_ui(
+    log: Res<ChatLog>,
+    input: Res<ChatInput>,
+    time: Res<Time>,
+    mut chat_box: Query<&mut BackgroundColor, With<ChatUI>>,
+    mut log_text: Query<&mut Text, (With<ChatLogText>, Without<ChatInputText>)>,
+    mut input_text: Query<(&mut Text, &mut Visibility), With<ChatInputText>>,
+) {
+    let now = time.elapsed_secs_f64();
+
+    // closed, only what was said lately stays up
+    let shown: Vec<String> = log
+        .lines
+        .iter()
+        .rev()
+        .take(VISIBLE_LINES)
+        .filter(|line| input.open || now - line.at < RECENT_FOR)
+        .map(|line| {
+            if line.name.is_empty() {
+                format!("[{}] {}", line.timestamp(), line.text)
+            } else {
+                format!("[{}] {}: {}", line.timestamp(), line.name, line.text)
+            }
+        })
+        .collect();
+    let content = shown.into_iter().rev().collect::<Vec<_>>().join("\n");
+
+    for mut text in &mut log_text {
+        if text.0 != content {
+            text.0 = content.clone();
+        }
+    }
+
+    for (mut text, mut visibility) in &mut input_text {
+        if input.open {
+            let typed = format!("> {}_", input.text);
+            if text.0 != typed {
+                text.0 = typed;
+            }
+            visibility.set_if_neq(Visibility::Inherited);
+        } else {
+            visibility.set_if_neq(Visibility::Hidden);
+        }
+    }
+
+    let background = if input.open { BOX_COLOR } else { Color::NONE };
+    for mut color in &mut chat_box {
+        color.set_if_neq(BackgroundColor(background));
+    }
+}
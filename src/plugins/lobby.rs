@@ -0,0 +1,544 @@
+//////////////////////////////////////////////////////
+///////////////////////// lobby //////////////////////
+//////////////////////////////////////////////////////
+//
+// Between the menu and the game: the rooms the server knows about, and a
+// name and password to join or open one by hand. Servers that don't list
+// rooms still work, you just have to know the name.
+use crate::plugins::menu::{
+    BG_COLOR, BTN_COLOR, BTN_HOVER, BTN_PRESS, DIM_TEXT, GOLD, GameState, LABEL_COLOR,
+    OUTLINE_COLOR, TEXT_COLOR,
+};
+use crate::plugins::network::config::{MAX_ROOM_NAME_LEN, NetworkConfig, clean_room_name};
+use crate::plugins::network::protocol::RoomInfo;
+use crate::plugins::network::resource::Rejected;
+use crate::plugins::network::rooms::{RefreshRooms, RoomList};
+use bevy::ecs::hierarchy::ChildSpawnerCommands;
+use bevy::input::ButtonState;
+use bevy::input::keyboard::{Key, KeyboardInput};
+use bevy::prelude::*;
+use bevy::time::common_conditions::on_timer;
+use bevy::window::{CursorGrabMode, CursorOptions};
+use std::time::Duration;
+
+const REFRESH_INTERVAL: f32 = 5.0; // seconds between room list refreshes
+
+const ERROR_COLOR: Color = Color::srgb(0.9, 0.4, 0.35);
+
+////////////////////////////////////////////////////////
+//////////////////////// Define ////////////////////////
+////////////////////////////////////////////////////////
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LobbyField {
+    Room,
+    Password,
+}
+
+/// What is typed into the lobby, kept around for the next visit
+#[derive(Resource, Debug, Default)]
+pub struct LobbyForm {
+    pub room: String,
+    pub password: String,
+    pub focus: Option<LobbyField>,
+    // why the last join didn't work out
+    pub error: Option<String>,
+}
+
+#[derive(Component)]
+struct LobbyUI;
+
+#[derive(Component)]
+struct LobbyCamera;
+
+#[derive(Component)]
+struct RoomListNode;
+
+#[derive(Component)]
+struct LobbyStatusText;
+
+#[derive(Component)]
+struct FieldText(LobbyField);
+
+#[derive(Component, Debug, Clone)]
+enum LobbyButton {
+    Join(RoomInfo),
+    Field(LobbyField),
+    Create,
+    Refresh,
+    Back,
+}
+
+pub struct LobbyPlugin;
+
+impl Plugin for LobbyPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LobbyForm>()
+            .add_systems(OnEnter(GameState::Lobby), setup_lobby)
+            .add_systems(OnExit(GameState::Lobby), cleanup_lobby)
+            .add_systems(
+                Update,
+                (
+                    handle_lobby_buttons,
+                    type_lobby_field,
+                    update_room_list,
+                    update_lobby_text,
+                )
+                    .chain()
+                    .run_if(in_state(GameState::Lobby)),
+            )
+            .add_systems(
+                Update,
+                refresh_rooms
+                    .run_if(in_state(GameState::Lobby))
+                    .run_if(on_timer(Duration::from_secs_f32(REFRESH_INTERVAL))),
+            )
+            .add_systems(
+                Update,
+                back_to_lobby
+                    .run_if(in_state(GameState::Playing))
+                    .run_if(resource_exists::<Rejected>),
+            );
+    }
+}
+
+////////////////////////////////////////////////////////
+//////////////////////// Systems ///////////////////////
+////////////////////////////////////////////////////////
+fn setup_lobby(
+    mut commands: Commands,
+    mut refresh: MessageWriter<RefreshRooms>,
+    mut cursor: Query<&mut CursorOptions>,
+    mut form: ResMut<LobbyForm>,
+    config: Res<NetworkConfig>,
+) {
+    // thrown out of a game with the mouse still locked
+    for mut cursor in &mut cursor {
+        cursor.visible = true;
+        cursor.grab_mode = CursorGrabMode::None;
+    }
+    if form.room.is_empty() {
+        form.room = config.room.clone();
+    }
+    form.focus = None;
+    refresh.write(RefreshRooms);
+
+    commands.spawn((LobbyCamera, Camera2d));
+
+    commands
+        .spawn((
+            LobbyUI,
+            Node {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                flex_direction: FlexDirection::Column,
+                ..default()
+            },
+            BackgroundColor(BG_COLOR),
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new("ROOMS"),
+                TextFont {
+                    font_size: 40.0,
+                    ..default()
+                },
+                TextColor(GOLD),
+                Node {
+                    margin: UiRect::bottom(Val::Px(20.0)),
+                    ..default()
+                },
+            ));
+
+            // filled in by `update_room_list`
+            parent.spawn((
+                RoomListNode,
+                Node {
+                    width: Val::Px(420.0),
+                    max_height: Val::Px(300.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    overflow: Overflow::scroll_y(),
+                    ..default()
+                },
+            ));
+
+            parent.spawn((
+                LobbyStatusText,
+                Text::new(""),
+                TextFont {
+                    font_size: 16.0,
+                    ..default()
+                },
+                TextColor(DIM_TEXT),
+                Node {
+                    margin: UiRect::vertical(Val::Px(12.0)),
+                    ..default()
+                },
+            ));
+
+            spawn_field(parent, LobbyField::Room);
+            spawn_field(parent, LobbyField::Password);
+
+            parent
+                .spawn(Node {
+                    flex_direction: FlexDirection::Row,
+                    ..default()
+                })
+                .with_children(|row| {
+                    spawn_lobby_button(row, LobbyButton::Create, "JOIN / CREATE", 200.0);
+                    spawn_lobby_button(row, LobbyButton::Refresh, "REFRESH", 140.0);
+                    spawn_lobby_button(row, LobbyButton::Back, "BACK", 140.0);
+                });
+        });
+}
+
+fn spawn_lobby_button(
+    parent: &mut ChildSpawnerCommands,
+    button: LobbyButton,
+    label: &str,
+    width: f32,
+) {
+    parent
+        .spawn((
+            Button,
+            button,
+            Node {
+                width: Val::Px(width),
+                height: Val::Px(50.0),
+                margin: UiRect::all(Val::Px(6.0)),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            BackgroundColor(BTN_COLOR),
+            Outline {
+                width: Val::Px(2.0),
+                offset: Val::Px(0.0),
+                color: OUTLINE_COLOR,
+            },
+            BorderRadius::all(Val::Px(8.0)),
+        ))
+        .with_child((
+            Text::new(label),
+            TextFont {
+                font_size: 20.0,
+                ..default()
+            },
+            TextColor(TEXT_COLOR),
+        ));
+}
+
+fn spawn_field(parent: &mut ChildSpawnerCommands, field: LobbyField) {
+    parent
+        .spawn((
+            Button,
+            LobbyButton::Field(field),
+            Node {
+                width: Val::Px(300.0),
+                height: Val::Px(40.0),
+                margin: UiRect::bottom(Val::Px(10.0)),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            BackgroundColor(BG_COLOR),
+            Outline {
+                width: Val::Px(2.0),
+                offset: Val::Px(0.0),
+                color: OUTLINE_COLOR,
+            },
+            BorderRadius::all(Val::Px(8.0)),
+        ))
+        .with_child((
+            FieldText(field),
+            Text::new(""),
+            TextFont {
+                font_size: 18.0,
+                ..default()
+            },
+            TextColor(LABEL_COLOR),
+        ));
+}
+
+fn spawn_room_row(parent: &mut ChildSpawnerCommands, room: &RoomInfo) {
+    let label = format!(
+        "{} ({} {}){}",
+        room.name,
+        room.players,
+        if room.players == 1 {
+            "player"
+        } else {
+            "players"
+        },
+        if room.locked { " [locked]" } else { "" },
+    );
+    parent
+        .spawn((
+            Button,
+            LobbyButton::Join(room.clone()),
+            Node {
+                width: Val::Px(400.0),
+                height: Val::Px(40.0),
+                margin: UiRect::all(Val::Px(4.0)),
+                padding: UiRect::horizontal(Val::Px(12.0)),
+                align_items: AlignItems::Center,
+                flex_shrink: 0.0,
+                ..default()
+            },
+            BackgroundColor(BTN_COLOR),
+            BorderRadius::all(Val::Px(6.0)),
+        ))
+        .with_child((
+            Text::new(label),
+            TextFont {
+                font_size: 18.0,
+                ..default()
+            },
+            TextColor(TEXT_COLOR),
+        ));
+}
+
+fn handle_lobby_buttons(
+    mut interaction_query: Query<
+        (&Interaction, &LobbyButton, &mut BackgroundColor),
+        Changed<Interaction>,
+    >,
+    mut form: ResMut<LobbyForm>,
+    mut config: ResMut<NetworkConfig>,
+    mut refresh: MessageWriter<RefreshRooms>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    for (interaction, button, mut color) in &mut interaction_query {
+        let idle = match button {
+            LobbyButton::Field(_) => BG_COLOR,
+            _ => BTN_COLOR,
+        };
+        match *interaction {
+            Interaction::Pressed => {
+                *color = BackgroundColor(BTN_PRESS);
+                match button {
+                    LobbyButton::Join(room) => {
+                        // a locked room needs the password typed first
+                        if room.locked && form.password.is_empty() {
+                            form.error =
+                                Some(format!("{} is locked, type its password", room.name));
+                            form.focus = Some(LobbyField::Password);
+                            continue;
+                        }
+                        let password = if room.locked {
+                            form.password.clone()
+                        } else {
+                            String::new()
+                        };
+                        join_room(
+                            &mut form,
+                            &mut config,
+                            &mut next_state,
+                            room.name.clone(),
+                            password,
+                        );
+                    }
+                    LobbyButton::Field(field) => {
+                        form.focus = Some(*field);
+                    }
+                    LobbyButton::Create => {
+                        submit(&mut form, &mut config, &mut next_state);
+                    }
+                    LobbyButton::Refresh => {
+                        refresh.write(RefreshRooms);
+                    }
+                    LobbyButton::Back => {
+                        next_state.set(GameState::Menu);
+                    }
+                }
+            }
+            Interaction::Hovered => {
+                *color = BackgroundColor(BTN_HOVER);
+            }
+            Interaction::None => {
+                *color = BackgroundColor(idle);
+            }
+        }
+    }
+}
+
+fn type_lobby_field(
+    mut keys: MessageReader<KeyboardInput>,
+    mut form: ResMut<LobbyForm>,
+    mut config: ResMut<NetworkConfig>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    for key in keys.read() {
+        if key.state != ButtonState::Pressed {
+            continue;
+        }
+        let Some(field) = form.focus else {
+            if key.logical_key == Key::Escape {
+                next_state.set(GameState::Menu);
+            }
+            continue;
+        };
+
+        match &key.logical_key {
+            Key::Enter => {
+                submit(&mut form, &mut config, &mut next_state);
+                break;
+            }
+            Key::Escape => {
+                form.focus = None;
+            }
+            Key::Tab => {
+                form.focus = Some(match field {
+                    LobbyField::Room => LobbyField::Password,
+                    LobbyField::Password => LobbyField::Room,
+                });
+            }
+            Key::Backspace => {
+                field_text(&mut form, field).pop();
+            }
+            _ => {
+                if let Some(text) = &key.text {
+                    let typed = field_text(&mut form, field);
+                    for c in text.chars().filter(|c| !c.is_control()) {
+                        if typed.chars().count() < MAX_ROOM_NAME_LEN {
+                            typed.push(c);
+                        }
+                    }
+                    // room names stay readable in the list, passwords are taken as typed
+                    if field == LobbyField::Room {
+                        *typed = clean_room_name(typed);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn field_text(form: &mut LobbyForm, field: LobbyField) -> &mut String {
+    match field {
+        LobbyField::Room => &mut form.room,
+        LobbyField::Password => &mut form.password,
+    }
+}
+
+// join whatever is typed in, the relay opens the room if nobody is in it yet
+fn submit(form: &mut LobbyForm, config: &mut NetworkConfig, next_state: &mut NextState<GameState>) {
+    let room = clean_room_name(&form.room);
+    if room.is_empty() {
+        form.error = Some(String::from("Type a room name first"));
+        form.focus = Some(LobbyField::Room);
+        return;
+    }
+    let password = form.password.clone();
+    join_room(form, config, next_state, room, password);
+}
+
+fn join_room(
+    form: &mut LobbyForm,
+    config: &mut NetworkConfig,
+    next_state: &mut NextState<GameState>,
+    room: String,
+    password: String,
+) {
+    info!("Joining room {}", room);
+    form.room = room.clone();
+    form.focus = None;
+    form.error = None;
+    config.room = room;
+    config.password = password;
+    next_state.set(GameState::Playing);
+}
+
+fn refresh_rooms(mut refresh: MessageWriter<RefreshRooms>, list: Res<RoomList>) {
+    if !list.loading() {
+        refresh.write(RefreshRooms);
+    }
+}
+
+fn update_room_list(
+    mut commands: Commands,
+    list: Res<RoomList>,
+    list_node: Query<Entity, With<RoomListNode>>,
+    fresh: Query<(), Added<RoomListNode>>,
+) {
+    // a fresh screen shows what we had last visit until the answer is in
+    if !list.is_changed() && fresh.is_empty() {
+        return;
+    }
+    for entity in &list_node {
+        commands
+            .entity(entity)
+            .despawn_related::<Children>()
+            .with_children(|parent| {
+                for room in &list.rooms {
+                    spawn_room_row(parent, room);
+                }
+            });
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn update_lobby_text(
+    form: Res<LobbyForm>,
+    list: Res<RoomList>,
+    mut status: Query<(&mut Text, &mut TextColor), (With<LobbyStatusText>, Without<FieldText>)>,
+    mut fields: Query<(&FieldText, &mut Text, &mut TextColor), Without<LobbyStatusText>>,
+) {
+    if !form.is_changed() && !list.is_changed() {
+        return;
+    }
+
+    let (message, color) = if let Some(error) = form.error.as_ref().or(list.error.as_ref()) {
+        (error.clone(), ERROR_COLOR)
+    } else if list.loading() && list.rooms.is_empty() {
+        (String::from("Looking for rooms..."), DIM_TEXT)
+    } else if list.rooms.is_empty() {
+        (String::from("No rooms yet, name one to open it"), DIM_TEXT)
+    } else {
+        (String::from("Pick a room or name a new one"), DIM_TEXT)
+    };
+    for (mut text, mut text_color) in &mut status {
+        text.0 = message.clone();
+        *text_color = TextColor(color);
+    }
+
+    for (FieldText(field), mut text, mut color) in &mut fields {
+        let focused = form.focus == Some(*field);
+        let (label, value) = match field {
+            LobbyField::Room => ("Room", form.room.clone()),
+            // never shown, only how long it is
+            LobbyField::Password => ("Password", "*".repeat(form.password.chars().count())),
+        };
+        text.0 = match (focused, value.is_empty()) {
+            (true, _) => format!("{}: {}_", label, value),
+            (false, true) => format!("{}: -", label),
+            (false, false) => format!("{}: {}", label, value),
+        };
+        *color = TextColor(if focused { GOLD } else { LABEL_COLOR });
+    }
+}
+
+fn cleanup_lobby(
+    mut commands: Commands,
+    ui: Query<Entity, With<LobbyUI>>,
+    camera: Query<Entity, With<LobbyCamera>>,
+) {
+    for entity in ui.iter().chain(camera.iter()) {
+        commands.entity(entity).despawn();
+    }
+}
+
+// turned away from a room, back to picking one
+fn back_to_lobby(
+    mut commands: Commands,
+    rejected: Res<Rejected>,
+    mut form: ResMut<LobbyForm>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    warn!("Rejected: {}", rejected.0);
+    form.error = Some(format!("Couldn't join: {}", rejected.0));
+    commands.remove_resource::<Rejected>();
+    next_state.set(GameState::Lobby);
+}
@@ -3,15 +3,136 @@ use crate::plugins::menu::GameState;
 use avian3d::prelude::*;
 use bevy::prelude::*;
 use rand::{Rng, SeedableRng, rngs::StdRng};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
 
-const MAP_SIZE: usize = 50;
+pub const MAP_SIZE: usize = 50;
+const DEFAULT_SEED: u64 = 12345;
+const MAX_PLATFORMS: u32 = 200; // more than this from a peer is somebody messing with us
+const PLATFORM_HEIGHT: f32 = 0.3;
+
+/// One step of the course, `size` is the whole box not half of it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Platform {
+    pub pos: Vec3,
+    pub size: Vec3,
+}
+
+/// Everything the course is generated from. Online, the room host or server
+/// picks it and everyone else regenerates when theirs arrives.
+/// Native builds read `MAP_SEED` then `--seed`, the browser `?seed=..`
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MapSettings {
+    pub seed: u64,
+    pub platforms: u32,
+    // platform width and depth
+    pub platform_size: Range<f32>,
+    // horizontal distance between platforms
+    pub jump_distance: Range<f32>,
+    // how much higher each platform is than the last
+    pub height_gain: Range<f32>,
+}
+
+impl Default for MapSettings {
+    fn default() -> Self {
+        Self {
+            seed: DEFAULT_SEED,
+            platforms: 30,
+            platform_size: 1.5..3.5,
+            jump_distance: 2.0..4.0,
+            height_gain: 0.3..1.2,
+        }
+    }
+}
+
+impl MapSettings {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load() -> Self {
+        let seed = crate::plugins::network::config::command_line_value("seed")
+            .or_else(|| std::env::var("MAP_SEED").ok());
+        Self::with_seed(seed)
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn load() -> Self {
+        let search = web_sys::window()
+            .and_then(|window| window.location().search().ok())
+            .unwrap_or_default();
+        Self::with_seed(crate::plugins::network::config::query_param(&search, "seed"))
+    }
+
+    fn with_seed(seed: Option<String>) -> Self {
+        let mut settings = Self::default();
+        if let Some(seed) = seed {
+            match seed.parse() {
+                Ok(seed) => settings.seed = seed,
+                Err(_) => warn!("Ignoring invalid map seed: {}", seed),
+            }
+        }
+        settings
+    }
+
+    /// Every platform of the course in order, the same for everyone with these settings
+    pub fn layout(&self) -> Vec<Platform> {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut current_pos = Vec3::new(5.0, 1.0, 5.0); // Starting position
+        let mut platforms = Vec::with_capacity(self.platforms as usize);
+
+        for _ in 0..self.platforms {
+            // Random platform size (smaller = harder)
+            let size_x = rng.random_range(self.platform_size.clone());
+            let size_z = rng.random_range(self.platform_size.clone());
+            platforms.push(Platform {
+                pos: current_pos,
+                size: Vec3::new(size_x, PLATFORM_HEIGHT, size_z),
+            });
+
+            // Calculate next platform position
+            let jump_distance = rng.random_range(self.jump_distance.clone()); // Horizontal jump distance
+            let height_gain = rng.random_range(self.height_gain.clone()); // Vertical climb
+            let angle = rng.random_range(0.0..std::f32::consts::TAU); // Random direction
+
+            current_pos.x += angle.cos() * jump_distance;
+            current_pos.z += angle.sin() * jump_distance;
+            current_pos.y += height_gain;
+
+            // Keep platforms within bounds
+            current_pos.x = current_pos.x.clamp(2.0, MAP_SIZE as f32 - 2.0);
+            current_pos.z = current_pos.z.clamp(2.0, MAP_SIZE as f32 - 2.0);
+        }
+
+        platforms
+    }
+
+    /// Whether it is safe to generate from, settings can come from anyone online
+    pub fn is_valid(&self) -> bool {
+        let range_ok = |range: &Range<f32>| {
+            range.start.is_finite()
+                && range.end.is_finite()
+                && 0.0 <= range.start
+                && range.start < range.end
+        };
+        self.platforms <= MAX_PLATFORMS
+            && range_ok(&self.platform_size)
+            && range_ok(&self.jump_distance)
+            && range_ok(&self.height_gain)
+            && self.platform_size.start > 0.0
+            && self.platform_size.end <= MAP_SIZE as f32
+    }
+}
 
 pub struct MapPlugin;
 
 impl Plugin for MapPlugin {
     fn build(&self, app: &mut App) {
+        // the server and tests bring their own
+        if !app.world().contains_resource::<MapSettings>() {
+            app.insert_resource(MapSettings::load());
+        }
+
         app.add_systems(OnEnter(GameState::Playing), generate_random_map)
-            .add_systems(OnExit(GameState::Playing), cleanup_map);
+            .add_systems(OnExit(GameState::Playing), cleanup_map)
+            .add_systems(Update, regenerate_map.run_if(in_state(GameState::Playing)));
     }
 }
 
@@ -42,46 +163,24 @@ impl Plugin for MapPlugin {
 
 fn generate_random_map(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    settings: Res<MapSettings>,
+    meshes: Option<ResMut<Assets<Mesh>>>,
+    materials: Option<ResMut<Assets<StandardMaterial>>>,
 ) {
-    let mut rng = StdRng::seed_from_u64(12345);  // Change this number to change the map
+    info!("Generating map from seed {}", settings.seed);
 
-    let floor_mat = materials.add(Color::srgb(0.3, 0.8, 0.3));
-    let platform_mat = materials.add(Color::srgb(0.8, 0.6, 0.2));
+    // headless apps (the server) have no render assets, only colliders
+    let mut visuals = meshes.zip(materials).map(|(meshes, mut materials)| {
+        let floor_mat = materials.add(Color::srgb(0.3, 0.8, 0.3));
+        let platform_mat = materials.add(Color::srgb(0.8, 0.6, 0.2));
+        (meshes, floor_mat, platform_mat)
+    });
 
     // Spawn floor
-    commands.spawn((
-        Name::new("Floor"),
-        Mesh3d(meshes.add(Cuboid::new(MAP_SIZE as f32, 0.1, MAP_SIZE as f32))),
-        MeshMaterial3d(floor_mat.clone()),
-        Transform::from_xyz(MAP_SIZE as f32 / 2.0, 0.0, MAP_SIZE as f32 / 2.0),
-        RigidBody::Static,
-        Friction::ZERO,
-        Ground,
-        CollisionLayers::new(
-            [GameLayer::Environment],
-            [GameLayer::LocalPlayer, GameLayer::OnlinePlayer],
-        ),
-        Collider::cuboid(MAP_SIZE as f32, 0.1, MAP_SIZE as f32),
-    ));
-
-    // Generate parkour path
-    let num_platforms = 30;
-    let mut current_pos = Vec3::new(5.0, 1.0, 5.0); // Starting position
-
-    for i in 0..num_platforms {
-        // Random platform size (smaller = harder)
-        let size_x = rng.random_range(1.5..3.5);
-        let size_z = rng.random_range(1.5..3.5);
-        let height = 0.3;
-
-        // Spawn platform
-        commands.spawn((
-            Name::new(format!("Platform_{}", i)),
-            Mesh3d(meshes.add(Cuboid::new(size_x, height, size_z))),
-            MeshMaterial3d(platform_mat.clone()),
-            Transform::from_translation(current_pos),
+    let floor = commands
+        .spawn((
+            Name::new("Floor"),
+            Transform::from_xyz(MAP_SIZE as f32 / 2.0, 0.0, MAP_SIZE as f32 / 2.0),
             RigidBody::Static,
             Friction::ZERO,
             Ground,
@@ -89,21 +188,41 @@ fn generate_random_map(
                 [GameLayer::Environment],
                 [GameLayer::LocalPlayer, GameLayer::OnlinePlayer],
             ),
-            Collider::cuboid(size_x, height, size_z),
-        ));
+            Collider::cuboid(MAP_SIZE as f32, 0.1, MAP_SIZE as f32),
+        ))
+        .id();
 
-        // Calculate next platform position
-        let jump_distance = rng.random_range(2.0..4.0); // Horizontal jump distance
-        let height_gain = rng.random_range(0.3..1.2); // Vertical climb
-        let angle = rng.random_range(0.0..std::f32::consts::TAU); // Random direction
+    if let Some((meshes, floor_mat, _)) = &mut visuals {
+        commands.entity(floor).insert((
+            Mesh3d(meshes.add(Cuboid::new(MAP_SIZE as f32, 0.1, MAP_SIZE as f32))),
+            MeshMaterial3d(floor_mat.clone()),
+        ));
+    }
 
-        current_pos.x += angle.cos() * jump_distance;
-        current_pos.z += angle.sin() * jump_distance;
-        current_pos.y += height_gain;
+    // Generate parkour path
+    for (i, Platform { pos, size }) in settings.layout().into_iter().enumerate() {
+        // Spawn platform
+        let platform = commands
+            .spawn((
+                Name::new(format!("Platform_{}", i)),
+                Transform::from_translation(pos),
+                RigidBody::Static,
+                Friction::ZERO,
+                Ground,
+                CollisionLayers::new(
+                    [GameLayer::Environment],
+                    [GameLayer::LocalPlayer, GameLayer::OnlinePlayer],
+                ),
+                Collider::cuboid(size.x, size.y, size.z),
+            ))
+            .id();
 
-        // Keep platforms within bounds
-        current_pos.x = current_pos.x.clamp(2.0, MAP_SIZE as f32 - 2.0);
-        current_pos.z = current_pos.z.clamp(2.0, MAP_SIZE as f32 - 2.0);
+        if let Some((meshes, _, platform_mat)) = &mut visuals {
+            commands.entity(platform).insert((
+                Mesh3d(meshes.add(Cuboid::new(size.x, size.y, size.z))),
+                MeshMaterial3d(platform_mat.clone()),
+            ));
+        }
     }
 }
 
@@ -115,3 +234,18 @@ fn cleanup_map(
         commands.entity(entity).despawn();
     }
 }
+
+// a new seed arrived while playing, swap the whole course out
+fn regenerate_map(
+    mut commands: Commands,
+    settings: Res<MapSettings>,
+    ground_query: Query<Entity, With<Ground>>,
+    meshes: Option<ResMut<Assets<Mesh>>>,
+    materials: Option<ResMut<Assets<StandardMaterial>>>,
+) {
+    if !settings.is_changed() || settings.is_added() {
+        return;
+    }
+    cleanup_map(commands.reborrow(), ground_query);
+    generate_random_map(commands, settings, meshes, materials);
+}
@@ -1,8 +1,14 @@
 use bevy::ecs::hierarchy::ChildSpawnerCommands;
+use bevy::ecs::system::SystemParam;
+use bevy::input::ButtonState;
+use bevy::input::keyboard::{Key, KeyboardInput};
 use bevy::prelude::*;
 use rand::Rng;
 
 use crate::components::entities::Player;
+use crate::plugins::network::config::NetworkConfig;
+use crate::plugins::network::identity::{MAX_NAME_LEN, PlayerIdentity, sanitize_name};
+use crate::plugins::network::token::JoinClaims;
 use crate::plugins::player::camera::CameraSettings;
 
 /////////////////////////////////
@@ -13,6 +19,8 @@ use crate::plugins::player::camera::CameraSettings;
 pub enum GameState {
     #[default]
     Menu,
+    // picking a room to play in
+    Lobby,
     Playing,
 }
 
@@ -255,6 +263,19 @@ struct BackButton;
 #[derive(Component)]
 struct ResetButton;
 
+#[derive(Component)]
+struct NameField;
+
+#[derive(Component)]
+struct NameFieldText;
+
+/// The name being typed, only saved once Enter is pressed or the menu closes
+#[derive(Resource, Default)]
+struct NameEdit {
+    editing: bool,
+    text: String,
+}
+
 /////////////////////////////////
 ///////// Menu Plugin ///////////
 /////////////////////////////////
@@ -265,13 +286,21 @@ impl Plugin for MenuPlugin {
     fn build(&self, app: &mut App) {
         app.init_state::<GameState>()
             .init_resource::<GameSettings>()
+            .init_resource::<NameEdit>()
             .add_systems(OnEnter(GameState::Menu), setup_menu)
-            .add_systems(OnExit(GameState::Menu), cleanup_menu)
+            .add_systems(OnExit(GameState::Menu), (cleanup_menu, finish_name_edit))
             .add_systems(
                 Update,
                 (handle_menu_buttons, handle_settings_buttons)
                     .run_if(in_state(GameState::Menu)),
             )
+            .add_systems(
+                Update,
+                (handle_name_field, type_name)
+                    .chain()
+                    .run_if(in_state(GameState::Menu))
+                    .run_if(resource_exists::<PlayerIdentity>),
+            )
             .add_systems(Update, apply_settings)
             .add_systems(
                 Update,
@@ -284,18 +313,21 @@ impl Plugin for MenuPlugin {
 ///////// Menu Setup ////////////
 /////////////////////////////////
 
-const BG_COLOR: Color = Color::srgb(0.1, 0.1, 0.12);
-const BTN_COLOR: Color = Color::srgb(0.2, 0.2, 0.25);
-const BTN_HOVER: Color = Color::srgb(0.3, 0.3, 0.35);
-const BTN_PRESS: Color = Color::srgb(0.4, 0.4, 0.5);
-const OUTLINE_COLOR: Color = Color::srgb(0.4, 0.4, 0.5);
-const GOLD: Color = Color::srgb(0.9, 0.75, 0.3);
-const TEXT_COLOR: Color = Color::srgb(0.9, 0.9, 0.9);
-const DIM_TEXT: Color = Color::srgb(0.5, 0.5, 0.55);
-const LABEL_COLOR: Color = Color::srgb(0.7, 0.7, 0.75);
-const SECTION_COLOR: Color = Color::srgb(0.8, 0.4, 0.6);
-
-fn setup_menu(mut commands: Commands, has_played: Option<Res<HasPlayed>>) {
+pub(crate) const BG_COLOR: Color = Color::srgb(0.1, 0.1, 0.12);
+pub(crate) const BTN_COLOR: Color = Color::srgb(0.2, 0.2, 0.25);
+pub(crate) const BTN_HOVER: Color = Color::srgb(0.3, 0.3, 0.35);
+pub(crate) const BTN_PRESS: Color = Color::srgb(0.4, 0.4, 0.5);
+pub(crate) const OUTLINE_COLOR: Color = Color::srgb(0.4, 0.4, 0.5);
+pub(crate) const GOLD: Color = Color::srgb(0.9, 0.75, 0.3);
+pub(crate) const TEXT_COLOR: Color = Color::srgb(0.9, 0.9, 0.9);
+pub(crate) const DIM_TEXT: Color = Color::srgb(0.5, 0.5, 0.55);
+pub(crate) const LABEL_COLOR: Color = Color::srgb(0.7, 0.7, 0.75);
+
+fn setup_menu(
+    mut commands: Commands,
+    has_played: Option<Res<HasPlayed>>,
+    identity: Option<Res<PlayerIdentity>>,
+) {
     let is_resuming = has_played.is_some();
 
     commands.spawn((MenuCamera, Camera2d));
@@ -342,6 +374,11 @@ fn setup_menu(mut commands: Commands, has_played: Option<Res<HasPlayed>>) {
                 },
             ));
 
+            // Only online play has a name to show
+            if let Some(identity) = &identity {
+                spawn_name_field(parent, &identity.name);
+            }
+
             let play_text = if is_resuming { "RESUME" } else { "PLAY" };
             spawn_menu_button(parent, MenuButton::Play, play_text);
             spawn_menu_button(parent, MenuButton::Settings, "SETTINGS");
@@ -380,6 +417,38 @@ fn spawn_menu_button(parent: &mut ChildSpawnerCommands, button: MenuButton, labe
         ));
 }
 
+fn spawn_name_field(parent: &mut ChildSpawnerCommands, name: &str) {
+    parent
+        .spawn((
+            Button,
+            NameField,
+            Node {
+                width: Val::Px(250.0),
+                height: Val::Px(45.0),
+                margin: UiRect::bottom(Val::Px(20.0)),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            BackgroundColor(BG_COLOR),
+            Outline {
+                width: Val::Px(2.0),
+                offset: Val::Px(0.0),
+                color: OUTLINE_COLOR,
+            },
+            BorderRadius::all(Val::Px(8.0)),
+        ))
+        .with_child((
+            NameFieldText,
+            Text::new(name),
+            TextFont {
+                font_size: 20.0,
+                ..default()
+            },
+            TextColor(LABEL_COLOR),
+        ));
+}
+
 /////////////////////////////////
 ////// Settings Screen //////////
 /////////////////////////////////
@@ -722,6 +791,7 @@ fn cleanup_menu(
 /////// Button Interaction //////
 /////////////////////////////////
 
+#[allow(clippy::too_many_arguments)]
 fn handle_menu_buttons(
     mut commands: Commands,
     mut interaction_query: Query<
@@ -732,6 +802,8 @@ fn handle_menu_buttons(
     player_query: Query<Entity, With<Player>>,
     menu_query: Query<Entity, With<MainMenuUI>>,
     settings: Res<GameSettings>,
+    has_played: Option<Res<HasPlayed>>,
+    network: Option<Res<NetworkConfig>>,
 ) {
     for (interaction, button_type, mut color) in &mut interaction_query {
         match *interaction {
@@ -739,7 +811,12 @@ fn handle_menu_buttons(
                 *color = BackgroundColor(BTN_PRESS);
                 match button_type {
                     MenuButton::Play => {
-                        next_state.set(GameState::Playing);
+                        // online, a fresh game starts by picking a room
+                        if has_played.is_none() && network.is_some() {
+                            next_state.set(GameState::Lobby);
+                        } else {
+                            next_state.set(GameState::Playing);
+                        }
                     }
                     MenuButton::Settings => {
                         // Despawn main menu, spawn settings screen
@@ -768,27 +845,34 @@ fn handle_menu_buttons(
     }
 }
 
+type AdjustFilter = (Changed<Interaction>, Without<BackButton>, Without<ResetButton>);
+type BackFilter = (Changed<Interaction>, With<BackButton>, Without<SettingAdjustButton>, Without<ResetButton>);
+type ResetFilter = (Changed<Interaction>, With<ResetButton>, Without<BackButton>, Without<SettingAdjustButton>);
+
+// the settings screen's buttons, one query each
+#[derive(SystemParam)]
+struct SettingsButtons<'w, 's> {
+    adjust: Query<
+        'w,
+        's,
+        (&'static Interaction, &'static SettingAdjustButton, &'static mut BackgroundColor),
+        AdjustFilter,
+    >,
+    back: Query<'w, 's, (&'static Interaction, &'static mut BackgroundColor), BackFilter>,
+    reset: Query<'w, 's, (&'static Interaction, &'static mut BackgroundColor), ResetFilter>,
+}
+
 fn handle_settings_buttons(
     mut commands: Commands,
-    mut adjust_query: Query<
-        (&Interaction, &SettingAdjustButton, &mut BackgroundColor),
-        (Changed<Interaction>, Without<BackButton>, Without<ResetButton>),
-    >,
-    mut back_query: Query<
-        (&Interaction, &mut BackgroundColor),
-        (Changed<Interaction>, With<BackButton>, Without<SettingAdjustButton>, Without<ResetButton>),
-    >,
-    mut reset_query: Query<
-        (&Interaction, &mut BackgroundColor),
-        (Changed<Interaction>, With<ResetButton>, Without<BackButton>, Without<SettingAdjustButton>),
-    >,
+    mut buttons: SettingsButtons,
     mut settings: ResMut<GameSettings>,
     mut displays: Query<(&mut Text, &SettingValueDisplay)>,
     settings_ui: Query<Entity, With<SettingsUI>>,
     has_played: Option<Res<HasPlayed>>,
+    identity: Option<Res<PlayerIdentity>>,
 ) {
     // Handle reset button - reset to defaults and rebuild settings screen
-    for (interaction, mut color) in &mut reset_query {
+    for (interaction, mut color) in &mut buttons.reset {
         match *interaction {
             Interaction::Pressed => {
                 *color = BackgroundColor(BTN_PRESS);
@@ -810,7 +894,7 @@ fn handle_settings_buttons(
     }
 
     // Handle adjust buttons
-    for (interaction, adjust, mut color) in &mut adjust_query {
+    for (interaction, adjust, mut color) in &mut buttons.adjust {
         match *interaction {
             Interaction::Pressed => {
                 *color = BackgroundColor(BTN_PRESS);
@@ -834,7 +918,7 @@ fn handle_settings_buttons(
     }
 
     // Handle back button
-    for (interaction, mut color) in &mut back_query {
+    for (interaction, mut color) in &mut buttons.back {
         match *interaction {
             Interaction::Pressed => {
                 *color = BackgroundColor(BTN_PRESS);
@@ -882,6 +966,9 @@ fn handle_settings_buttons(
                                 ..default()
                             },
                         ));
+                        if let Some(identity) = &identity {
+                            spawn_name_field(parent, &identity.name);
+                        }
                         let play_text = if is_resuming { "RESUME" } else { "PLAY" };
                         spawn_menu_button(parent, MenuButton::Play, play_text);
                         spawn_menu_button(parent, MenuButton::Settings, "SETTINGS");
@@ -902,6 +989,124 @@ fn handle_settings_buttons(
     }
 }
 
+/////////////////////////////////
+///////// Name Field ////////////
+/////////////////////////////////
+
+#[allow(clippy::type_complexity)]
+fn handle_name_field(
+    mut field_query: Query<
+        (&Interaction, &mut BackgroundColor),
+        (Changed<Interaction>, With<NameField>),
+    >,
+    mut edit: ResMut<NameEdit>,
+    identity: Res<PlayerIdentity>,
+) {
+    for (interaction, mut color) in &mut field_query {
+        match *interaction {
+            Interaction::Pressed => {
+                *color = BackgroundColor(BTN_COLOR);
+                if !edit.editing {
+                    edit.editing = true;
+                    edit.text = identity.name.clone();
+                }
+            }
+            Interaction::Hovered => {
+                *color = BackgroundColor(BTN_COLOR);
+            }
+            Interaction::None => {
+                *color = BackgroundColor(BG_COLOR);
+            }
+        }
+    }
+}
+
+fn type_name(
+    mut keys: MessageReader<KeyboardInput>,
+    mut edit: ResMut<NameEdit>,
+    mut identity: ResMut<PlayerIdentity>,
+    network: Option<Res<NetworkConfig>>,
+    mut field_text: Query<(&mut Text, &mut TextColor), With<NameFieldText>>,
+) {
+    if !edit.editing {
+        keys.clear();
+        return;
+    }
+
+    for key in keys.read() {
+        if key.state != ButtonState::Pressed {
+            continue;
+        }
+        match &key.logical_key {
+            Key::Enter => {
+                save_name(&mut edit, &mut identity, network.as_deref());
+                break;
+            }
+            Key::Escape => {
+                edit.editing = false;
+                break;
+            }
+            Key::Backspace => {
+                edit.text.pop();
+            }
+            _ => {
+                if let Some(text) = &key.text {
+                    for c in text.chars().filter(|c| !c.is_control()) {
+                        if edit.text.chars().count() < MAX_NAME_LEN {
+                            edit.text.push(c);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    if !edit.is_changed() {
+        return;
+    }
+    for (mut text, mut color) in &mut field_text {
+        if edit.editing {
+            *text = Text::new(format!("{}_", edit.text));
+            *color = TextColor(GOLD);
+        } else {
+            *text = Text::new(identity.name.clone());
+            *color = TextColor(LABEL_COLOR);
+        }
+    }
+}
+
+// leaving the menu mid-edit keeps what was typed
+fn finish_name_edit(
+    mut edit: ResMut<NameEdit>,
+    identity: Option<ResMut<PlayerIdentity>>,
+    network: Option<Res<NetworkConfig>>,
+) {
+    if let Some(mut identity) = identity
+        && edit.editing
+    {
+        save_name(&mut edit, &mut identity, network.as_deref());
+    }
+}
+
+fn save_name(
+    edit: &mut NameEdit,
+    identity: &mut PlayerIdentity,
+    network: Option<&NetworkConfig>,
+) {
+    edit.editing = false;
+    // with a join token we play as whoever it says, and that is not ours to store
+    if network.is_some_and(|network| JoinClaims::peek(&network.token).is_some()) {
+        info!("The join token names us {}, not renaming", identity.name);
+        return;
+    }
+    let name = sanitize_name(&edit.text, identity.id);
+    if name != identity.name {
+        info!("Now playing as {}", name);
+        identity.name = name;
+        identity.save(network.and_then(|network| network.identity.as_deref()));
+    }
+}
+
 /////////////////////////////////
 /////// Apply Settings //////////
 /////////////////////////////////
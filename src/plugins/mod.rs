@@ -1,7 +1,13 @@
+pub mod bot;
+pub mod chat;
+pub mod lobby;
 pub mod map;
 pub mod menu;
+pub mod nameplate;
 pub mod network;
 pub mod player;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod server;
 use avian3d::prelude::PhysicsLayer;
 
 // Define collision layers
@@ -0,0 +1,230 @@
+/////////////////////////////////////////////////////////////
+///////////////////////// nameplates ////////////////////////
+/////////////////////////////////////////////////////////////
+//
+// Names floating over online players. They are plain UI text moved to
+// wherever the player's head lands on screen each frame, shrinking and
+// fading out with distance and hidden while a wall is in the way.
+use crate::components::entities::DisplayName;
+use crate::plugins::GameLayer;
+use crate::plugins::menu::GameState;
+use crate::plugins::network::Recieved;
+use crate::plugins::network::resource::NetworkStats;
+use crate::plugins::network::synchronizer::Synchronizer;
+use crate::plugins::player::PLAYER_SCALE;
+use avian3d::prelude::*;
+use bevy::prelude::*;
+
+const HEAD_HEIGHT: f32 = 1.4 * PLAYER_SCALE.y + 0.4; // just above the model
+const FONT_SIZE: f32 = 18.0; // at `FULL_SIZE_DISTANCE`
+const FULL_SIZE_DISTANCE: f32 = 8.0;
+const MIN_SCALE: f32 = 0.5;
+const MAX_SCALE: f32 = 1.3;
+const FADE_START: f32 = 30.0;
+const MAX_DISTANCE: f32 = 50.0; // fully faded out past this
+
+////////////////////////////////////////////////////////
+//////////////////////// Define ////////////////////////
+////////////////////////////////////////////////////////
+#[derive(Resource, Debug, Default)]
+pub struct NameplateSettings {
+    // show their round trip next to the name, only known on a relay
+    pub show_ping: bool,
+}
+
+/// On an online player, the label floating above it
+#[derive(Component)]
+pub struct Nameplate(pub Entity);
+
+/// On the label, the online player it belongs to
+#[derive(Component)]
+pub struct NameplateLabel {
+    pub target: Entity,
+}
+
+pub struct NameplatePlugin;
+
+impl Plugin for NameplatePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NameplateSettings>()
+            .add_systems(
+                Update,
+                (spawn_nameplates, update_nameplates)
+                    .chain()
+                    .run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(OnExit(GameState::Playing), despawn_nameplates);
+    }
+}
+
+////////////////////////////////////////////////////////
+//////////////////////// Systems ///////////////////////
+////////////////////////////////////////////////////////
+#[allow(clippy::type_complexity)]
+fn spawn_nameplates(
+    mut commands: Commands,
+    players: Query<(Entity, &DisplayName), (With<Recieved>, Without<Nameplate>)>,
+) {
+    for (entity, name) in &players {
+        let label = commands
+            .spawn((
+                Name::new("Nameplate"),
+                NameplateLabel { target: entity },
+                Text::new(name.0.clone()),
+                TextFont {
+                    font_size: FONT_SIZE,
+                    ..default()
+                },
+                TextColor(Color::WHITE),
+                TextShadow::default(),
+                Node {
+                    position_type: PositionType::Absolute,
+                    ..default()
+                },
+                // shown once we know where it goes
+                Visibility::Hidden,
+            ))
+            .id();
+        commands.entity(entity).insert(Nameplate(label));
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn update_nameplates(
+    mut commands: Commands,
+    mut labels: Query<(
+        Entity,
+        &NameplateLabel,
+        &mut Text,
+        &mut TextFont,
+        &mut TextColor,
+        &mut Node,
+        &mut Visibility,
+        &ComputedNode,
+    )>,
+    players: Query<
+        (&DisplayName, &GlobalTransform, &Synchronizer, &Visibility),
+        Without<NameplateLabel>,
+    >,
+    camera: Query<(&Camera, &GlobalTransform), With<Camera3d>>,
+    spatial_query: SpatialQuery,
+    settings: Res<NameplateSettings>,
+    stats: Option<Res<NetworkStats>>,
+) {
+    let Ok((camera, camera_transform)) = camera.single() else {
+        return;
+    };
+    let eye = camera_transform.translation();
+    let walls = SpatialQueryFilter::from_mask(GameLayer::Environment);
+
+    for (entity, label, mut text, mut font, mut color, mut node, mut visibility, computed) in
+        &mut labels
+    {
+        // the player left, the label goes with them
+        let Ok((name, transform, synchronizer, shown)) = players.get(label.target) else {
+            commands.entity(entity).despawn();
+            continue;
+        };
+
+        let ping = stats
+            .as_ref()
+            .filter(|_| settings.show_ping)
+            .and_then(|stats| stats.round_trips.get(&synchronizer.id));
+        let content = match ping {
+            Some(rtt) => format!("{} {:.0}ms", name.0, rtt * 1000.0),
+            None => name.0.clone(),
+        };
+        if text.0 != content {
+            text.0 = content;
+        }
+
+        let head = transform.translation() + Vec3::Y * HEAD_HEIGHT;
+        let to_head = head - eye;
+        let distance = to_head.length();
+        let on_screen = camera.world_to_viewport(camera_transform, head).ok();
+        let occluded = Dir3::new(to_head).is_ok_and(|direction| {
+            spatial_query
+                .cast_ray(eye, direction, distance, true, &walls)
+                .is_some()
+        });
+
+        let hidden = *shown == Visibility::Hidden;
+        let Some(position) = on_screen.filter(|_| distance < MAX_DISTANCE && !occluded && !hidden)
+        else {
+            visibility.set_if_neq(Visibility::Hidden);
+            continue;
+        };
+        visibility.set_if_neq(Visibility::Inherited);
+
+        let scale = (FULL_SIZE_DISTANCE / distance).clamp(MIN_SCALE, MAX_SCALE);
+        let alpha = ((MAX_DISTANCE - distance) / (MAX_DISTANCE - FADE_START)).clamp(0.0, 1.0);
+        if font.font_size != FONT_SIZE * scale {
+            font.font_size = FONT_SIZE * scale;
+        }
+        if color.0.alpha() != alpha {
+            color.0.set_alpha(alpha);
+        }
+
+        // centered over the head, sitting on top of it
+        let size = computed.size() * computed.inverse_scale_factor();
+        node.left = Val::Px(position.x - size.x / 2.0);
+        node.top = Val::Px(position.y - size.y);
+    }
+}
+
+fn despawn_nameplates(
+    mut commands: Commands,
+    labels: Query<Entity, With<NameplateLabel>>,
+    players: Query<Entity, With<Nameplate>>,
+) {
+    for entity in &labels {
+        commands.entity(entity).despawn();
+    }
+    // back in game they get new ones
+    for entity in &players {
+        commands.entity(entity).remove::<Nameplate>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::mesh::MeshPlugin;
+    use bevy::scene::ScenePlugin;
+    use bevy::state::app::StatesPlugin;
+
+    #[test]
+    fn nameplates_run_next_to_an_online_player() {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            StatesPlugin,
+            TransformPlugin,
+            // the physics collider backend expects mesh and scene assets to exist
+            AssetPlugin::default(),
+            MeshPlugin,
+            ScenePlugin,
+            PhysicsPlugins::default(),
+            NameplatePlugin,
+        ))
+        .insert_state(GameState::Playing);
+        app.world_mut().spawn((
+            DisplayName(String::from("Bob")),
+            Synchronizer::default(),
+            Recieved,
+            Transform::default(),
+            Visibility::default(),
+        ));
+
+        // the first frame spawns the label, the second moves it
+        app.update();
+        app.update();
+
+        let labels = app
+            .world_mut()
+            .query::<&NameplateLabel>()
+            .iter(app.world())
+            .count();
+        assert_eq!(labels, 1);
+    }
+}
@@ -0,0 +1,63 @@
+//////////////////////////////////////////////////////
+///////////////////////// chat ///////////////////////
+//////////////////////////////////////////////////////
+//
+// The wire side of chat: how long a line may be and how fast anyone may
+// talk. Both ends enforce it, a relay just passes everything along.
+use super::protocol::{ChatMessage, NetMessage};
+use super::resource::WSMessageChannels;
+use bevy::prelude::*;
+
+pub const MAX_CHAT_LEN: usize = 200; // characters
+const CHAT_BURST: f32 = 5.0; // lines that may go out back to back
+const CHAT_REFILL: f32 = 0.5; // lines per second after that
+
+/// A chat line someone else sent, `ChatPlugin` puts it in the log
+#[derive(Message, Debug, Clone)]
+pub struct ChatReceived(pub ChatMessage);
+
+/// Token bucket, full when nobody has said anything for a while
+#[derive(Debug, Clone)]
+pub struct ChatLimiter {
+    tokens: f32,
+    updated_at: f64,
+}
+
+impl Default for ChatLimiter {
+    fn default() -> Self {
+        Self {
+            tokens: CHAT_BURST,
+            updated_at: 0.0,
+        }
+    }
+}
+
+impl ChatLimiter {
+    /// Takes a token for one line, false if there is none left
+    pub fn allow(&mut self, now: f64) -> bool {
+        let refill = (now - self.updated_at).max(0.0) as f32 * CHAT_REFILL;
+        self.tokens = (self.tokens + refill).min(CHAT_BURST);
+        self.updated_at = now;
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+/// Printable and at most `MAX_CHAT_LEN` characters, None when nothing is left
+pub fn clamp_chat(text: &str) -> Option<String> {
+    let text: String = text
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_CHAT_LEN)
+        .collect();
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+pub fn send_chat(channels: &WSMessageChannels, id: i64, text: String) {
+    channels.send(&NetMessage::Chat(ChatMessage { id, text }));
+}
@@ -0,0 +1,593 @@
+/////////////////////////////////////////////////////////////
+///////////////////////// compression ///////////////////////
+/////////////////////////////////////////////////////////////
+//
+// State goes out many times a second for every player, so it gets a hand
+// packed layout instead of bincode. Everything is quantized to what the
+// eye can tell apart:
+//   position  3 x u16 inside the map bounds (~1.5 mm steps)
+//   rotation  u32, smallest three components at 10 bits each
+//   velocity  3 x i16 (~2 mm/s steps)
+//   flags     jump and grounded, packed into spare bits
+use super::protocol::{ProtocolError, StateDelta};
+use super::synchronizer::Synchronizer;
+use crate::plugins::map::MAP_SIZE;
+use bevy::prelude::*;
+use std::f32::consts::FRAC_1_SQRT_2;
+
+// players do wander off the edges, leave them half a map of room
+const POS_MIN: f32 = -(MAP_SIZE as f32) / 2.0;
+const POS_MAX: f32 = MAP_SIZE as f32 * 1.5;
+const VEL_MAX: f32 = 64.0;
+const SPEED_SCALE: f32 = 100.0;
+const QUAT_BITS: u32 = 10;
+
+// delta field mask, the top two bits carry the flags themselves
+const HAS_POS: u8 = 1 << 0;
+const HAS_ROT: u8 = 1 << 1;
+const HAS_VEL: u8 = 1 << 2;
+const HAS_SPEED: u8 = 1 << 3;
+const HAS_ANIMATION: u8 = 1 << 4;
+const FLAG_JUMP: u8 = 1 << 6;
+const FLAG_GROUNDED: u8 = 1 << 7;
+
+////////////////////////////////////////////////////////
+//////////////////////// States ////////////////////////
+////////////////////////////////////////////////////////
+pub fn encode_state(state: &Synchronizer, bytes: &mut Vec<u8>) {
+    let mut w = PacketWriter(bytes);
+    w.i64(state.id);
+    w.u32(pack_timestamp(state.timestamp));
+    w.varint(state.input_seq);
+    w.u16(state.keyframe);
+    w.position(state.pos);
+    w.u32(pack_quat(state.rot));
+    w.velocity(state.vel);
+    w.u16(pack_speed(state.speed));
+    w.varint(state.animation_playing.index() as u32);
+    w.u8(pack_flags(state.jump, state.grounded));
+}
+
+pub fn decode_state(bytes: &[u8]) -> Result<Synchronizer, ProtocolError> {
+    let mut r = PacketReader::new(bytes);
+    let id = r.i64()?;
+    let timestamp = unpack_timestamp(r.u32()?);
+    let input_seq = r.varint()?;
+    let keyframe = r.u16()?;
+    let pos = r.position()?;
+    let rot = unpack_quat(r.u32()?);
+    let vel = r.velocity()?;
+    let speed = unpack_speed(r.u16()?);
+    let animation_playing = AnimationNodeIndex::new(r.varint()? as usize);
+    let flags = r.u8()?;
+    r.finish()?;
+
+    Ok(Synchronizer {
+        id,
+        pos,
+        rot,
+        vel,
+        speed,
+        jump: flags & FLAG_JUMP != 0,
+        grounded: flags & FLAG_GROUNDED != 0,
+        animation_playing,
+        timestamp,
+        input_seq,
+        keyframe,
+    })
+}
+
+////////////////////////////////////////////////////////
+//////////////////////// Deltas ////////////////////////
+////////////////////////////////////////////////////////
+pub fn encode_delta(delta: &StateDelta, bytes: &mut Vec<u8>) {
+    let mut mask = pack_flags(delta.jump, delta.grounded);
+    for (present, bit) in [
+        (delta.pos.is_some(), HAS_POS),
+        (delta.rot.is_some(), HAS_ROT),
+        (delta.vel.is_some(), HAS_VEL),
+        (delta.speed.is_some(), HAS_SPEED),
+        (delta.animation_playing.is_some(), HAS_ANIMATION),
+    ] {
+        if present {
+            mask |= bit;
+        }
+    }
+
+    let mut w = PacketWriter(bytes);
+    w.i64(delta.id);
+    w.u32(pack_timestamp(delta.timestamp));
+    w.varint(delta.input_seq);
+    w.u16(delta.base);
+    w.u8(mask);
+    if let Some(pos) = delta.pos {
+        w.position(pos);
+    }
+    if let Some(rot) = delta.rot {
+        w.u32(pack_quat(rot));
+    }
+    if let Some(vel) = delta.vel {
+        w.velocity(vel);
+    }
+    if let Some(speed) = delta.speed {
+        w.u16(pack_speed(speed));
+    }
+    if let Some(animation) = delta.animation_playing {
+        w.varint(animation.index() as u32);
+    }
+}
+
+pub fn decode_delta(bytes: &[u8]) -> Result<StateDelta, ProtocolError> {
+    let mut r = PacketReader::new(bytes);
+    let id = r.i64()?;
+    let timestamp = unpack_timestamp(r.u32()?);
+    let input_seq = r.varint()?;
+    let base = r.u16()?;
+    let mask = r.u8()?;
+
+    let delta = StateDelta {
+        id,
+        timestamp,
+        input_seq,
+        base,
+        pos: (mask & HAS_POS != 0).then(|| r.position()).transpose()?,
+        rot: (mask & HAS_ROT != 0)
+            .then(|| r.u32().map(unpack_quat))
+            .transpose()?,
+        vel: (mask & HAS_VEL != 0).then(|| r.velocity()).transpose()?,
+        speed: (mask & HAS_SPEED != 0)
+            .then(|| r.u16().map(unpack_speed))
+            .transpose()?,
+        animation_playing: (mask & HAS_ANIMATION != 0)
+            .then(|| r.varint().map(|i| AnimationNodeIndex::new(i as usize)))
+            .transpose()?,
+        jump: mask & FLAG_JUMP != 0,
+        grounded: mask & FLAG_GROUNDED != 0,
+    };
+    r.finish()?;
+
+    Ok(delta)
+}
+
+//////////////////////////////////////////////////////////
+//////////////////////// Quantize ////////////////////////
+//////////////////////////////////////////////////////////
+fn quantize(value: f32, min: f32, max: f32) -> u16 {
+    let t = ((value - min) / (max - min)).clamp(0.0, 1.0);
+    (t * u16::MAX as f32).round() as u16
+}
+
+fn dequantize(value: u16, min: f32, max: f32) -> f32 {
+    min + (value as f32 / u16::MAX as f32) * (max - min)
+}
+
+fn pack_velocity(value: f32) -> i16 {
+    ((value / VEL_MAX).clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16
+}
+
+fn unpack_velocity(value: i16) -> f32 {
+    value as f32 / i16::MAX as f32 * VEL_MAX
+}
+
+fn pack_speed(speed: f32) -> u16 {
+    (speed * SPEED_SCALE).round().clamp(0.0, u16::MAX as f32) as u16
+}
+
+fn unpack_speed(speed: u16) -> f32 {
+    speed as f32 / SPEED_SCALE
+}
+
+// milliseconds are plenty for interpolation and wrap after 49 days
+fn pack_timestamp(timestamp: f64) -> u32 {
+    (timestamp * 1000.0).round().clamp(0.0, u32::MAX as f64) as u32
+}
+
+fn unpack_timestamp(timestamp: u32) -> f64 {
+    timestamp as f64 / 1000.0
+}
+
+fn pack_flags(jump: bool, grounded: bool) -> u8 {
+    (if jump { FLAG_JUMP } else { 0 }) | (if grounded { FLAG_GROUNDED } else { 0 })
+}
+
+/// Drops the largest component (it follows from the other three since the
+/// quaternion is unit length) and stores which one it was in the top two bits.
+pub fn pack_quat(rot: Quat) -> u32 {
+    let components = rot.normalize().to_array();
+    let largest = (0..4)
+        .max_by(|&a, &b| components[a].abs().total_cmp(&components[b].abs()))
+        .unwrap_or(3);
+    // q and -q are the same rotation, flip so the dropped one is positive
+    let sign = components[largest].signum();
+
+    let max = (1 << QUAT_BITS) - 1;
+    let mut packed = largest as u32;
+    for (i, component) in components.iter().enumerate() {
+        if i == largest {
+            continue;
+        }
+        let t = (component * sign / FRAC_1_SQRT_2 + 1.0) / 2.0;
+        packed = (packed << QUAT_BITS) | (t.clamp(0.0, 1.0) * max as f32).round() as u32;
+    }
+    packed
+}
+
+pub fn unpack_quat(packed: u32) -> Quat {
+    let max = (1 << QUAT_BITS) - 1;
+    let largest = (packed >> (QUAT_BITS * 3)) as usize;
+
+    let mut components = [0.0; 4];
+    let mut shift = QUAT_BITS * 3;
+    for (i, component) in components.iter_mut().enumerate() {
+        if i == largest {
+            continue;
+        }
+        shift -= QUAT_BITS;
+        let t = ((packed >> shift) & max) as f32 / max as f32;
+        *component = (t * 2.0 - 1.0) * FRAC_1_SQRT_2;
+    }
+    let rest: f32 = components.iter().map(|c| c * c).sum();
+    components[largest] = (1.0 - rest).max(0.0).sqrt();
+
+    Quat::from_array(components).normalize()
+}
+
+///////////////////////////////////////////////////////
+//////////////////////// Bytes ////////////////////////
+///////////////////////////////////////////////////////
+struct PacketWriter<'a>(&'a mut Vec<u8>);
+
+impl PacketWriter<'_> {
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn i64(&mut self, value: i64) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    // small numbers in few bytes, 7 bits at a time
+    fn varint(&mut self, mut value: u32) {
+        while value >= 0x80 {
+            self.0.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn position(&mut self, pos: Vec3) {
+        for axis in pos.to_array() {
+            self.u16(quantize(axis, POS_MIN, POS_MAX));
+        }
+    }
+
+    fn velocity(&mut self, vel: Vec3) {
+        for axis in vel.to_array() {
+            self.0.extend_from_slice(&pack_velocity(axis).to_le_bytes());
+        }
+    }
+}
+
+struct PacketReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> PacketReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], ProtocolError> {
+        let (head, rest) = self
+            .bytes
+            .split_first_chunk::<N>()
+            .ok_or(ProtocolError::Truncated)?;
+        self.bytes = rest;
+        Ok(*head)
+    }
+
+    fn u8(&mut self) -> Result<u8, ProtocolError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ProtocolError> {
+        Ok(u16::from_le_bytes(self.take()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, ProtocolError> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    fn i64(&mut self) -> Result<i64, ProtocolError> {
+        Ok(i64::from_le_bytes(self.take()?))
+    }
+
+    fn varint(&mut self) -> Result<u32, ProtocolError> {
+        let mut value = 0u32;
+        for shift in (0..35).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as u32)
+                .checked_shl(shift)
+                .ok_or_else(|| ProtocolError::Malformed("varint overflow".into()))?;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(ProtocolError::Malformed("varint too long".into()))
+    }
+
+    fn position(&mut self) -> Result<Vec3, ProtocolError> {
+        Ok(Vec3::new(
+            dequantize(self.u16()?, POS_MIN, POS_MAX),
+            dequantize(self.u16()?, POS_MIN, POS_MAX),
+            dequantize(self.u16()?, POS_MIN, POS_MAX),
+        ))
+    }
+
+    fn velocity(&mut self) -> Result<Vec3, ProtocolError> {
+        let mut axis = || -> Result<f32, ProtocolError> {
+            Ok(unpack_velocity(i16::from_le_bytes(self.take()?)))
+        };
+        Ok(Vec3::new(axis()?, axis()?, axis()?))
+    }
+
+    fn finish(self) -> Result<(), ProtocolError> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(ProtocolError::Malformed(format!(
+                "{} trailing bytes",
+                self.bytes.len()
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POS_STEP: f32 = (POS_MAX - POS_MIN) / u16::MAX as f32;
+    const VEL_STEP: f32 = VEL_MAX / i16::MAX as f32;
+    // 10 bits over the ±1/√2 range of the smaller components
+    const QUAT_STEP: f32 = 2.0 * FRAC_1_SQRT_2 / ((1 << QUAT_BITS) - 1) as f32;
+
+    fn state() -> Synchronizer {
+        Synchronizer {
+            id: -42,
+            pos: Vec3::new(12.3, 4.56, 30.0),
+            rot: Quat::from_euler(EulerRot::YXZ, 1.0, 0.2, -0.1),
+            vel: Vec3::new(3.0, -9.81, 0.25),
+            speed: 7.5,
+            jump: true,
+            animation_playing: AnimationNodeIndex::new(4),
+            timestamp: 123.456,
+            input_seq: 70_000,
+            grounded: false,
+            keyframe: 65_535,
+        }
+    }
+
+    fn empty_delta() -> StateDelta {
+        StateDelta {
+            id: 9,
+            timestamp: 2.5,
+            input_seq: 3,
+            base: 11,
+            pos: None,
+            rot: None,
+            vel: None,
+            speed: None,
+            animation_playing: None,
+            jump: false,
+            grounded: false,
+        }
+    }
+
+    fn state_round_trip(state: &Synchronizer) -> Synchronizer {
+        let mut bytes = Vec::new();
+        encode_state(state, &mut bytes);
+        decode_state(&bytes).unwrap()
+    }
+
+    fn delta_round_trip(delta: &StateDelta) -> StateDelta {
+        let mut bytes = Vec::new();
+        encode_delta(delta, &mut bytes);
+        decode_delta(&bytes).unwrap()
+    }
+
+    // q and -q are the same rotation
+    fn same_rotation(a: Quat, b: Quat) -> f32 {
+        1.0 - a.dot(b).abs()
+    }
+
+    #[test]
+    fn positions_are_within_half_a_step() {
+        let edges = [
+            POS_MIN,
+            POS_MIN + 0.001,
+            0.0,
+            MAP_SIZE as f32,
+            POS_MAX - 0.001,
+            POS_MAX,
+        ];
+        for x in edges {
+            for y in [0.0, 1.234, -3.3] {
+                let pos = Vec3::new(x, y, POS_MAX - x + POS_MIN);
+                let decoded = state_round_trip(&Synchronizer { pos, ..state() }).pos;
+                let error = (decoded - pos).abs().max_element();
+                assert!(
+                    error <= POS_STEP / 2.0 + 1e-4,
+                    "{} came back as {}",
+                    pos,
+                    decoded
+                );
+            }
+        }
+
+        // off the map it sticks to the edge
+        let pos = Vec3::new(POS_MIN - 10.0, POS_MAX + 10.0, 0.0);
+        let decoded = state_round_trip(&Synchronizer { pos, ..state() }).pos;
+        assert!(decoded.x - POS_MIN < 1e-3 && POS_MAX - decoded.y < 1e-3);
+    }
+
+    #[test]
+    fn rotations_survive_smallest_three() {
+        let mut rotations = vec![
+            Quat::IDENTITY,
+            Quat::from_rotation_y(0.7),
+            Quat::from_euler(EulerRot::XYZ, 0.3, -1.2, 2.9),
+        ];
+        // each of x, y, z and w as the largest component
+        for largest in 0..4 {
+            let mut components = [0.1, -0.2, 0.15, 0.05];
+            components[largest] = 0.95;
+            rotations.push(Quat::from_array(components).normalize());
+        }
+
+        for rot in rotations {
+            for rot in [rot, -rot] {
+                let decoded = unpack_quat(pack_quat(rot));
+                assert!(
+                    same_rotation(rot, decoded) < QUAT_STEP,
+                    "{} came back as {}",
+                    rot,
+                    decoded
+                );
+            }
+        }
+        assert_eq!(pack_quat(Quat::IDENTITY) >> (QUAT_BITS * 3), 3);
+    }
+
+    #[test]
+    fn velocities_are_clamped() {
+        let vel = Vec3::new(VEL_MAX * 2.0, -VEL_MAX * 10.0, 1.5);
+        let decoded = state_round_trip(&Synchronizer { vel, ..state() }).vel;
+        assert_eq!(decoded.x, VEL_MAX);
+        assert_eq!(decoded.y, -VEL_MAX);
+        assert!((decoded.z - 1.5).abs() <= VEL_STEP / 2.0);
+    }
+
+    #[test]
+    fn the_rest_of_a_state_survives() {
+        let state = state();
+        let decoded = state_round_trip(&state);
+        assert_eq!(decoded.id, state.id);
+        assert_eq!(decoded.input_seq, state.input_seq);
+        assert_eq!(decoded.keyframe, state.keyframe);
+        assert_eq!(decoded.animation_playing, state.animation_playing);
+        assert_eq!(decoded.speed, state.speed);
+        assert!((decoded.timestamp - state.timestamp).abs() < 1e-9);
+    }
+
+    #[test]
+    fn flags_use_their_own_bits() {
+        for (jump, grounded) in [(false, false), (true, false), (false, true), (true, true)] {
+            let decoded = state_round_trip(&Synchronizer {
+                jump,
+                grounded,
+                ..state()
+            });
+            assert_eq!((decoded.jump, decoded.grounded), (jump, grounded));
+
+            let decoded = delta_round_trip(&StateDelta {
+                jump,
+                grounded,
+                ..empty_delta()
+            });
+            assert_eq!((decoded.jump, decoded.grounded), (jump, grounded));
+        }
+    }
+
+    #[test]
+    fn every_delta_mask_survives() {
+        let full = state();
+        for mask in 0..1u8 << 5 {
+            let delta = StateDelta {
+                pos: (mask & HAS_POS != 0).then_some(full.pos),
+                rot: (mask & HAS_ROT != 0).then_some(full.rot),
+                vel: (mask & HAS_VEL != 0).then_some(full.vel),
+                speed: (mask & HAS_SPEED != 0).then_some(full.speed),
+                animation_playing: (mask & HAS_ANIMATION != 0).then_some(full.animation_playing),
+                jump: mask & 1 != 0,
+                ..empty_delta()
+            };
+            let decoded = delta_round_trip(&delta);
+
+            assert_eq!(
+                decoded.pos.is_some(),
+                delta.pos.is_some(),
+                "mask {:05b}",
+                mask
+            );
+            assert_eq!(
+                decoded.rot.is_some(),
+                delta.rot.is_some(),
+                "mask {:05b}",
+                mask
+            );
+            assert_eq!(
+                decoded.vel.is_some(),
+                delta.vel.is_some(),
+                "mask {:05b}",
+                mask
+            );
+            assert_eq!(decoded.speed, delta.speed, "mask {:05b}", mask);
+            assert_eq!(
+                decoded.animation_playing, delta.animation_playing,
+                "mask {:05b}",
+                mask
+            );
+            assert_eq!(
+                (decoded.id, decoded.base, decoded.jump),
+                (delta.id, delta.base, delta.jump)
+            );
+            if let (Some(a), Some(b)) = (decoded.pos, delta.pos) {
+                assert!(a.distance(b) < POS_STEP);
+            }
+            if let (Some(a), Some(b)) = (decoded.rot, delta.rot) {
+                assert!(same_rotation(a, b) < QUAT_STEP);
+            }
+        }
+    }
+
+    #[test]
+    fn packets_shrink_to_about_half() {
+        let state = state();
+        let mut packed = Vec::new();
+        encode_state(&state, &mut packed);
+
+        // what the state used to go out as, before the hand packed layout
+        let fields = (
+            state.id,
+            state.pos.to_array(),
+            state.rot.to_array(),
+            state.vel.to_array(),
+            state.speed,
+            state.jump,
+            state.animation_playing.index() as u32,
+            state.timestamp,
+            state.input_seq,
+            state.grounded,
+        );
+        let unpacked = bincode::serde::encode_to_vec(fields, bincode::config::legacy()).unwrap();
+
+        // id 8, timestamp 4, input 3, keyframe 2, position 6, rotation 4,
+        // velocity 6, speed 2, animation 1, flags 1
+        assert_eq!(packed.len(), 37);
+        assert_eq!(unpacked.len(), 70);
+
+        // a delta with nothing but the header is smaller still
+        let mut delta = Vec::new();
+        encode_delta(&empty_delta(), &mut delta);
+        assert_eq!(delta.len(), 8 + 4 + 1 + 2 + 1);
+    }
+}
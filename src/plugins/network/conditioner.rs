@@ -0,0 +1,270 @@
+/////////////////////////////////////////////////////////////
+///////////////////////// conditioner ///////////////////////
+/////////////////////////////////////////////////////////////
+//
+// Makes a good connection bad on purpose. Sits between the game and
+// whatever transport is in use and delays, reorders, duplicates and
+// drops packets in both directions, so laggy player bugs can be had
+// on localhost and in tests.
+#[cfg(not(target_arch = "wasm32"))]
+use super::queue::{self, QUEUE_CAPACITY, Receiver, Sender};
+#[cfg(not(target_arch = "wasm32"))]
+use super::resource::{WSMessageChannels, WSMessages};
+#[cfg(not(target_arch = "wasm32"))]
+use rand::Rng;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::mpsc::RecvTimeoutError;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant};
+
+// how long an idle lane sleeps before checking the other end is still there
+#[cfg(not(target_arch = "wasm32"))]
+const IDLE_WAIT: Duration = Duration::from_millis(100);
+
+/// Per direction, so a round trip sees twice the latency
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct NetworkConditions {
+    pub latency_ms: f32,
+    // +- this much on top of latency
+    pub jitter_ms: f32,
+    // chances between 0 and 1, per packet
+    pub loss: f32,
+    pub duplicate: f32,
+    pub reorder: f32,
+}
+
+impl NetworkConditions {
+    /// Named presets for `--net-profile`
+    pub fn profile(name: &str) -> Option<Self> {
+        let (latency_ms, jitter_ms, loss, duplicate, reorder) = match name {
+            "perfect" => (0.0, 0.0, 0.0, 0.0, 0.0),
+            "lan" => (2.0, 1.0, 0.0, 0.0, 0.0),
+            "wifi" => (20.0, 10.0, 0.01, 0.0, 0.01),
+            "mobile" => (80.0, 40.0, 0.03, 0.005, 0.02),
+            "awful" => (200.0, 100.0, 0.1, 0.02, 0.1),
+            _ => return None,
+        };
+        Some(Self {
+            latency_ms,
+            jitter_ms,
+            loss,
+            duplicate,
+            reorder,
+        })
+    }
+
+    pub fn is_perfect(&self) -> bool {
+        *self == Self::default()
+    }
+
+    // when this packet should come out the other end, if at all
+    #[cfg(not(target_arch = "wasm32"))]
+    fn schedule(&self, now: Instant, rng: &mut impl Rng) -> Vec<Instant> {
+        if rng.random::<f32>() < self.loss {
+            return Vec::new();
+        }
+
+        let copies = if rng.random::<f32>() < self.duplicate {
+            2
+        } else {
+            1
+        };
+        (0..copies)
+            .map(|_| {
+                let jitter = rng.random_range(-1.0..=1.0) * self.jitter_ms;
+                let mut delay = (self.latency_ms + jitter).max(0.0);
+                // held back long enough for the next few packets to overtake it
+                if rng.random::<f32>() < self.reorder {
+                    delay += self.latency_ms.max(50.0);
+                }
+                now + Duration::from_secs_f32(delay / 1000.0)
+            })
+            .collect()
+    }
+
+    // the plain latency, without any of the bad luck
+    #[cfg(not(target_arch = "wasm32"))]
+    fn delay(&self, now: Instant) -> Instant {
+        now + Duration::from_secs_f32(self.latency_ms.max(0.0) / 1000.0)
+    }
+}
+
+/// Puts the conditions between the game and `channels`, returns what the game should use
+#[cfg(not(target_arch = "wasm32"))]
+pub fn condition(channels: WSMessageChannels, conditions: NetworkConditions) -> WSMessageChannels {
+    let (to_others_tx, to_others_rx) = queue::channel(QUEUE_CAPACITY);
+    let (to_us_tx, to_us_rx) = queue::channel(QUEUE_CAPACITY);
+
+    spawn_lane(to_others_rx, channels.outgoing, conditions);
+    spawn_lane(channels.incomming, to_us_tx, conditions);
+
+    WSMessageChannels::new(to_us_rx, to_others_tx)
+}
+
+// one direction, on its own thread so it works with or without an async runtime.
+// the browser has no threads, so this stays off there
+#[cfg(not(target_arch = "wasm32"))]
+fn spawn_lane(mut from: Receiver, to: Sender, conditions: NetworkConditions) {
+    std::thread::spawn(move || {
+        let mut rng = rand::rng();
+        let mut in_flight: Vec<(Instant, WSMessages)> = Vec::new();
+        let mut closed = false;
+
+        while !to.is_closed() {
+            // sleeps until the next packet is due or another one comes in
+            let next_due = in_flight.first().map(|(at, _)| *at);
+            let wait_until = next_due.unwrap_or_else(|| Instant::now() + IDLE_WAIT);
+            let received = if closed {
+                std::thread::sleep(wait_until.saturating_duration_since(Instant::now()));
+                Err(RecvTimeoutError::Disconnected)
+            } else {
+                from.recv_until(wait_until)
+            };
+
+            let now = Instant::now();
+            match received {
+                Ok(WSMessages::Sync(bytes)) => {
+                    for at in conditions.schedule(now, &mut rng) {
+                        in_flight.push((at, WSMessages::Sync(bytes.clone())));
+                    }
+                }
+                // the socket opening or closing is never lost and never overtakes
+                // what was sent before it
+                Ok(other) => {
+                    let last = in_flight.iter().map(|(at, _)| *at).max();
+                    let at = conditions.delay(now).max(last.unwrap_or(now));
+                    in_flight.push((at, other));
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => closed = true,
+            }
+
+            // stable, so what is due at the same time keeps its order
+            in_flight.sort_by_key(|(at, _)| *at);
+            let due = in_flight.partition_point(|(at, _)| *at <= now);
+            for (_, msg) in in_flight.drain(..due) {
+                to.send(msg).ok();
+            }
+
+            if closed && in_flight.is_empty() {
+                break;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WAIT: Duration = Duration::from_secs(2);
+
+    // what the socket sees on one side, the game's end of the conditions on the other
+    fn conditioned(conditions: NetworkConditions) -> (Sender, WSMessageChannels) {
+        let (from_socket_tx, from_socket_rx) = queue::channel(QUEUE_CAPACITY);
+        let (to_socket_tx, _to_socket_rx) = queue::channel(QUEUE_CAPACITY);
+        let game = condition(
+            WSMessageChannels::new(from_socket_rx, to_socket_tx),
+            conditions,
+        );
+        (from_socket_tx, game)
+    }
+
+    fn receive(game: &mut WSMessageChannels) -> Option<WSMessages> {
+        game.incomming.recv_until(Instant::now() + WAIT).ok()
+    }
+
+    fn packet(n: u8) -> WSMessages {
+        WSMessages::Sync(vec![n])
+    }
+
+    fn is_packet(msg: Option<WSMessages>, n: u8) -> bool {
+        matches!(msg, Some(WSMessages::Sync(bytes)) if bytes == [n])
+    }
+
+    #[test]
+    fn perfect_conditions_keep_everything_in_order() {
+        let (socket, mut game) = conditioned(NetworkConditions::default());
+        socket.send(WSMessages::Connected).unwrap();
+        for n in 0..10 {
+            socket.send(packet(n)).unwrap();
+        }
+
+        assert!(matches!(receive(&mut game), Some(WSMessages::Connected)));
+        for n in 0..10 {
+            assert!(is_packet(receive(&mut game), n));
+        }
+    }
+
+    #[test]
+    fn latency_holds_everything_back() {
+        let latency = Duration::from_millis(50);
+        let (socket, mut game) = conditioned(NetworkConditions {
+            latency_ms: latency.as_millis() as f32,
+            ..NetworkConditions::default()
+        });
+
+        let sent = Instant::now();
+        socket.send(WSMessages::Connected).unwrap();
+        socket.send(packet(1)).unwrap();
+        assert!(matches!(receive(&mut game), Some(WSMessages::Connected)));
+        assert!(is_packet(receive(&mut game), 1));
+        assert!(sent.elapsed() >= latency);
+    }
+
+    #[test]
+    fn lost_packets_never_take_the_socket_state_with_them() {
+        let (socket, mut game) = conditioned(NetworkConditions {
+            latency_ms: 10.0,
+            loss: 1.0,
+            ..NetworkConditions::default()
+        });
+
+        socket.send(WSMessages::Connected).unwrap();
+        for n in 0..10 {
+            socket.send(packet(n)).unwrap();
+        }
+        socket
+            .send(WSMessages::Disconnected(String::from("bye")))
+            .unwrap();
+
+        assert!(matches!(receive(&mut game), Some(WSMessages::Connected)));
+        assert!(matches!(
+            receive(&mut game),
+            Some(WSMessages::Disconnected(_))
+        ));
+    }
+
+    #[test]
+    fn closing_comes_after_the_packets_before_it() {
+        let (socket, mut game) = conditioned(NetworkConditions {
+            latency_ms: 20.0,
+            jitter_ms: 20.0,
+            reorder: 0.5,
+            ..NetworkConditions::default()
+        });
+
+        for n in 0..20 {
+            socket.send(packet(n)).unwrap();
+        }
+        socket
+            .send(WSMessages::Disconnected(String::from("bye")))
+            .unwrap();
+        drop(socket);
+
+        let mut packets = 0;
+        loop {
+            match receive(&mut game) {
+                Some(WSMessages::Sync(_)) => packets += 1,
+                Some(WSMessages::Disconnected(_)) => break,
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        assert_eq!(packets, 20);
+        // the lane is done once the socket is gone and everything is through
+        assert_eq!(
+            game.incomming.recv_until(Instant::now() + WAIT).err(),
+            Some(RecvTimeoutError::Disconnected)
+        );
+    }
+}
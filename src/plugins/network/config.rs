@@ -0,0 +1,342 @@
+/////////////////////////////////////////////////////////
+///////////////////////// config ////////////////////////
+/////////////////////////////////////////////////////////
+use super::conditioner::NetworkConditions;
+use bevy::prelude::*;
+
+const DEFAULT_URL: &str = "wss://broadcast.dogfetus.no";
+const DEFAULT_APP_ID: &str = "67";
+pub const DEFAULT_ROOM: &str = "default";
+const DEFAULT_TICK_RATE: f64 = 20.0;
+pub const MAX_ROOM_NAME_LEN: usize = 24;
+
+/// Where and with whom we play, and how often we tell them about it.
+/// Native builds read `MULTIPLAYER_URL`, `MULTIPLAYER_APP_ID`, `MULTIPLAYER_ROOM`,
+/// `MULTIPLAYER_PASSWORD`, `MULTIPLAYER_TICK_RATE` and `MULTIPLAYER_TOKEN`, then `--url`,
+/// `--app-id`, `--room`, `--password`, `--tick-rate` and `--token`.
+/// The browser reads `?url=..&app_id=..&room=..&password=..&tick_rate=..&token=..`
+///
+/// For testing, `--net-profile lan|wifi|mobile|awful` (or `MULTIPLAYER_NET_PROFILE`) makes the
+/// connection worse on purpose, `--latency`, `--jitter` (ms) and `--loss`, `--duplicate`,
+/// `--reorder` (0..1) fine tune it. `--record <file>` (or `MULTIPLAYER_RECORD`) saves the
+/// traffic for `PlaybackTransport`. Native only
+#[derive(Resource, Debug, Clone)]
+pub struct NetworkConfig {
+    pub url: String,
+    pub app_id: String,
+    pub room: String,
+    // empty for an open room, whoever creates a room picks it
+    pub password: String,
+    // join token from the server's operator, empty where nobody asks for one (see `token`)
+    pub token: String,
+    // ask for the room list instead of joining `room`
+    pub list_rooms: bool,
+    // state updates sent per second
+    pub tick_rate: f64,
+    // perfect unless asked otherwise
+    pub conditions: NetworkConditions,
+    // file to record every session's traffic to, native only
+    pub record: Option<String>,
+    // where our identity is kept instead of the usual file, native only
+    pub identity: Option<String>,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            url: DEFAULT_URL.to_string(),
+            app_id: DEFAULT_APP_ID.to_string(),
+            room: DEFAULT_ROOM.to_string(),
+            password: String::new(),
+            token: String::new(),
+            list_rooms: false,
+            tick_rate: DEFAULT_TICK_RATE,
+            conditions: NetworkConditions::default(),
+            record: None,
+            identity: None,
+        }
+    }
+}
+
+impl NetworkConfig {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load() -> Self {
+        let mut config = Self::default();
+
+        for (var, key) in [
+            ("MULTIPLAYER_URL", "url"),
+            ("MULTIPLAYER_APP_ID", "app_id"),
+            ("MULTIPLAYER_ROOM", "room"),
+            ("MULTIPLAYER_PASSWORD", "password"),
+            ("MULTIPLAYER_TICK_RATE", "tick_rate"),
+            ("MULTIPLAYER_NET_PROFILE", "net_profile"),
+            ("MULTIPLAYER_TOKEN", "token"),
+            ("MULTIPLAYER_RECORD", "record"),
+            ("MULTIPLAYER_IDENTITY", "identity"),
+        ] {
+            if let Ok(value) = std::env::var(var) {
+                config.set(key, value);
+            }
+        }
+
+        // unknown flags are left for whoever else reads the command line
+        for (key, value) in command_line() {
+            config.set(&key, value);
+        }
+
+        config
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn load() -> Self {
+        let mut config = Self::default();
+
+        let search = web_sys::window()
+            .and_then(|window| window.location().search().ok())
+            .unwrap_or_default();
+
+        for key in ["url", "app_id", "room", "password", "tick_rate", "token"] {
+            if let Some(value) = query_param(&search, key) {
+                config.set(key, value);
+            }
+        }
+
+        config
+    }
+
+    fn set(&mut self, key: &str, value: String) {
+        match key {
+            "url" => self.url = value,
+            "app_id" => self.app_id = value,
+            "room" => self.room = value,
+            "password" => self.password = value,
+            "token" => self.token = value,
+            "record" => self.record = Some(value),
+            "identity" if !value.is_empty() => self.identity = Some(value),
+            "tick_rate" => match value.parse::<f64>() {
+                Ok(rate) if rate > 0.0 => self.tick_rate = rate,
+                _ => warn!("Ignoring invalid tick rate: {}", value),
+            },
+            "net_profile" => match NetworkConditions::profile(&value) {
+                Some(conditions) => self.conditions = conditions,
+                None => warn!("Ignoring unknown network profile: {}", value),
+            },
+            "latency" | "jitter" => match value.parse::<f32>() {
+                Ok(ms) if ms >= 0.0 => match key {
+                    "latency" => self.conditions.latency_ms = ms,
+                    _ => self.conditions.jitter_ms = ms,
+                },
+                _ => warn!("Ignoring invalid {}: {}", key, value),
+            },
+            "loss" | "duplicate" | "reorder" => match value.parse::<f32>() {
+                Ok(chance) if (0.0..=1.0).contains(&chance) => match key {
+                    "loss" => self.conditions.loss = chance,
+                    "duplicate" => self.conditions.duplicate = chance,
+                    _ => self.conditions.reorder = chance,
+                },
+                _ => warn!("Ignoring invalid {} chance: {}", key, value),
+            },
+            _ => {}
+        }
+    }
+
+    /// The browser can't set headers on a websocket, so everything goes in the query
+    pub fn url_with_query(&self) -> String {
+        let separator = if self.url.contains('?') { '&' } else { '?' };
+        let mut url = format!(
+            "{}{}app_id={}&room={}",
+            self.url,
+            separator,
+            percent_encode(&self.app_id),
+            percent_encode(&self.room)
+        );
+        if !self.password.is_empty() {
+            url.push_str(&format!("&password={}", percent_encode(&self.password)));
+        }
+        if !self.token.is_empty() {
+            url.push_str(&format!("&token={}", percent_encode(&self.token)));
+        }
+        if self.list_rooms {
+            url.push_str("&list_rooms=1");
+        }
+        url
+    }
+
+    /// Same place, but asking what rooms there are
+    pub fn room_list(&self) -> Self {
+        Self {
+            room: String::new(),
+            password: String::new(),
+            list_rooms: true,
+            ..self.clone()
+        }
+    }
+}
+
+/// Room names keep to letters, digits, `-` and `_`, so they read the same in the
+/// room list, the url and on the command line
+pub fn clean_room_name(name: &str) -> String {
+    name.trim()
+        .chars()
+        .map(|c| if c == ' ' { '-' } else { c })
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+        .take(MAX_ROOM_NAME_LEN)
+        .collect()
+}
+
+/// Looks up `key` in a `?a=1&b=2` style query string
+pub fn query_param(query: &str, key: &str) -> Option<String> {
+    query
+        .trim_start_matches('?')
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| percent_decode(v))
+}
+
+/// Everything but letters, digits and `-_.~` as `%XX`, safe in a query and in a header
+pub fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-_.~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// `%20` and `+` back to spaces and so on, broken escapes are kept as they are
+pub fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+            (b'+', _) => decoded.push(b' '),
+            (byte, _) => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+////////////////////////////////////////////////////////
+////////////////////// Command line ////////////////////
+////////////////////////////////////////////////////////
+/// Every `--key value` pair on the command line, with `-` in keys turned into `_`.
+/// Everyone who takes flags reads them from here and skips the keys that aren't theirs
+#[cfg(not(target_arch = "wasm32"))]
+pub fn command_line() -> Vec<(String, String)> {
+    parse_args(std::env::args().skip(1))
+}
+
+/// The value of the last `--key` on the command line
+#[cfg(not(target_arch = "wasm32"))]
+pub fn command_line_value(key: &str) -> Option<String> {
+    command_line()
+        .into_iter()
+        .rev()
+        .find(|(k, _)| k == key)
+        .map(|(_, value)| value)
+}
+
+// a flag followed by another flag (or nothing) gets an empty value, so it can't eat the next one
+#[cfg(not(target_arch = "wasm32"))]
+fn parse_args(args: impl IntoIterator<Item = String>) -> Vec<(String, String)> {
+    let mut args = args.into_iter().peekable();
+    let mut pairs = Vec::new();
+    while let Some(arg) = args.next() {
+        let Some(key) = arg.strip_prefix("--") else {
+            continue;
+        };
+        let value = args
+            .next_if(|next| !next.starts_with("--"))
+            .unwrap_or_default();
+        pairs.push((key.replace('-', "_"), value));
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<(String, String)> {
+        parse_args(line.split(' ').map(String::from))
+    }
+
+    fn pair(key: &str, value: &str) -> (String, String) {
+        (key.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn flags_take_the_value_after_them() {
+        assert_eq!(
+            args("--room lobby --tick-rate 30"),
+            [pair("room", "lobby"), pair("tick_rate", "30")]
+        );
+    }
+
+    #[test]
+    fn flags_without_a_value_leave_the_next_flag_alone() {
+        assert_eq!(
+            args("--playback --identity me.json --seed"),
+            [
+                pair("playback", ""),
+                pair("identity", "me.json"),
+                pair("seed", "")
+            ]
+        );
+        // a stray value belongs to nobody
+        assert_eq!(args("stray --bots 5"), [pair("bots", "5")]);
+    }
+
+    #[test]
+    fn unknown_flags_are_left_alone() {
+        let mut config = NetworkConfig::default();
+        for (key, value) in args("--bots 5 --room lobby --fps 30") {
+            config.set(&key, value);
+        }
+        assert_eq!(config.room, "lobby");
+        assert_eq!(config.tick_rate, DEFAULT_TICK_RATE);
+    }
+
+    #[test]
+    fn query_values_are_decoded() {
+        let query = "?room=my%20room&password=a%26b%3Dc&name=caf%C3%A9+au+lait&bad=%zz%4";
+        assert_eq!(query_param(query, "room").as_deref(), Some("my room"));
+        assert_eq!(query_param(query, "password").as_deref(), Some("a&b=c"));
+        assert_eq!(query_param(query, "name").as_deref(), Some("café au lait"));
+        assert_eq!(query_param(query, "bad").as_deref(), Some("%zz%4"));
+        assert_eq!(query_param(query, "missing"), None);
+    }
+
+    #[test]
+    fn query_values_are_encoded() {
+        let config = NetworkConfig {
+            url: String::from("ws://host/?x=1"),
+            room: String::from("my room"),
+            password: String::from("a&b=c+d é"),
+            token: String::from("e30.c2ln"),
+            ..NetworkConfig::default()
+        };
+        let url = config.url_with_query();
+        let query = url.split_once("?x=1").unwrap().1;
+        assert_eq!(query_param(query, "room").as_deref(), Some("my room"));
+        assert_eq!(query_param(query, "password").as_deref(), Some("a&b=c+d é"));
+        assert_eq!(query_param(query, "token").as_deref(), Some("e30.c2ln"));
+        // nothing left that a header would refuse
+        assert!(url.chars().all(|c| c.is_ascii_graphic()));
+    }
+}
@@ -0,0 +1,52 @@
+////////////////////////////////////////////////////////////
+///////////////////////// connection ///////////////////////
+////////////////////////////////////////////////////////////
+//
+// Where the socket is at. The transport tasks keep retrying on their
+// own and report back through `WSMessages`, the game only mirrors it.
+use bevy::prelude::*;
+use std::time::Duration;
+
+const INITIAL_BACKOFF: f32 = 0.5; // seconds before the first retry
+const MAX_BACKOFF: f32 = 30.0;
+const MAX_ATTEMPTS: u32 = 12; // roughly five minutes of trying before going offline
+
+#[derive(States, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ConnectionState {
+    // not playing, or gave up on the server
+    #[default]
+    Offline,
+    Connecting,
+    Connected,
+    Reconnecting,
+}
+
+/// Exponential backoff with a bit of jitter so a room full of clients
+/// does not hammer the server in lockstep after it restarts.
+#[derive(Debug, Clone, Default)]
+pub struct Backoff {
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+
+    /// How long to wait before the next try, `None` once we should give up
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        if self.attempt >= MAX_ATTEMPTS {
+            return None;
+        }
+
+        let delay = (INITIAL_BACKOFF * 2f32.powi(self.attempt as i32)).min(MAX_BACKOFF);
+        let jitter = rand::random_range(0.0..=delay * 0.25);
+        self.attempt += 1;
+
+        Some(Duration::from_secs_f32(delay + jitter))
+    }
+
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+}
@@ -0,0 +1,164 @@
+//////////////////////////////////////////////////////////
+///////////////////////// identity ///////////////////////
+//////////////////////////////////////////////////////////
+//
+// Who we are across sessions: an id that sticks and the name others see.
+// Kept in a small json file natively and in localStorage in the browser.
+use super::Recieved;
+use super::synchronizer::Synchronizer;
+use super::token::JoinClaims;
+use crate::components::entities::{DisplayName, Player};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub const MAX_NAME_LEN: usize = 16;
+
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PlayerIdentity {
+    pub id: i64,
+    pub name: String,
+}
+
+impl PlayerIdentity {
+    fn new() -> Self {
+        // positive, so it can never be the server's id
+        let id = rand::random_range(1..i64::MAX);
+        Self {
+            id,
+            name: default_name(id),
+        }
+    }
+
+    /// The stored identity, or a fresh one that gets stored right away.
+    /// `path` replaces the usual file, see `NetworkConfig::identity`
+    pub fn load(path: Option<&str>) -> Self {
+        match read_stored(path).and_then(|json| serde_json::from_str::<Self>(&json).ok()) {
+            Some(mut identity) => {
+                identity.name = sanitize_name(&identity.name, identity.id);
+                identity
+            }
+            None => {
+                let identity = Self::new();
+                identity.save(path);
+                identity
+            }
+        }
+    }
+
+    /// Plays as whoever a join token says, without storing it
+    pub fn adopt(&mut self, claims: &JoinClaims) {
+        self.id = claims.id;
+        self.name = sanitize_name(&claims.name, claims.id);
+    }
+
+    pub fn save(&self, path: Option<&str>) {
+        match serde_json::to_string(self) {
+            Ok(json) => write_stored(path, &json),
+            Err(e) => warn!("Failed to save identity: {}", e),
+        }
+    }
+}
+
+/// Printable, at most `MAX_NAME_LEN` characters and never empty
+pub fn sanitize_name(name: &str, id: i64) -> String {
+    let name: String = name
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_NAME_LEN)
+        .collect();
+    let name = name.trim();
+
+    if name.is_empty() {
+        default_name(id)
+    } else {
+        name.to_string()
+    }
+}
+
+fn default_name(id: i64) -> String {
+    format!("Player{:04}", id.rem_euclid(10_000))
+}
+
+// renaming in the menu shows up on our player, and from there on the others' screens
+pub(crate) fn update_display_name(
+    identity: Res<PlayerIdentity>,
+    mut player: Query<&mut DisplayName, With<Player>>,
+) {
+    if !identity.is_changed() {
+        return;
+    }
+    for mut name in &mut player {
+        if name.0 != identity.name {
+            name.0 = identity.name.clone();
+        }
+    }
+}
+
+// without a server in between nobody else checks what others call themselves
+#[allow(clippy::type_complexity)]
+pub(crate) fn sanitize_remote_names(
+    mut names: Query<(&mut DisplayName, &Synchronizer), (Changed<DisplayName>, With<Recieved>)>,
+) {
+    for (mut name, synchronizer) in &mut names {
+        let clean = sanitize_name(&name.0, synchronizer.id);
+        if name.0 != clean {
+            name.0 = clean;
+        }
+    }
+}
+
+/////////////////////////////////////////////////////////
+//////////////////////// Storage ////////////////////////
+/////////////////////////////////////////////////////////
+#[cfg(not(target_arch = "wasm32"))]
+fn storage_path(path: Option<&str>) -> std::path::PathBuf {
+    // two clients on one machine need an identity each
+    if let Some(path) = path {
+        return path.into();
+    }
+
+    match std::env::var_os("HOME") {
+        Some(home) => std::path::Path::new(&home)
+            .join(".bavytest")
+            .join("identity.json"),
+        None => "identity.json".into(),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_stored(path: Option<&str>) -> Option<String> {
+    std::fs::read_to_string(storage_path(path)).ok()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_stored(path: Option<&str>, json: &str) {
+    let path = storage_path(path);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).ok();
+    }
+    if let Err(e) = std::fs::write(&path, json) {
+        warn!("Failed to write identity to {}: {}", path.display(), e);
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+const STORAGE_KEY: &str = "bavytest.identity";
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read_stored(_path: Option<&str>) -> Option<String> {
+    local_storage()?.get_item(STORAGE_KEY).ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write_stored(_path: Option<&str>, json: &str) {
+    if let Some(storage) = local_storage()
+        && storage.set_item(STORAGE_KEY, json).is_err()
+    {
+        warn!("Failed to write identity to localStorage");
+    }
+}
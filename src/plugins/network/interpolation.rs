@@ -0,0 +1,107 @@
+///////////////////////////////////////////////////////////////
+///////////////////////// interpolation ///////////////////////
+///////////////////////////////////////////////////////////////
+//
+// Remote players are drawn a little in the past so there is almost
+// always a snapshot on either side to blend between. Packets arrive
+// whenever they like, the buffer smooths that out.
+use super::Recieved;
+use super::resource::NetworkStats;
+use bevy::prelude::*;
+use std::collections::VecDeque;
+
+pub const INTERPOLATION_DELAY: f64 = 0.1; // seconds behind the newest state we render
+const MAX_EXTRAPOLATION: f64 = 0.25; // how far past the last snapshot we dare to guess
+const MAX_SNAPSHOTS: usize = 32;
+const TELEPORT_THRESHOLD: f32 = 5.0; // jumps further than this are snapped, not blended
+const CLOCK_RESET: f64 = 1.0; // a sender clock this far off means they restarted
+const JITTER_MARGIN: f64 = 3.0; // on a shaky connection, stay this many jitters behind instead
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Snapshot {
+    // sender's clock, seconds
+    pub time: f64,
+    pub pos: Vec3,
+    pub rot: Quat,
+    pub vel: Vec3,
+}
+
+#[derive(Component, Debug, Default)]
+pub struct SnapshotBuffer {
+    snapshots: VecDeque<Snapshot>,
+    // our clock minus theirs, as low as we have seen it (that's the least delayed packet)
+    clock_offset: Option<f64>,
+}
+
+impl SnapshotBuffer {
+    pub fn push(&mut self, snapshot: Snapshot, received_at: f64) {
+        let offset = received_at - snapshot.time;
+        match self.clock_offset {
+            Some(current) if (offset - current).abs() > CLOCK_RESET => {
+                self.snapshots.clear();
+                self.clock_offset = Some(offset);
+            }
+            // faster packet, tighter bound
+            Some(current) if offset < current => self.clock_offset = Some(offset),
+            // creep upwards so a drifting clock doesn't leave us starved forever
+            Some(current) => self.clock_offset = Some(current + (offset - current) * 0.01),
+            None => self.clock_offset = Some(offset),
+        }
+
+        // packets can overtake each other, keep the buffer ordered and drop repeats
+        let index = self.snapshots.partition_point(|s| s.time < snapshot.time);
+        if self.snapshots.get(index).is_some_and(|s| s.time == snapshot.time) {
+            return;
+        }
+        self.snapshots.insert(index, snapshot);
+
+        while self.snapshots.len() > MAX_SNAPSHOTS {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// Forgets where they were, so they don't slide over from there once they are back
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+
+    /// Where the player was `delay` ago, by our clock
+    pub fn sample(&mut self, now: f64, delay: f64) -> Option<(Vec3, Quat)> {
+        let render_time = now - self.clock_offset? - delay;
+
+        // only the newest snapshot before render_time is still useful
+        while self.snapshots.len() > 1 && self.snapshots[1].time <= render_time {
+            self.snapshots.pop_front();
+        }
+
+        let from = *self.snapshots.front()?;
+        let Some(to) = self.snapshots.get(1).copied() else {
+            // ran dry: keep going the way they were heading, but not for long
+            let ahead = (render_time - from.time).clamp(0.0, MAX_EXTRAPOLATION) as f32;
+            return Some((from.pos + from.vel * ahead, from.rot));
+        };
+
+        if render_time <= from.time || from.pos.distance(to.pos) > TELEPORT_THRESHOLD {
+            return Some((from.pos, from.rot));
+        }
+
+        let t = ((render_time - from.time) / (to.time - from.time)) as f32;
+        Some((from.pos.lerp(to.pos, t), from.rot.slerp(to.rot, t)))
+    }
+}
+
+pub(crate) fn interpolate_remote_players(
+    time: Res<Time>,
+    stats: Res<NetworkStats>,
+    mut query: Query<(&mut SnapshotBuffer, &mut Transform), With<Recieved>>,
+) {
+    let now = time.elapsed_secs_f64();
+    let delay = INTERPOLATION_DELAY.max(stats.jitter * JITTER_MARGIN);
+
+    for (mut buffer, mut transform) in &mut query {
+        if let Some((pos, rot)) = buffer.sample(now, delay) {
+            transform.translation = pos;
+            transform.rotation = rot;
+        }
+    }
+}
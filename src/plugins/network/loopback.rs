@@ -0,0 +1,229 @@
+//////////////////////////////////////////////////////////
+///////////////////////// loopback ///////////////////////
+//////////////////////////////////////////////////////////
+//
+// An in-process relay. Every `App` given a clone of the same
+// `LoopbackTransport` hears the others as if they were online,
+// which is all we need to test multiplayer without a server.
+use super::config::NetworkConfig;
+use super::protocol::RoomInfo;
+use super::queue::{self, QUEUE_CAPACITY, Sender};
+use super::resource::{WSMessageChannels, WSMessages};
+use super::transport::Transport;
+use bevy::prelude::*;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+type PeerId = u64;
+type Rooms = HashMap<String, HashMap<PeerId, Sender>>;
+
+#[derive(Resource, Clone, Default)]
+pub struct LoopbackTransport {
+    rooms: Arc<Mutex<Rooms>>,
+    next_peer: Arc<AtomicU64>,
+}
+
+impl LoopbackTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drops every peer in the room, as if the server went away
+    pub fn disconnect_room(&self, config: &NetworkConfig, reason: &str) {
+        let peers = self.rooms.lock().unwrap().remove(&room_key(config));
+        for to_peer in peers.into_iter().flat_map(HashMap::into_values) {
+            to_peer
+                .send(WSMessages::Disconnected(reason.to_string()))
+                .ok();
+        }
+    }
+}
+
+impl Transport for LoopbackTransport {
+    fn connect(&self, config: &NetworkConfig) -> WSMessageChannels {
+        let (to_others_tx, mut to_others_rx) = queue::channel(QUEUE_CAPACITY);
+        let (to_us_tx, to_us_rx) = queue::channel(QUEUE_CAPACITY);
+
+        if config.list_rooms {
+            let prefix = format!("{}/", config.app_id);
+            let rooms: Vec<RoomInfo> = self
+                .rooms
+                .lock()
+                .unwrap()
+                .iter()
+                .filter_map(|(key, peers)| {
+                    let name = key.strip_prefix(&prefix)?;
+                    (!peers.is_empty()).then(|| RoomInfo {
+                        name: name.to_string(),
+                        players: peers.len(),
+                        locked: false,
+                    })
+                })
+                .collect();
+            to_us_tx.send(WSMessages::Connected).ok();
+            if let Ok(json) = serde_json::to_string(&rooms) {
+                to_us_tx.send(WSMessages::Message(json)).ok();
+            }
+            return WSMessageChannels::new(to_us_rx, to_others_tx);
+        }
+
+        let peer = self.next_peer.fetch_add(1, Ordering::Relaxed);
+        let key = room_key(config);
+        self.rooms
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .insert(peer, to_us_tx.clone());
+        to_us_tx.send(WSMessages::Connected).ok();
+
+        // a plain thread, so tests don't need an async runtime
+        let rooms = self.rooms.clone();
+        std::thread::spawn(move || {
+            while let Some(msg) = to_others_rx.blocking_recv() {
+                let WSMessages::Sync(bytes) = msg else {
+                    continue;
+                };
+                let mut rooms = rooms.lock().unwrap();
+                let Some(peers) = rooms.get_mut(&key) else {
+                    continue;
+                };
+                peers.retain(|&other, to_other| {
+                    other == peer || to_other.send(WSMessages::Sync(bytes.clone())).is_ok()
+                });
+            }
+
+            // the game dropped its channels, leave the room
+            if let Some(peers) = rooms.lock().unwrap().get_mut(&key) {
+                peers.remove(&peer);
+            }
+        });
+
+        WSMessageChannels::new(to_us_rx, to_others_tx)
+    }
+}
+
+fn room_key(config: &NetworkConfig) -> String {
+    format!("{}/{}", config.app_id, config.room)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::entities::{DisplayName, Player, PlayerBody};
+    use crate::components::vitals::Movement;
+    use crate::plugins::menu::GameState;
+    use crate::plugins::network::identity::PlayerIdentity;
+    use crate::plugins::network::replication::Replicate;
+    use crate::plugins::network::synchronizer::Synchronizer;
+    use crate::plugins::network::{MultiplayerPlugin, Recieved};
+    use avian3d::prelude::LinearVelocity;
+    use bevy::state::app::StatesPlugin;
+    use std::time::Duration;
+
+    // a headless game with its local player, straight into playing
+    fn player_app(transport: &LoopbackTransport, id: i64) -> App {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            StatesPlugin,
+            AssetPlugin::default(),
+            bevy::scene::ScenePlugin,
+            bevy::mesh::MeshPlugin,
+        ))
+        .init_asset::<bevy::gltf::Gltf>()
+        .insert_resource(PlayerIdentity {
+            id,
+            name: format!("Player{}", id),
+        })
+        .add_plugins(MultiplayerPlugin::new(transport.clone()))
+        .insert_state(GameState::Menu);
+
+        app.world_mut().spawn((
+            Synchronizer { id, ..default() },
+            Transform::from_xyz(id as f32, 0.0, 0.0),
+            LinearVelocity::default(),
+            Movement {
+                speed: 1.0,
+                sprint_aplifier: 1.0,
+                jump_strength: 1.0,
+                is_grounded: true,
+                extra_jumps: 0,
+                current_jumps: 0,
+            },
+            Player,
+            PlayerBody,
+            DisplayName(format!("Player{}", id)),
+            Replicate,
+        ));
+        app.world_mut()
+            .resource_mut::<NextState<GameState>>()
+            .set(GameState::Playing);
+        app
+    }
+
+    fn run(apps: &mut [&mut App], frames: usize) {
+        for _ in 0..frames {
+            for app in apps.iter_mut() {
+                app.update();
+            }
+            // the relay runs on threads of its own
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    // where `app` has the remote players, by id
+    fn remote_players(app: &mut App) -> Vec<(i64, Vec3)> {
+        let mut query = app
+            .world_mut()
+            .query_filtered::<(&Synchronizer, &Transform), With<Recieved>>();
+        query
+            .iter(app.world())
+            .map(|(sync, transform)| (sync.id, transform.translation))
+            .collect()
+    }
+
+    fn move_local(app: &mut App, to: Vec3) {
+        let mut query = app
+            .world_mut()
+            .query_filtered::<&mut Transform, (With<Synchronizer>, Without<Recieved>)>();
+        query.single_mut(app.world_mut()).unwrap().translation = to;
+    }
+
+    #[test]
+    fn two_apps_join_update_and_leave() {
+        let transport = LoopbackTransport::new();
+        let mut first = player_app(&transport, 1);
+        let mut second = player_app(&transport, 2);
+
+        run(&mut [&mut first, &mut second], 20);
+        assert_eq!(
+            remote_players(&mut first)
+                .iter()
+                .map(|p| p.0)
+                .collect::<Vec<_>>(),
+            [2]
+        );
+        assert_eq!(
+            remote_players(&mut second)
+                .iter()
+                .map(|p| p.0)
+                .collect::<Vec<_>>(),
+            [1]
+        );
+
+        let moved = Vec3::new(5.0, 1.0, -3.0);
+        move_local(&mut first, moved);
+        run(&mut [&mut first, &mut second], 40);
+        let (_, seen) = remote_players(&mut second)[0];
+        assert!(seen.distance(moved) < 0.1, "second sees first at {}", seen);
+
+        first
+            .world_mut()
+            .resource_mut::<NextState<GameState>>()
+            .set(GameState::Menu);
+        run(&mut [&mut first, &mut second], 10);
+        assert!(remote_players(&mut second).is_empty());
+    }
+}
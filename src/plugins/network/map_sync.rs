@@ -0,0 +1,155 @@
+//////////////////////////////////////////////////////////
+///////////////////////// map sync ///////////////////////
+//////////////////////////////////////////////////////////
+//
+// Everyone in a room plays on the same map. An authoritative server sends
+// its own to each newcomer. On a relay the first one in hosts: whoever
+// hears nothing for a moment after connecting claims the room, answers
+// everybody who joins later, and hands over to the lowest id on leaving.
+use super::Recieved;
+use super::protocol::{MapInfo, NetMessage, SERVER_ID};
+use super::resource::{LobbyInfo, WSMessageChannels};
+use super::synchronizer::Synchronizer;
+use crate::plugins::map::MapSettings;
+use bevy::prelude::*;
+
+const MAP_CLAIM_DELAY: f64 = 2.0; // seconds to wait for someone else's map
+
+/// A map someone in the room sent us
+#[derive(Message, Debug, Clone)]
+pub struct MapReceived(pub MapInfo);
+
+/// Who picked the map we are playing on
+#[derive(Resource, Debug, Default)]
+pub struct MapHost {
+    // `None` while we wait to hear from the room
+    pub host: Option<i64>,
+    // the host showed up in the room, so we notice once they are gone
+    host_seen: bool,
+    // elapsed seconds when we started waiting
+    waiting_since: f64,
+}
+
+// the server always wins, between players the lower id does
+fn outranks(a: i64, b: i64) -> bool {
+    a == SERVER_ID || (b != SERVER_ID && a < b)
+}
+
+fn send_map(channels: &WSMessageChannels, host: i64, settings: &MapSettings) {
+    channels.send(&NetMessage::Map(MapInfo {
+        host,
+        settings: settings.clone(),
+    }));
+}
+
+// every (re)connect, the room may have moved on without us
+pub(crate) fn wait_for_map(mut host: ResMut<MapHost>, time: Res<Time>) {
+    *host = MapHost {
+        waiting_since: time.elapsed_secs_f64(),
+        ..default()
+    };
+}
+
+pub(crate) fn receive_map(
+    mut received: MessageReader<MapReceived>,
+    mut host: ResMut<MapHost>,
+    mut settings: ResMut<MapSettings>,
+    channels: Res<WSMessageChannels>,
+    local: Query<&Synchronizer, Without<Recieved>>,
+) {
+    let Ok(local) = local.single() else {
+        received.clear();
+        return;
+    };
+
+    for MapReceived(info) in received.read() {
+        if info.host == local.id {
+            continue;
+        }
+        if !info.settings.is_valid() {
+            warn!("Ignoring a broken map from {}", info.host);
+            continue;
+        }
+        // somebody else claimed the room at the same time as us
+        if host.host == Some(local.id) && outranks(local.id, info.host) {
+            send_map(&channels, local.id, &settings);
+            continue;
+        }
+        if let Some(current) = host.host
+            && current != info.host
+            && !outranks(info.host, current)
+        {
+            continue;
+        }
+
+        if host.host != Some(info.host) {
+            info!(
+                "Playing on the map of {} (seed {})",
+                info.host, info.settings.seed
+            );
+            host.host = Some(info.host);
+            host.host_seen = false;
+        }
+        // only regenerates when it actually is a different map
+        settings.set_if_neq(info.settings.clone());
+    }
+}
+
+pub(crate) fn update_map_host(
+    mut host: ResMut<MapHost>,
+    lobby: Res<LobbyInfo>,
+    settings: Res<MapSettings>,
+    channels: Res<WSMessageChannels>,
+    local: Query<&Synchronizer, Without<Recieved>>,
+    time: Res<Time>,
+) {
+    let Ok(local) = local.single() else {
+        return;
+    };
+
+    match host.host {
+        None => {
+            if time.elapsed_secs_f64() - host.waiting_since > MAP_CLAIM_DELAY {
+                info!("Nobody here has a map yet, hosting seed {}", settings.seed);
+                host.host = Some(local.id);
+                send_map(&channels, local.id, &settings);
+            }
+        }
+        Some(id) if id == local.id || id == SERVER_ID => {}
+        Some(id) => {
+            if lobby.players.contains_key(&id) {
+                host.host_seen = true;
+            } else if host.host_seen {
+                // everyone left works this out the same way, nothing to send
+                let next = lobby
+                    .players
+                    .keys()
+                    .copied()
+                    .chain([local.id])
+                    .min()
+                    .unwrap_or(local.id);
+                info!("Map host {} left, {} hosts now", id, next);
+                host.host = Some(next);
+                host.host_seen = false;
+            }
+        }
+    }
+}
+
+// the host tells everyone who shows up where they are
+pub(crate) fn send_map_to_newcomers(
+    newcomers: Query<(), Added<Recieved>>,
+    host: Res<MapHost>,
+    settings: Res<MapSettings>,
+    channels: Res<WSMessageChannels>,
+    local: Query<&Synchronizer, Without<Recieved>>,
+) {
+    if newcomers.is_empty() {
+        return;
+    }
+    if let Ok(local) = local.single()
+        && host.host == Some(local.id)
+    {
+        send_map(&channels, local.id, &settings);
+    }
+}
@@ -1,24 +1,63 @@
 /////////////////////////////////////////////////////////
 ///////////////////////// Network mod ////////////////////////
 /////////////////////////////////////////////////////////
+pub mod chat;
+pub mod compression;
+pub mod conditioner;
+pub mod config;
+pub mod connection;
+pub mod identity;
+pub mod interpolation;
+// relays on a thread, which the browser doesn't have
+#[cfg(not(target_arch = "wasm32"))]
+pub mod loopback;
+pub mod map_sync;
+pub mod ping;
+pub mod prediction;
+pub mod presence;
+pub mod protocol;
+pub mod queue;
+// files and threads, native only
+#[cfg(not(target_arch = "wasm32"))]
+pub mod recording;
+pub mod replication;
 pub mod resource;
+pub mod rooms;
 pub mod synchronizer;
+pub mod token;
+pub mod transport;
 
 #[cfg(not(target_arch = "wasm32"))]
 pub mod native;
 #[cfg(not(target_arch = "wasm32"))]
-use native::connect_multiplayer;
+pub use native::WebSocketTransport;
 
 #[allow(inactive_code)]
 #[cfg(target_arch = "wasm32")]
 pub mod wasm;
 #[cfg(target_arch = "wasm32")]
-use wasm::connect_multiplayer;
+pub use wasm::WebSocketTransport;
 
-use synchronizer::{handle_sync, multiplayer_sender};
+use bevy::time::common_conditions::on_timer;
+use chat::ChatReceived;
+use config::NetworkConfig;
+use connection::ConnectionState;
+use identity::{PlayerIdentity, sanitize_remote_names, update_display_name};
+use interpolation::interpolate_remote_players;
+use map_sync::{
+    MapHost, MapReceived, receive_map, send_map_to_newcomers, update_map_host, wait_for_map,
+};
+use ping::{PING_INTERVAL, send_ping};
+use prediction::{ServerCorrection, reconcile_local_player};
+use replication::{register_replicated_components, send_replicated};
+use rooms::{RefreshRooms, RoomList, hang_up_room_list, receive_room_list, request_room_list};
+use presence::{HEARTBEAT_INTERVAL, despawn_silent_players, disconnect_multiplayer, send_heartbeat};
+use synchronizer::{SendKeyframe, handle_sync, multiplayer_sender};
+use token::JoinClaims;
+use transport::{Transport, connect_multiplayer, tick_transport};
 use bevy::prelude::*;
-use tokio::runtime::Builder;
-use std::sync::Arc;
+use std::time::Duration;
+use crate::plugins::map::MapSettings;
 use crate::plugins::menu::GameState;
 
 
@@ -26,26 +65,110 @@ use crate::plugins::menu::GameState;
 pub struct Recieved;
 
 
-pub struct MultiplayerPlugin;
+/// Online play over any `Transport`, websockets unless told otherwise
+pub struct MultiplayerPlugin<T: Transport = WebSocketTransport> {
+    pub transport: T,
+}
+
+impl Default for MultiplayerPlugin {
+    fn default() -> Self {
+        Self::new(WebSocketTransport::default())
+    }
+}
 
+impl<T: Transport> MultiplayerPlugin<T> {
+    pub fn new(transport: T) -> Self {
+        Self { transport }
+    }
+}
 
-impl Plugin for MultiplayerPlugin {
+
+impl<T: Transport> Plugin for MultiplayerPlugin<T> {
     fn build(&self, app: &mut App) {
-        #[cfg(not(target_arch = "wasm32"))]
-        {
-            use native::MultiplayerRuntime;
-            let mp_runtime = Arc::new(
-                Builder::new_multi_thread()
-                    .worker_threads(2)
-                    .thread_name("mp-workers")
-                    .enable_all()
-                    .build()
-                    .expect("Failed to create Tokio runtime for multiplayer")
-            );
-            app.insert_resource(MultiplayerRuntime(mp_runtime));
+        app.insert_resource(self.transport.clone());
+
+        // other binaries and tests can provide their own config before adding the plugin
+        if !app.world().contains_resource::<NetworkConfig>() {
+            app.insert_resource(NetworkConfig::load());
+        }
+        if !app.world().contains_resource::<PlayerIdentity>() {
+            let path = app.world().resource::<NetworkConfig>().identity.clone();
+            app.insert_resource(PlayerIdentity::load(path.as_deref()));
+        }
+        // with a join token the server decides who we are, so we play as that from the start
+        let token = app.world().resource::<NetworkConfig>().token.clone();
+        if let Some(claims) = JoinClaims::peek(&token) {
+            app.world_mut()
+                .resource_mut::<PlayerIdentity>()
+                .adopt(&claims);
         }
+        // what we host with if nobody else in the room has a map
+        if !app.world().contains_resource::<MapSettings>() {
+            app.insert_resource(MapSettings::load());
+        }
+
+        app.init_state::<ConnectionState>();
+        app.add_message::<ServerCorrection>();
+        app.add_message::<SendKeyframe>();
+        app.add_message::<ChatReceived>();
+        app.add_message::<MapReceived>();
+        app.init_resource::<MapHost>();
+        register_replicated_components(app);
+        app.add_systems(OnEnter(GameState::Playing), connect_multiplayer::<T>);
+        app.add_systems(OnExit(GameState::Playing), disconnect_multiplayer);
+
+        app.add_message::<RefreshRooms>();
+        app.init_resource::<RoomList>();
+        app.add_systems(
+            Update,
+            (request_room_list::<T>, receive_room_list)
+                .chain()
+                .run_if(in_state(GameState::Lobby)),
+        );
+        app.add_systems(OnExit(GameState::Lobby), hang_up_room_list);
+        app.add_systems(
+            Update,
+            (
+                (
+                    tick_transport::<T>,
+                    handle_sync,
+                    (interpolate_remote_players, reconcile_local_player, send_replicated),
+                )
+                    .chain(),
+                despawn_silent_players,
+                (update_display_name, sanitize_remote_names),
+            )
+                .run_if(in_state(GameState::Playing)),
+        );
+        app.add_systems(OnEnter(ConnectionState::Connected), wait_for_map);
+        app.add_systems(
+            Update,
+            (receive_map, update_map_host, send_map_to_newcomers)
+                .chain()
+                .after(handle_sync)
+                .run_if(in_state(GameState::Playing))
+                .run_if(in_state(ConnectionState::Connected)),
+        );
 
-        app.add_systems(OnEnter(GameState::Playing), connect_multiplayer);
-        app.add_systems(Update, (multiplayer_sender, handle_sync).run_if(in_state(GameState::Playing)));
+        // bandwidth follows the network tick instead of the framerate
+        let tick = Duration::from_secs_f64(1.0 / app.world().resource::<NetworkConfig>().tick_rate);
+        app.add_systems(
+            Update,
+            multiplayer_sender
+                .run_if(in_state(GameState::Playing))
+                .run_if(on_timer(tick)),
+        );
+        app.add_systems(
+            Update,
+            send_heartbeat
+                .run_if(in_state(GameState::Playing))
+                .run_if(on_timer(Duration::from_secs_f32(HEARTBEAT_INTERVAL))),
+        );
+        app.add_systems(
+            Update,
+            send_ping
+                .run_if(in_state(GameState::Playing))
+                .run_if(on_timer(Duration::from_secs_f32(PING_INTERVAL))),
+        );
     }
 }
@@ -2,25 +2,24 @@
 ///////////////////////// native ////////////////////////
 /////////////////////////////////////////////////////////
 
-use super::resource;
-use super::resource::WSMessages;
+use super::config::{NetworkConfig, percent_encode};
+use super::queue::{self, QUEUE_CAPACITY};
+use super::resource::WSMessageChannels;
+use super::transport::{Frame, Transport, run_connection};
 use bevy::prelude::*;
-use futures_util::{SinkExt, StreamExt};
-use tokio::sync::mpsc::{UnboundedSender as Sender, UnboundedReceiver as Receiver, self};
+use futures_util::{SinkExt, StreamExt, future};
 use std::sync::Arc;
-use tokio::runtime::Runtime;
+use tokio::runtime::{Builder, Runtime};
 
 use tokio_tungstenite::{
-    tungstenite::Message,
+    tungstenite::{self, Message},
     tungstenite::client::IntoClientRequest,
     connect_async,
+    MaybeTlsStream,
+    WebSocketStream,
 };
 
-
-
-const APP_ID: &str = "67";
-const URL: &str = "wss://broadcast.dogfetus.no";
-
+type Socket = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
 
 
 
@@ -30,94 +29,102 @@ const URL: &str = "wss://broadcast.dogfetus.no";
 #[derive(Resource, Clone)]
 pub struct MultiplayerRuntime(pub Arc<Runtime>);
 
-pub(crate) fn connect_multiplayer(
-    mut commands: Commands,
-    mp_runtime: Res<MultiplayerRuntime>,
-) {
-    let (to_others_tx, to_others_rx) = mpsc::unbounded_channel();
-    let (to_us_tx, to_us_rx) = mpsc::unbounded_channel();
-
-    spawn_ws_tasks_native(mp_runtime, to_others_rx, to_us_tx);
-
-    commands.insert_resource(resource::WSMessageChannels {
-        incomming: to_us_rx,
-        outgoing: to_others_tx,
-    });
-
-    commands.init_resource::<resource::LobbyInfo>();
+#[derive(Resource, Clone)]
+pub struct WebSocketTransport {
+    runtime: MultiplayerRuntime,
 }
 
+impl Default for WebSocketTransport {
+    fn default() -> Self {
+        let runtime = Arc::new(
+            Builder::new_multi_thread()
+                .worker_threads(2)
+                .thread_name("mp-workers")
+                .enable_all()
+                .build()
+                .expect("Failed to create Tokio runtime for multiplayer")
+        );
+        Self::with_runtime(runtime)
+    }
+}
 
-// spawn websocket tasks for native
-fn spawn_ws_tasks_native(
-    mp_runtime: Res<MultiplayerRuntime>,
-    to_others: Receiver<WSMessages>,
-    to_us: Sender<WSMessages>,
-) {
-    mp_runtime.0.spawn(async {
-        let mut request = URL.into_client_request().expect("Invalid URL");
-        request
-            .headers_mut()
-            .insert("app_id", APP_ID.parse().expect("Invalid APP_ID"));
-
-         match connect_async(request).await {
-            Ok((socket, _response)) => {
-                // info!("WebSocket connected successfully");
-                let (sender, receiver) = socket.split();
-
-                tokio::spawn(ws_sender(sender, to_others));
-                tokio::spawn(ws_receiver(receiver, to_us));
-            }
-            Err(e) => {
-                error!("Failed to connect to WebSocket: {:?}", e);
-            }
+impl WebSocketTransport {
+    // lets several clients share one runtime
+    pub fn with_runtime(runtime: Arc<Runtime>) -> Self {
+        Self {
+            runtime: MultiplayerRuntime(runtime),
         }
-    });
+    }
 }
 
-// Spawn sender task
-async fn ws_sender(
-    mut ws_sender: futures_util::stream::SplitSink<
-        tokio_tungstenite::WebSocketStream<
-            tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
-        >,
-        Message,
-    >,
-    mut to_others: Receiver<WSMessages>,
-) {
-    while let Some(msg) = to_others.recv().await {
-        let to_send = match msg {
-            WSMessages::Sync(s) => {
-                Message::Binary(s.into())
-            }
-            _ => Message::Ping(vec![].into()),
-        };
-        if let Err(e) = ws_sender.send(to_send).await {
-            eprintln!("Failed to send message: {:?}", e);
-            break;
-        }
+impl Transport for WebSocketTransport {
+    fn connect(&self, config: &NetworkConfig) -> WSMessageChannels {
+        let (to_others_tx, to_others_rx) = queue::channel(QUEUE_CAPACITY);
+        let (to_us_tx, to_us_rx) = queue::channel(QUEUE_CAPACITY);
+
+        let config = config.clone();
+        let open = move || open_socket(config.clone());
+        let wait = tokio::time::sleep;
+        self.runtime
+            .0
+            .spawn(run_connection(open, wait, to_others_rx, to_us_tx));
+
+        WSMessageChannels::new(to_us_rx, to_others_tx)
     }
 }
 
 
-// Spawn receiver task
-async fn ws_receiver(
-    mut ws_receiver: futures_util::stream::SplitStream<
-        tokio_tungstenite::WebSocketStream<
-            tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
-        >,
-    >,
-    to_us: Sender<WSMessages>,
-) {
-    while let Some(message) = ws_receiver.next().await {
-        match message {
-            Ok(msg) => {
-                let bytes = msg.into_data();
-                to_us.send(WSMessages::Sync(bytes.to_vec())).ok();
-            }
+async fn open_socket(
+    config: NetworkConfig,
+) -> Result<
+    (
+        impl futures_util::Sink<Vec<u8>, Error = tungstenite::Error> + Unpin,
+        impl futures_util::Stream<Item = Frame> + Unpin,
+    ),
+    String,
+> {
+    let mut request = config
+        .url
+        .as_str()
+        .into_client_request()
+        .map_err(|e| format!("invalid multiplayer url {}: {}", config.url, e))?;
+    // encoded like the query, so whatever was typed fits in a header
+    let header = |value: &str| {
+        tungstenite::http::HeaderValue::from_str(&percent_encode(value))
+            .expect("percent encoding leaves only plain ascii")
+    };
+    request.headers_mut().insert("app_id", header(&config.app_id));
+    request.headers_mut().insert("room", header(&config.room));
+    if !config.password.is_empty() {
+        request.headers_mut().insert("password", header(&config.password));
+    }
+    if !config.token.is_empty() {
+        request.headers_mut().insert("token", header(&config.token));
+    }
+    if config.list_rooms {
+        request
+            .headers_mut()
+            .insert("list_rooms", tungstenite::http::HeaderValue::from_static("1"));
+    }
+
+    let (socket, _response): (Socket, _) = connect_async(request).await.map_err(|e| e.to_string())?;
+    let (sender, receiver) = socket.split();
+
+    let sender = sender.with(|bytes: Vec<u8>| future::ready(Ok(Message::Binary(bytes.into()))));
+    let receiver = receiver.filter_map(|message| {
+        future::ready(match message {
+            Ok(Message::Binary(bytes)) => Some(Frame::Data(bytes.to_vec())),
+            Ok(Message::Text(text)) => Some(Frame::Text(text.to_string())),
+            Ok(Message::Close(frame)) => Some(Frame::Closed(
+                frame.map_or_else(|| "connection closed".to_string(), |f| f.reason.to_string()),
+            )),
+            Ok(_) => None,
             Err(e) => {
                 eprintln!("WebSocket error: {:?}", e);
+                Some(Frame::Closed(e.to_string()))
             }
-        };
-    }
+        })
+    });
+
+    Ok((sender, receiver))
 }
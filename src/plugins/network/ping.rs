@@ -0,0 +1,184 @@
+//////////////////////////////////////////////////////
+///////////////////////// ping ///////////////////////
+//////////////////////////////////////////////////////
+//
+// Every second we ping someone, and they pong back with their clock. Half
+// the round trip is how old their clock reading is when it gets here,
+// which is all we need to line their clock up with ours. On a relay the
+// pings take turns going to each player, so a room of N costs N pongs a
+// second rather than everyone answering everyone.
+use super::Recieved;
+use super::map_sync::MapHost;
+use super::protocol::{NetMessage, PingInfo, PongInfo, SERVER_ID};
+use super::resource::{LobbyInfo, NetworkStats, WSMessageChannels};
+use super::synchronizer::Synchronizer;
+use bevy::prelude::*;
+
+pub const PING_INTERVAL: f32 = 1.0; // seconds between pings
+const PING_TIMEOUT: f64 = 2.0; // unanswered for this long counts as lost
+// same weights TCP uses for its round trip estimate
+const RTT_SMOOTHING: f64 = 0.125;
+const JITTER_SMOOTHING: f64 = 0.25;
+const LOSS_SMOOTHING: f32 = 0.1;
+
+impl NetworkStats {
+    pub(crate) fn record_pong(&mut self, pong: &PongInfo, now: f64) {
+        // late, duplicated or from someone we didn't ask
+        let Some(index) = self
+            .pending
+            .iter()
+            .position(|(seq, to, _)| *seq == pong.seq && *to == pong.id)
+        else {
+            return;
+        };
+        self.pending.remove(index);
+        self.record_loss(false);
+
+        let rtt = (now - pong.sent_at).max(0.0);
+        let offset = pong.time + rtt / 2.0 - now;
+
+        if self.clock_offsets.is_empty() {
+            self.rtt = rtt;
+            self.jitter = rtt / 2.0;
+        } else {
+            self.jitter += ((rtt - self.rtt).abs() - self.jitter) * JITTER_SMOOTHING;
+            self.rtt += (rtt - self.rtt) * RTT_SMOOTHING;
+        }
+
+        self.clock_offsets
+            .entry(pong.id)
+            .and_modify(|current| *current += (offset - *current) * RTT_SMOOTHING)
+            .or_insert(offset);
+        self.round_trips
+            .entry(pong.id)
+            .and_modify(|current| *current += (rtt - *current) * RTT_SMOOTHING)
+            .or_insert(rtt);
+    }
+
+    fn record_loss(&mut self, lost: bool) {
+        let sample = if lost { 1.0 } else { 0.0 };
+        self.packet_loss += (sample - self.packet_loss) * LOSS_SMOOTHING;
+    }
+}
+
+// the server when there is one, or nobody to take turns with yet
+fn ping_target(seq: u32, lobby: &LobbyInfo, host: Option<&MapHost>) -> i64 {
+    if host.is_some_and(|host| host.host == Some(SERVER_ID)) || lobby.players.is_empty() {
+        return SERVER_ID;
+    }
+    let mut players: Vec<i64> = lobby.players.keys().copied().collect();
+    players.sort_unstable();
+    players[seq as usize % players.len()]
+}
+
+pub(crate) fn send_ping(
+    channels: Res<WSMessageChannels>,
+    mut stats: ResMut<NetworkStats>,
+    lobby: Res<LobbyInfo>,
+    host: Option<Res<MapHost>>,
+    local: Query<&Synchronizer, Without<Recieved>>,
+    time: Res<Time>,
+) {
+    let Ok(local) = local.single() else {
+        return;
+    };
+    let now = time.elapsed_secs_f64();
+
+    // alone in a relay room nobody can answer, that isn't loss
+    let someone_listening =
+        !lobby.players.is_empty() || stats.clock_offsets.contains_key(&SERVER_ID);
+    while stats
+        .pending
+        .front()
+        .is_some_and(|(_, _, sent_at)| now - sent_at > PING_TIMEOUT)
+    {
+        stats.pending.pop_front();
+        if someone_listening {
+            stats.record_loss(true);
+        }
+    }
+
+    let seq = stats.next_ping;
+    stats.next_ping = seq.wrapping_add(1);
+    let to = ping_target(seq, &lobby, host.as_deref());
+    stats.pending.push_back((seq, to, now));
+    channels.send(&NetMessage::Ping(PingInfo {
+        id: local.id,
+        to,
+        seq,
+        sent_at: now,
+    }));
+}
+
+pub(crate) fn answer_ping(
+    channels: &WSMessageChannels,
+    local: &Synchronizer,
+    ping: PingInfo,
+    now: f64,
+) {
+    channels.send(&NetMessage::Pong(PongInfo {
+        id: local.id,
+        to: ping.id,
+        seq: ping.seq,
+        sent_at: ping.sent_at,
+        time: now,
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lobby(ids: &[i64]) -> LobbyInfo {
+        let mut lobby = LobbyInfo::default();
+        for &id in ids {
+            lobby.players.insert(id, Entity::PLACEHOLDER);
+        }
+        lobby
+    }
+
+    fn pong(id: i64, seq: u32, sent_at: f64) -> PongInfo {
+        PongInfo {
+            id,
+            to: 1,
+            seq,
+            sent_at,
+            time: 100.0,
+        }
+    }
+
+    #[test]
+    fn pings_take_turns_on_a_relay() {
+        let lobby = lobby(&[30, 10, 20]);
+        let targets: Vec<i64> = (0..6).map(|seq| ping_target(seq, &lobby, None)).collect();
+        assert_eq!(targets, [10, 20, 30, 10, 20, 30]);
+    }
+
+    #[test]
+    fn the_server_gets_every_ping() {
+        let mut host = MapHost::default();
+        host.host = Some(SERVER_ID);
+        assert_eq!(ping_target(1, &lobby(&[10, 20]), Some(&host)), SERVER_ID);
+        // alone, or before anyone shows up
+        assert_eq!(ping_target(1, &lobby(&[]), None), SERVER_ID);
+    }
+
+    #[test]
+    fn only_the_addressed_pong_counts() {
+        let mut stats = NetworkStats::default();
+        stats.pending.push_back((4, 10, 1.0));
+
+        // someone who wasn't asked
+        stats.record_pong(&pong(20, 4, 1.0), 1.5);
+        assert!(stats.round_trips.is_empty());
+
+        stats.record_pong(&pong(10, 4, 1.0), 1.2);
+        assert!((stats.rtt - 0.2).abs() < 1e-9);
+        assert!(stats.pending.is_empty());
+
+        // the same pong again changes nothing
+        stats.record_pong(&pong(10, 4, 1.0), 1.9);
+        assert!((stats.rtt - 0.2).abs() < 1e-9);
+        assert_eq!(stats.round_trips.len(), 1);
+    }
+}
@@ -0,0 +1,188 @@
+////////////////////////////////////////////////////////////
+///////////////////////// prediction ///////////////////////
+////////////////////////////////////////////////////////////
+//
+// We move right away and remember what each numbered input did. When
+// the server answers for input N we rewind to where it says N left us
+// and replay every input after N on top, then ease over to the result.
+// Physics only applies an input on the frame after it was given, so an
+// input is filed away once the next one shows where it really took us.
+use super::synchronizer::Synchronizer;
+use crate::components::entities::Player;
+use bevy::prelude::*;
+use std::collections::VecDeque;
+
+const MAX_HISTORY: usize = 256; // a few seconds of frames, older acks are useless anyway
+const DEAD_ZONE: f32 = 0.25; // errors this small are the server being a tick behind
+const SNAP_THRESHOLD: f32 = 3.0; // further off than this and we just jump there
+const CORRECTION_RATE: f32 = 0.2; // fraction of the error fixed per correction
+
+/// Authoritative state for our own player, sent back by the server
+#[derive(Message, Debug, Clone)]
+pub struct ServerCorrection(pub Synchronizer);
+
+#[derive(Debug, Clone, Copy)]
+pub struct InputFrame {
+    pub seq: u32,
+    pub velocity: Vec3,
+    pub jump: bool,
+    // how far this input moved us
+    pub moved: Vec3,
+    // where it left us, or where it started while still pending
+    pub pos: Vec3,
+}
+
+#[derive(Component, Debug, Default)]
+pub struct PredictionHistory {
+    next_seq: u32,
+    // inputs physics has applied, oldest first
+    frames: VecDeque<InputFrame>,
+    // the newest input, physics hasn't had a go at it yet
+    pending: Option<InputFrame>,
+}
+
+impl PredictionHistory {
+    /// Stores this frame's input, `pos` being where the previous one left us.
+    /// Returns the newest input `pos` includes, the one to tell the server about
+    pub fn record(&mut self, velocity: Vec3, jump: bool, pos: Vec3) -> u32 {
+        if let Some(mut applied) = self.pending.take() {
+            applied.moved = pos - applied.pos;
+            applied.pos = pos;
+            self.frames.push_back(applied);
+            while self.frames.len() > MAX_HISTORY {
+                self.frames.pop_front();
+            }
+        }
+
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        self.pending = Some(InputFrame {
+            seq,
+            velocity,
+            jump,
+            moved: Vec3::ZERO,
+            pos,
+        });
+
+        seq.wrapping_sub(1)
+    }
+
+    /// Forgets everything before `seq` and returns where we predicted `seq` left us
+    pub fn acknowledge(&mut self, seq: u32) -> Option<Vec3> {
+        while self.frames.front().is_some_and(|f| f.seq != seq && seq_after(seq, f.seq)) {
+            self.frames.pop_front();
+        }
+        self.frames.front().filter(|f| f.seq == seq).map(|f| f.pos)
+    }
+
+    /// Rewinds to `pos` for the last acknowledged input and replays the ones
+    /// after it, returns where that puts us now
+    pub fn replay(&mut self, pos: Vec3) -> Vec3 {
+        let mut frames = self.frames.iter_mut();
+        let Some(acked) = frames.next() else {
+            return pos;
+        };
+        acked.pos = pos;
+
+        let mut pos = pos;
+        for frame in frames {
+            pos += frame.moved;
+            frame.pos = pos;
+        }
+        pos
+    }
+
+    /// We were moved by `offset` outside of any input, the pending one starts there
+    fn shift_pending(&mut self, offset: Vec3) {
+        if let Some(pending) = &mut self.pending {
+            pending.pos += offset;
+        }
+    }
+}
+
+// sequence numbers wrap, compare them like TCP does
+fn seq_after(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+pub(crate) fn reconcile_local_player(
+    mut corrections: MessageReader<ServerCorrection>,
+    player: Option<Single<(&mut Transform, &mut PredictionHistory), With<Player>>>,
+) {
+    let Some(player) = player else {
+        corrections.clear();
+        return;
+    };
+    let (mut transform, mut history) = player.into_inner();
+
+    for ServerCorrection(authoritative) in corrections.read() {
+        let Some(predicted) = history.acknowledge(authoritative.input_seq) else {
+            continue;
+        };
+        if authoritative.pos.distance(predicted) <= DEAD_ZONE {
+            continue;
+        }
+
+        let replayed = history.replay(authoritative.pos);
+        let error = replayed - transform.translation;
+        let correction = if error.length() > SNAP_THRESHOLD {
+            error
+        } else {
+            error * CORRECTION_RATE
+        };
+
+        transform.translation += correction;
+        history.shift_pending(correction);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // one step along x per input, physics applying each a frame late
+    fn walked(steps: u32) -> (PredictionHistory, u32) {
+        let mut history = PredictionHistory::default();
+        let mut sent = 0;
+        for step in 0..steps {
+            sent = history.record(Vec3::X, false, Vec3::X * step as f32);
+        }
+        (history, sent)
+    }
+
+    #[test]
+    fn an_input_is_stored_where_physics_left_us() {
+        let (mut history, sent) = walked(5);
+        // the position sent with the fifth record is the fourth input's doing
+        assert_eq!(sent, 3);
+        assert_eq!(history.acknowledge(sent), Some(Vec3::X * 4.0));
+        // the newest input hasn't been applied yet
+        assert_eq!(history.acknowledge(4), None);
+    }
+
+    #[test]
+    fn acknowledging_forgets_older_inputs() {
+        let (mut history, _) = walked(10);
+        assert_eq!(history.acknowledge(5), Some(Vec3::X * 6.0));
+        assert_eq!(history.acknowledge(2), None);
+        assert_eq!(history.acknowledge(5), Some(Vec3::X * 6.0));
+    }
+
+    #[test]
+    fn replay_starts_from_the_server_and_redoes_later_inputs() {
+        let (mut history, _) = walked(10);
+        history.acknowledge(5);
+        // the server had input 5 leave us half a step short and a bit higher
+        let replayed = history.replay(Vec3::new(5.5, 1.0, 0.0));
+        // inputs 6, 7 and 8 each walked one step from there
+        assert_eq!(replayed, Vec3::new(8.5, 1.0, 0.0));
+        assert_eq!(history.acknowledge(7), Some(Vec3::new(7.5, 1.0, 0.0)));
+    }
+
+    #[test]
+    fn sequence_numbers_wrap() {
+        assert!(seq_after(0, u32::MAX));
+        assert!(seq_after(5, 3));
+        assert!(!seq_after(3, 5));
+    }
+}
@@ -0,0 +1,80 @@
+//////////////////////////////////////////////////////////
+///////////////////////// presence ///////////////////////
+//////////////////////////////////////////////////////////
+//
+// Who is online: hello on connect, heartbeat while playing,
+// goodbye when leaving and a timeout for those who vanish.
+use super::Recieved;
+use super::connection::ConnectionState;
+use super::protocol::{HeartbeatInfo, JoinInfo, LeaveInfo, NetMessage};
+use super::resource::{LobbyInfo, NetworkStats, WSMessageChannels};
+use super::synchronizer::Synchronizer;
+use bevy::prelude::*;
+
+pub const HEARTBEAT_INTERVAL: f32 = 1.0; // seconds between heartbeats
+pub const PEER_TIMEOUT: f32 = 5.0; // seconds of silence before a player is dropped
+
+// our state follows as a keyframe on the next network tick
+pub(crate) fn announce_join(channels: &WSMessageChannels, local: &Synchronizer, name: &str) {
+    channels.send(&NetMessage::Join(JoinInfo {
+        id: local.id,
+        name: name.to_string(),
+    }));
+}
+
+pub(crate) fn send_heartbeat(
+    channels: Res<WSMessageChannels>,
+    local: Query<&Synchronizer, Without<Recieved>>,
+) {
+    for local in &local {
+        channels.send(&NetMessage::Heartbeat(HeartbeatInfo {
+            id: local.id,
+            out_of_range: false,
+        }));
+    }
+}
+
+pub(crate) fn despawn_silent_players(
+    mut commands: Commands,
+    mut lobby: ResMut<LobbyInfo>,
+    time: Res<Time>,
+) {
+    let now = time.elapsed_secs();
+    let silent: Vec<i64> = lobby
+        .last_seen
+        .iter()
+        .filter(|(_, seen)| now - **seen > PEER_TIMEOUT)
+        .map(|(id, _)| *id)
+        .collect();
+
+    for id in silent {
+        info!("Player {} timed out", id);
+        lobby.remove(id, &mut commands);
+    }
+}
+
+// leaving the game means leaving the room, the next PLAY connects again
+pub(crate) fn disconnect_multiplayer(
+    mut commands: Commands,
+    lobby: Option<Res<LobbyInfo>>,
+    channels: Option<Res<WSMessageChannels>>,
+    local: Query<&Synchronizer, Without<Recieved>>,
+    mut connection: ResMut<NextState<ConnectionState>>,
+) {
+    if let Some(channels) = channels {
+        for local in &local {
+            channels.send(&NetMessage::Leave(LeaveInfo { id: local.id }));
+        }
+    }
+
+    if let Some(lobby) = lobby {
+        for &entity in lobby.players.values() {
+            commands.entity(entity).despawn();
+        }
+    }
+
+    commands.remove_resource::<LobbyInfo>();
+    commands.remove_resource::<NetworkStats>();
+    commands.remove_resource::<WSMessageChannels>();
+    connection.set(ConnectionState::Offline);
+}
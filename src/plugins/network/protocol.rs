@@ -0,0 +1,450 @@
+//////////////////////////////////////////////////////////
+///////////////////////// protocol ///////////////////////
+//////////////////////////////////////////////////////////
+//
+// Every packet is `[version: u8][kind: u8][payload]`. State payloads are
+// packed by hand in `compression`, everything else is plain bincode.
+// Bump PROTOCOL_VERSION whenever a payload changes shape.
+use super::compression;
+use super::synchronizer::Synchronizer;
+use crate::plugins::map::MapSettings;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+pub const PROTOCOL_VERSION: u8 = 9;
+const HEADER_LEN: usize = 2;
+
+////////////////////////////////////////////////////////
+//////////////////////// Define ////////////////////////
+////////////////////////////////////////////////////////
+/// Only what changed since keyframe `base`, `None` means "same as in the keyframe"
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateDelta {
+    pub id: i64,
+    pub timestamp: f64,
+    pub input_seq: u32,
+    pub base: u16,
+    pub pos: Option<Vec3>,
+    pub rot: Option<Quat>,
+    pub vel: Option<Vec3>,
+    pub speed: Option<f32>,
+    pub animation_playing: Option<AnimationNodeIndex>,
+    // a bit each, cheaper to always send than to mark
+    pub jump: bool,
+    pub grounded: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct JoinInfo {
+    pub id: i64,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LeaveInfo {
+    pub id: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HeartbeatInfo {
+    pub id: i64,
+    // from the server: they are still here, just too far away to get their state
+    pub out_of_range: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChatMessage {
+    pub id: i64,
+    pub text: String,
+}
+
+/// `to` answers with a `Pong` addressed to `id`, everyone else ignores it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PingInfo {
+    pub id: i64,
+    // `SERVER_ID` for the authoritative server
+    pub to: i64,
+    pub seq: u32,
+    // sender's clock, echoed back untouched
+    pub sent_at: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PongInfo {
+    // who answered, `SERVER_ID` for an authoritative server
+    pub id: i64,
+    pub to: i64,
+    pub seq: u32,
+    pub sent_at: f64,
+    // answerer's clock when it answered
+    pub time: f64,
+}
+
+/// A replicated component's value, `key` says which one (see `replication`)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ComponentUpdate {
+    // the player it belongs to
+    pub id: i64,
+    pub key: u32,
+    pub data: Vec<u8>,
+}
+
+/// The map everyone in the room should be playing on, and who picked it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MapInfo {
+    // `SERVER_ID` on an authoritative server
+    pub host: i64,
+    pub settings: MapSettings,
+}
+
+/// Pongs from the authoritative server carry this id, players never get it
+pub const SERVER_ID: i64 = 0;
+
+/// Close reasons starting with this mean trying again won't help
+pub const REJECTION_PREFIX: &str = "rejected: ";
+
+/// One entry of the room list a relay or server sends back, as a json text
+/// frame, to a connection made with `list_rooms`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RoomInfo {
+    pub name: String,
+    pub players: usize,
+    // joining needs the password it was created with
+    pub locked: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NetMessage {
+    StateSync(Synchronizer),
+    Join(JoinInfo),
+    Leave(LeaveInfo),
+    Chat(ChatMessage),
+    Heartbeat(HeartbeatInfo),
+    StateDelta(StateDelta),
+    Ping(PingInfo),
+    Pong(PongInfo),
+    Component(ComponentUpdate),
+    Map(MapInfo),
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageKind {
+    StateSync = 1,
+    Join = 2,
+    Leave = 3,
+    Chat = 4,
+    Heartbeat = 5,
+    StateDelta = 6,
+    Ping = 7,
+    Pong = 8,
+    Component = 9,
+    Map = 10,
+}
+
+impl MessageKind {
+    fn from_u8(byte: u8) -> Option<Self> {
+        match byte {
+            1 => Some(Self::StateSync),
+            2 => Some(Self::Join),
+            3 => Some(Self::Leave),
+            4 => Some(Self::Chat),
+            5 => Some(Self::Heartbeat),
+            6 => Some(Self::StateDelta),
+            7 => Some(Self::Ping),
+            8 => Some(Self::Pong),
+            9 => Some(Self::Component),
+            10 => Some(Self::Map),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProtocolError {
+    Truncated,
+    UnknownVersion(u8),
+    UnknownKind(u8),
+    Malformed(String),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated => write!(f, "packet is shorter than its header"),
+            Self::UnknownVersion(v) => write!(
+                f,
+                "protocol version {} is not supported (expected {})",
+                v, PROTOCOL_VERSION
+            ),
+            Self::UnknownKind(k) => write!(f, "unknown message kind {}", k),
+            Self::Malformed(e) => write!(f, "malformed payload: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+//////////////////////////////////////////////////////////
+//////////////////////// Encoding ////////////////////////
+//////////////////////////////////////////////////////////
+impl NetMessage {
+    pub fn kind(&self) -> MessageKind {
+        match self {
+            Self::StateSync(_) => MessageKind::StateSync,
+            Self::Join(_) => MessageKind::Join,
+            Self::Leave(_) => MessageKind::Leave,
+            Self::Chat(_) => MessageKind::Chat,
+            Self::Heartbeat(_) => MessageKind::Heartbeat,
+            Self::StateDelta(_) => MessageKind::StateDelta,
+            Self::Ping(_) => MessageKind::Ping,
+            Self::Pong(_) => MessageKind::Pong,
+            Self::Component(_) => MessageKind::Component,
+            Self::Map(_) => MessageKind::Map,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![PROTOCOL_VERSION, self.kind() as u8];
+
+        match self {
+            Self::StateSync(sync) => compression::encode_state(sync, &mut bytes),
+            Self::StateDelta(delta) => compression::encode_delta(delta, &mut bytes),
+            Self::Join(join) => encode_payload(join, &mut bytes),
+            Self::Leave(leave) => encode_payload(leave, &mut bytes),
+            Self::Chat(chat) => encode_payload(chat, &mut bytes),
+            Self::Heartbeat(heartbeat) => encode_payload(heartbeat, &mut bytes),
+            Self::Ping(ping) => encode_payload(ping, &mut bytes),
+            Self::Pong(pong) => encode_payload(pong, &mut bytes),
+            Self::Component(update) => encode_payload(update, &mut bytes),
+            Self::Map(map) => encode_payload(map, &mut bytes),
+        }
+
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        if bytes.len() < HEADER_LEN {
+            return Err(ProtocolError::Truncated);
+        }
+        if bytes[0] != PROTOCOL_VERSION {
+            return Err(ProtocolError::UnknownVersion(bytes[0]));
+        }
+        let kind = MessageKind::from_u8(bytes[1]).ok_or(ProtocolError::UnknownKind(bytes[1]))?;
+        let payload = &bytes[HEADER_LEN..];
+
+        Ok(match kind {
+            MessageKind::StateSync => Self::StateSync(compression::decode_state(payload)?),
+            MessageKind::Join => Self::Join(decode_payload(payload)?),
+            MessageKind::Leave => Self::Leave(decode_payload(payload)?),
+            MessageKind::Chat => Self::Chat(decode_payload(payload)?),
+            MessageKind::Heartbeat => Self::Heartbeat(decode_payload(payload)?),
+            MessageKind::StateDelta => Self::StateDelta(compression::decode_delta(payload)?),
+            MessageKind::Ping => Self::Ping(decode_payload(payload)?),
+            MessageKind::Pong => Self::Pong(decode_payload(payload)?),
+            MessageKind::Component => Self::Component(decode_payload(payload)?),
+            MessageKind::Map => Self::Map(decode_payload(payload)?),
+        })
+    }
+}
+
+/// Kind and player of a state update without decoding the rest, `None` for
+/// anything else. Both layouts lead with the player id (see `compression`)
+pub fn peek_state(bytes: &[u8]) -> Option<(MessageKind, i64)> {
+    if bytes.first() != Some(&PROTOCOL_VERSION) {
+        return None;
+    }
+    let kind = MessageKind::from_u8(*bytes.get(1)?)?;
+    if !matches!(kind, MessageKind::StateSync | MessageKind::StateDelta) {
+        return None;
+    }
+    let id = bytes.get(HEADER_LEN..HEADER_LEN + 8)?;
+    Some((kind, i64::from_le_bytes(id.try_into().ok()?)))
+}
+
+fn encode_payload<T: Serialize>(payload: &T, bytes: &mut Vec<u8>) {
+    // only fails for types serde can't describe, which none of ours are
+    bincode::serde::encode_into_std_write(payload, bytes, bincode::config::standard())
+        .expect("Failed to encode payload");
+}
+
+fn decode_payload<T: for<'de> Deserialize<'de>>(payload: &[u8]) -> Result<T, ProtocolError> {
+    let (value, read) =
+        bincode::serde::decode_from_slice(payload, bincode::config::standard()).map_err(
+            |e| match e {
+                bincode::error::DecodeError::UnexpectedEnd { .. } => ProtocolError::Truncated,
+                e => ProtocolError::Malformed(e.to_string()),
+            },
+        )?;
+
+    if read != payload.len() {
+        return Err(ProtocolError::Malformed(format!(
+            "{} trailing bytes",
+            payload.len() - read
+        )));
+    }
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(message: NetMessage) -> NetMessage {
+        let decoded = NetMessage::decode(&message.encode()).unwrap();
+        assert_eq!(decoded.kind(), message.kind());
+        decoded
+    }
+
+    #[test]
+    fn every_message_survives_a_round_trip() {
+        let exact = [
+            NetMessage::Join(JoinInfo {
+                id: 7,
+                name: String::from("Player7"),
+            }),
+            NetMessage::Leave(LeaveInfo { id: -3 }),
+            NetMessage::Chat(ChatMessage {
+                id: 7,
+                text: String::from("hello ✓"),
+            }),
+            NetMessage::Heartbeat(HeartbeatInfo {
+                id: i64::MAX,
+                out_of_range: true,
+            }),
+            NetMessage::Ping(PingInfo {
+                id: 7,
+                to: SERVER_ID,
+                seq: 42,
+                sent_at: 12.5,
+            }),
+            NetMessage::Pong(PongInfo {
+                id: SERVER_ID,
+                to: 7,
+                seq: 42,
+                sent_at: 12.5,
+                time: 99.25,
+            }),
+            NetMessage::Component(ComponentUpdate {
+                id: 7,
+                key: 0xdead_beef,
+                data: vec![0, 1, 2, 255],
+            }),
+            NetMessage::Map(MapInfo {
+                host: 7,
+                settings: MapSettings::default(),
+            }),
+        ];
+        for message in exact {
+            assert_eq!(round_trip(message.clone()), message);
+        }
+    }
+
+    // states are quantized on the way, but decoding them again changes nothing more
+    #[test]
+    fn state_messages_survive_a_round_trip() {
+        let sync = NetMessage::StateSync(Synchronizer {
+            id: 7,
+            pos: Vec3::new(1.0, 2.0, 3.0),
+            rot: Quat::from_rotation_y(0.5),
+            vel: Vec3::new(0.5, -9.0, 2.0),
+            speed: 3.5,
+            jump: true,
+            animation_playing: AnimationNodeIndex::new(3),
+            timestamp: 1.25,
+            input_seq: 300,
+            grounded: false,
+            keyframe: 9,
+        });
+        let delta = NetMessage::StateDelta(StateDelta {
+            id: 7,
+            timestamp: 1.25,
+            input_seq: 301,
+            base: 9,
+            pos: Some(Vec3::new(1.0, 2.5, 3.0)),
+            rot: None,
+            vel: None,
+            speed: Some(4.0),
+            animation_playing: None,
+            jump: false,
+            grounded: true,
+        });
+
+        for message in [sync, delta] {
+            let once = round_trip(message);
+            assert_eq!(round_trip(once.clone()), once);
+        }
+    }
+
+    #[test]
+    fn short_packets_are_truncated() {
+        assert_eq!(NetMessage::decode(&[]), Err(ProtocolError::Truncated));
+        assert_eq!(
+            NetMessage::decode(&[PROTOCOL_VERSION]),
+            Err(ProtocolError::Truncated)
+        );
+        // header but no payload
+        assert_eq!(
+            NetMessage::decode(&[PROTOCOL_VERSION, MessageKind::Join as u8]),
+            Err(ProtocolError::Truncated)
+        );
+    }
+
+    #[test]
+    fn wrong_version_and_kind_are_rejected() {
+        let mut bytes = NetMessage::Leave(LeaveInfo { id: 1 }).encode();
+        bytes[0] = PROTOCOL_VERSION + 1;
+        assert_eq!(
+            NetMessage::decode(&bytes),
+            Err(ProtocolError::UnknownVersion(PROTOCOL_VERSION + 1))
+        );
+
+        assert_eq!(
+            NetMessage::decode(&[PROTOCOL_VERSION, 0, 1, 2]),
+            Err(ProtocolError::UnknownKind(0))
+        );
+        assert_eq!(
+            NetMessage::decode(&[PROTOCOL_VERSION, 200]),
+            Err(ProtocolError::UnknownKind(200))
+        );
+    }
+
+    #[test]
+    fn garbage_payloads_are_malformed() {
+        // a name that isn't utf-8
+        let bytes = [PROTOCOL_VERSION, MessageKind::Join as u8, 2, 2, 0xff, 0xfe];
+        assert!(matches!(
+            NetMessage::decode(&bytes),
+            Err(ProtocolError::Malformed(_))
+        ));
+
+        let mut bytes = NetMessage::Leave(LeaveInfo { id: 1 }).encode();
+        bytes.push(0);
+        assert_eq!(
+            NetMessage::decode(&bytes),
+            Err(ProtocolError::Malformed(String::from("1 trailing bytes")))
+        );
+
+        let mut bytes = NetMessage::StateDelta(StateDelta {
+            id: 1,
+            timestamp: 0.0,
+            input_seq: 0,
+            base: 0,
+            pos: None,
+            rot: None,
+            vel: None,
+            speed: None,
+            animation_playing: None,
+            jump: false,
+            grounded: false,
+        })
+        .encode();
+        bytes.extend_from_slice(&[1, 2, 3]);
+        assert!(matches!(
+            NetMessage::decode(&bytes),
+            Err(ProtocolError::Malformed(_))
+        ));
+    }
+}
@@ -0,0 +1,370 @@
+//////////////////////////////////////////////////////////
+///////////////////////// queue //////////////////////////
+//////////////////////////////////////////////////////////
+//
+// The channels between the game and its socket. They hold a fixed number
+// of messages: a stalled socket or a frozen tab must not eat all memory.
+// Once full, the oldest state update goes first, those are replaced by the
+// next one anyway. Everything else (connects, joins, leaves, chat) is never
+// thrown out, with no state left to drop a new message is turned away
+// instead. Past half full, a state update replaces the one still waiting
+// for the same player instead of queueing up behind it.
+use super::protocol::{MessageKind, peek_state};
+use super::resource::WSMessages;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Condvar, Mutex};
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+use tokio::sync::Notify;
+use tokio::sync::mpsc::error::TryRecvError;
+
+pub const QUEUE_CAPACITY: usize = 512; // messages each way
+
+#[derive(Debug)]
+struct Queue {
+    messages: VecDeque<WSMessages>,
+    senders: usize,
+    receiver_alive: bool,
+}
+
+#[derive(Debug)]
+struct Shared {
+    queue: Mutex<Queue>,
+    capacity: usize,
+    // wakes an async receiver
+    notify: Notify,
+    // wakes a blocking one
+    condvar: Condvar,
+    dropped: AtomicU64,
+    coalesced: AtomicU64,
+}
+
+impl Shared {
+    fn wake(&self) {
+        self.notify.notify_one();
+        self.condvar.notify_all();
+    }
+}
+
+/// The receiving end is gone, the message is handed back
+pub struct Closed(pub WSMessages);
+
+impl fmt::Debug for Closed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Closed(..)")
+    }
+}
+
+#[derive(Debug)]
+pub struct Sender(Arc<Shared>);
+
+#[derive(Debug)]
+pub struct Receiver(Arc<Shared>);
+
+pub fn channel(capacity: usize) -> (Sender, Receiver) {
+    let shared = Arc::new(Shared {
+        queue: Mutex::new(Queue {
+            messages: VecDeque::new(),
+            senders: 1,
+            receiver_alive: true,
+        }),
+        capacity: capacity.max(1),
+        notify: Notify::new(),
+        condvar: Condvar::new(),
+        dropped: AtomicU64::new(0),
+        coalesced: AtomicU64::new(0),
+    });
+    (Sender(shared.clone()), Receiver(shared))
+}
+
+fn state_of(msg: &WSMessages) -> Option<(MessageKind, i64)> {
+    match msg {
+        WSMessages::Sync(bytes) => peek_state(bytes),
+        _ => None,
+    }
+}
+
+impl Sender {
+    /// Never waits, makes room by dropping old state instead
+    pub fn send(&self, msg: WSMessages) -> Result<(), Closed> {
+        let shared = &self.0;
+        let mut queue = shared.queue.lock().unwrap();
+        if !queue.receiver_alive {
+            return Err(Closed(msg));
+        }
+
+        let state = state_of(&msg);
+        let messages = &mut queue.messages;
+
+        // under pressure the newest state of a player is all that counts
+        if let Some((kind, id)) = state
+            && messages.len() >= shared.capacity / 2
+            && let Some(older) = messages
+                .iter_mut()
+                .rev()
+                .find(|queued| state_of(queued).is_some_and(|(_, other)| other == id))
+            && state_of(older).is_some_and(|(other_kind, _)| other_kind == kind)
+        {
+            *older = msg;
+            shared.coalesced.fetch_add(1, Ordering::Relaxed);
+            drop(queue);
+            shared.wake();
+            return Ok(());
+        }
+
+        if messages.len() >= shared.capacity {
+            shared.dropped.fetch_add(1, Ordering::Relaxed);
+            match messages
+                .iter()
+                .position(|queued| state_of(queued).is_some())
+            {
+                Some(oldest) => {
+                    messages.remove(oldest);
+                }
+                // nothing in there we can do without, so it's this one
+                None => return Ok(()),
+            }
+        }
+        messages.push_back(msg);
+        drop(queue);
+        shared.wake();
+        Ok(())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        !self.0.queue.lock().unwrap().receiver_alive
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.0.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn coalesced(&self) -> u64 {
+        self.0.coalesced.load(Ordering::Relaxed)
+    }
+}
+
+impl Clone for Sender {
+    fn clone(&self) -> Self {
+        self.0.queue.lock().unwrap().senders += 1;
+        Self(self.0.clone())
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        self.0.queue.lock().unwrap().senders -= 1;
+        self.0.wake();
+    }
+}
+
+impl Receiver {
+    pub fn try_recv(&mut self) -> Result<WSMessages, TryRecvError> {
+        let mut queue = self.0.queue.lock().unwrap();
+        match queue.messages.pop_front() {
+            Some(msg) => Ok(msg),
+            None if queue.senders == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    pub async fn recv(&mut self) -> Option<WSMessages> {
+        loop {
+            match self.try_recv() {
+                Ok(msg) => return Some(msg),
+                Err(TryRecvError::Disconnected) => return None,
+                // a wake-up between the check and here is kept as a permit
+                Err(TryRecvError::Empty) => self.0.notify.notified().await,
+            }
+        }
+    }
+
+    /// For plain threads, the browser doesn't have those
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn blocking_recv(&mut self) -> Option<WSMessages> {
+        let mut queue = self.0.queue.lock().unwrap();
+        loop {
+            if let Some(msg) = queue.messages.pop_front() {
+                return Some(msg);
+            }
+            if queue.senders == 0 {
+                return None;
+            }
+            queue = self.0.condvar.wait(queue).unwrap();
+        }
+    }
+
+    /// Like `blocking_recv`, but gives up at `deadline`
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn recv_until(&mut self, deadline: Instant) -> Result<WSMessages, RecvTimeoutError> {
+        let mut queue = self.0.queue.lock().unwrap();
+        loop {
+            if let Some(msg) = queue.messages.pop_front() {
+                return Ok(msg);
+            }
+            if queue.senders == 0 {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let Some(left) = deadline.checked_duration_since(Instant::now()) else {
+                return Err(RecvTimeoutError::Timeout);
+            };
+            queue = self.0.condvar.wait_timeout(queue, left).unwrap().0;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.queue.lock().unwrap().messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.0.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn coalesced(&self) -> u64 {
+        self.0.coalesced.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        self.0.queue.lock().unwrap().receiver_alive = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::network::protocol::{ChatMessage, NetMessage};
+    use crate::plugins::network::synchronizer::Synchronizer;
+    use std::time::Duration;
+
+    fn state(id: i64, keyframe: u16) -> WSMessages {
+        WSMessages::Sync(
+            NetMessage::StateSync(Synchronizer {
+                id,
+                keyframe,
+                ..Synchronizer::default()
+            })
+            .encode(),
+        )
+    }
+
+    fn chat(id: i64) -> WSMessages {
+        WSMessages::Sync(
+            NetMessage::Chat(ChatMessage {
+                id,
+                text: String::from("hi"),
+            })
+            .encode(),
+        )
+    }
+
+    fn drain(receiver: &mut Receiver) -> Vec<WSMessages> {
+        std::iter::from_fn(|| receiver.try_recv().ok()).collect()
+    }
+
+    fn bytes(msg: &WSMessages) -> &[u8] {
+        match msg {
+            WSMessages::Sync(bytes) => bytes,
+            _ => &[],
+        }
+    }
+
+    #[test]
+    fn newer_state_replaces_the_queued_one_past_half_full() {
+        let (sender, mut receiver) = channel(4);
+        sender.send(state(1, 1)).unwrap();
+        sender.send(state(2, 1)).unwrap();
+        // half full now, player 1 gets replaced in place
+        sender.send(state(1, 2)).unwrap();
+        assert_eq!(sender.coalesced(), 1);
+
+        let queued = drain(&mut receiver);
+        assert_eq!(queued.len(), 2);
+        assert_eq!(bytes(&queued[0]), bytes(&state(1, 2)));
+        assert_eq!(bytes(&queued[1]), bytes(&state(2, 1)));
+    }
+
+    #[test]
+    fn a_full_queue_drops_the_oldest_state_first() {
+        let (sender, mut receiver) = channel(3);
+        sender.send(WSMessages::Connected).unwrap();
+        sender.send(state(1, 1)).unwrap();
+        sender.send(chat(1)).unwrap();
+        sender.send(state(2, 1)).unwrap();
+        assert_eq!(sender.dropped(), 1);
+
+        let queued = drain(&mut receiver);
+        assert!(matches!(queued[0], WSMessages::Connected));
+        assert_eq!(bytes(&queued[1]), bytes(&chat(1)));
+        assert_eq!(bytes(&queued[2]), bytes(&state(2, 1)));
+    }
+
+    #[test]
+    fn control_messages_are_never_evicted() {
+        let (sender, mut receiver) = channel(2);
+        sender.send(WSMessages::Connected).unwrap();
+        sender.send(chat(1)).unwrap();
+        // no state to make room with, the newcomers are the ones counted as dropped
+        sender.send(state(1, 1)).unwrap();
+        sender
+            .send(WSMessages::Disconnected(String::from("bye")))
+            .unwrap();
+        assert_eq!(sender.dropped(), 2);
+
+        let queued = drain(&mut receiver);
+        assert_eq!(queued.len(), 2);
+        assert!(matches!(queued[0], WSMessages::Connected));
+        assert_eq!(bytes(&queued[1]), bytes(&chat(1)));
+    }
+
+    #[test]
+    fn closing_either_end_is_noticed() {
+        let (sender, receiver) = channel(4);
+        drop(receiver);
+        assert!(sender.is_closed());
+        assert!(matches!(
+            sender.send(WSMessages::Connected),
+            Err(Closed(WSMessages::Connected))
+        ));
+
+        let (sender, mut receiver) = channel(4);
+        sender.send(WSMessages::Offline).unwrap();
+        drop(sender);
+        // what was sent before still arrives
+        assert!(matches!(receiver.try_recv(), Ok(WSMessages::Offline)));
+        assert!(matches!(
+            receiver.try_recv(),
+            Err(TryRecvError::Disconnected)
+        ));
+    }
+
+    #[tokio::test]
+    async fn recv_wakes_up_for_a_message_and_for_the_last_sender_leaving() {
+        let (sender, mut receiver) = channel(4);
+        let waiting = tokio::spawn(async move {
+            let first = receiver.recv().await;
+            let second = receiver.recv().await;
+            (first, second)
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        sender.send(WSMessages::Connected).unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        drop(sender);
+
+        let (first, second) = tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .expect("recv never woke up")
+            .unwrap();
+        assert!(matches!(first, Some(WSMessages::Connected)));
+        assert!(second.is_none());
+    }
+}
@@ -0,0 +1,379 @@
+//////////////////////////////////////////////////////////
+///////////////////////// recording //////////////////////
+//////////////////////////////////////////////////////////
+//
+// Everything that goes through `WSMessageChannels`, saved with when it
+// happened so a session can be played back later without a socket. The
+// file is a short header followed by bincode entries back to back, a crash
+// halfway through an entry only loses that one.
+//
+// Every session gets a file of its own, later ones are numbered instead of
+// writing over the first. Playback runs on the game clock rather than a
+// thread of its own: a message comes out on the first frame past the time
+// it was recorded at, so it lines up in time with back then but not frame
+// for frame.
+use super::config::NetworkConfig;
+use super::protocol::{PROTOCOL_VERSION, RoomInfo};
+use super::queue::{self, QUEUE_CAPACITY, Receiver, Sender};
+use super::resource::{WSMessageChannels, WSMessages};
+use super::transport::Transport;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+const MAGIC: &[u8; 4] = b"BVNR";
+const MAX_SESSIONS: u32 = 1000; // numbered files we try before giving up
+
+////////////////////////////////////////////////////////
+//////////////////////// Define ////////////////////////
+////////////////////////////////////////////////////////
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Recorded {
+    // seconds since the session started
+    pub at: f64,
+    // from the others to us, the rest is what we sent
+    pub incoming: bool,
+    pub message: WSMessages,
+}
+
+////////////////////////////////////////////////////////
+/////////////////////// Recording //////////////////////
+////////////////////////////////////////////////////////
+type RecordFile = Arc<Mutex<BufWriter<File>>>;
+
+/// Writes a session to a file while it passes through
+pub struct Recorder {
+    file: RecordFile,
+    path: PathBuf,
+    started: Instant,
+}
+
+impl Recorder {
+    /// Records to `path`, or to `path` numbered if an earlier session is there already
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let (file, path) = create_unused(path.as_ref())?;
+        let mut file = BufWriter::new(file);
+        file.write_all(MAGIC)?;
+        file.write_all(&[PROTOCOL_VERSION])?;
+        file.flush()?;
+
+        Ok(Self {
+            file: Arc::new(Mutex::new(file)),
+            path,
+            started: Instant::now(),
+        })
+    }
+
+    /// Where this session ends up
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Puts the recorder between the game and `channels`, returns what the game should use
+    pub fn tap(self, channels: WSMessageChannels) -> WSMessageChannels {
+        let (to_others_tx, to_others_rx) = queue::channel(QUEUE_CAPACITY);
+        let (to_us_tx, to_us_rx) = queue::channel(QUEUE_CAPACITY);
+
+        spawn_tap(to_others_rx, channels.outgoing, false, &self);
+        spawn_tap(channels.incomming, to_us_tx, true, &self);
+
+        WSMessageChannels::new(to_us_rx, to_others_tx)
+    }
+}
+
+// game.rec, then game-2.rec, game-3.rec and so on
+fn create_unused(path: &Path) -> io::Result<(File, PathBuf)> {
+    for n in 1..=MAX_SESSIONS {
+        let candidate = match n {
+            1 => path.to_path_buf(),
+            n => numbered(path, n),
+        };
+        match File::create_new(&candidate) {
+            Ok(file) => return Ok((file, candidate)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("{} sessions recorded there already", MAX_SESSIONS),
+    ))
+}
+
+fn numbered(path: &Path, n: u32) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, n, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, n),
+    };
+    path.with_file_name(name)
+}
+
+// one direction on its own thread, like the conditioner's lanes
+fn spawn_tap(mut from: Receiver, to: Sender, incoming: bool, recorder: &Recorder) {
+    let file = recorder.file.clone();
+    let started = recorder.started;
+
+    std::thread::spawn(move || {
+        while let Some(message) = from.blocking_recv() {
+            let entry = Recorded {
+                at: started.elapsed().as_secs_f64(),
+                incoming,
+                message,
+            };
+            // flushed every time, a crashing game is what we want recorded most
+            let mut file = file.lock().unwrap();
+            let written = bincode::serde::encode_into_std_write(
+                &entry,
+                &mut *file,
+                bincode::config::standard(),
+            )
+            .map_err(|e| e.to_string())
+            .and_then(|_| file.flush().map_err(|e| e.to_string()));
+            if let Err(e) = written {
+                eprintln!("Failed to record message: {}", e);
+            }
+            drop(file);
+
+            if to.send(entry.message).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+////////////////////////////////////////////////////////
+/////////////////////// Playback ///////////////////////
+////////////////////////////////////////////////////////
+/// Plays a recorded session back instead of going online. What the others
+/// sent arrives when it did back then, whatever we send goes nowhere
+#[derive(Resource, Clone)]
+pub struct PlaybackTransport {
+    recording: Arc<Vec<Recorded>>,
+    session: Arc<Mutex<Option<Session>>>,
+}
+
+struct Session {
+    to_us: Sender,
+    to_others: Receiver,
+    // next entry of the recording to look at
+    next: usize,
+    // game clock on the first tick
+    started_at: Option<f64>,
+}
+
+impl PlaybackTransport {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    pub fn read(mut reader: impl Read) -> io::Result<Self> {
+        let mut header = [0; 5];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a network recording",
+            ));
+        }
+        if header[4] != PROTOCOL_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "recorded with protocol version {}, this build speaks {}",
+                    header[4], PROTOCOL_VERSION
+                ),
+            ));
+        }
+
+        let mut recording = Vec::new();
+        loop {
+            match bincode::serde::decode_from_std_read::<Recorded, _, _>(
+                &mut reader,
+                bincode::config::standard(),
+            ) {
+                Ok(entry) => recording.push(entry),
+                // the end, or an entry cut short by a crash
+                Err(bincode::error::DecodeError::UnexpectedEnd { .. }) => break,
+                Err(bincode::error::DecodeError::Io { inner, .. })
+                    if inner.kind() == io::ErrorKind::UnexpectedEof =>
+                {
+                    break;
+                }
+                Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+            }
+        }
+
+        Ok(Self {
+            recording: Arc::new(recording),
+            session: Arc::default(),
+        })
+    }
+
+    pub fn recording(&self) -> &[Recorded] {
+        &self.recording
+    }
+
+    /// Everything recorded has been played back
+    pub fn finished(&self) -> bool {
+        self.session
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|session| session.next >= self.recording.len())
+    }
+}
+
+impl Transport for PlaybackTransport {
+    fn connect(&self, config: &NetworkConfig) -> WSMessageChannels {
+        let (to_others_tx, to_others_rx) = queue::channel(QUEUE_CAPACITY);
+        let (to_us_tx, to_us_rx) = queue::channel(QUEUE_CAPACITY);
+
+        // whatever room gets picked, it's the recording
+        if config.list_rooms {
+            let rooms = [RoomInfo {
+                name: String::from("playback"),
+                players: 0,
+                locked: false,
+            }];
+            to_us_tx.send(WSMessages::Connected).ok();
+            if let Ok(json) = serde_json::to_string(&rooms) {
+                to_us_tx.send(WSMessages::Message(json)).ok();
+            }
+            return WSMessageChannels::new(to_us_rx, to_others_tx);
+        }
+
+        info!("Playing back {} recorded messages", self.recording.len());
+        *self.session.lock().unwrap() = Some(Session {
+            to_us: to_us_tx,
+            to_others: to_others_rx,
+            next: 0,
+            started_at: None,
+        });
+        WSMessageChannels::new(to_us_rx, to_others_tx)
+    }
+
+    fn tick(&self, now: f64) {
+        let mut session = self.session.lock().unwrap();
+        let Some(session) = session.as_mut() else {
+            return;
+        };
+        let elapsed = now - *session.started_at.get_or_insert(now);
+
+        while let Some(entry) = self.recording.get(session.next)
+            && entry.at <= elapsed
+        {
+            if entry.incoming {
+                session.to_us.send(entry.message.clone()).ok();
+            }
+            session.next += 1;
+        }
+        // nobody is listening, don't let it pile up
+        while session.to_others.try_recv().is_ok() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::network::protocol::{NetMessage, PingInfo, SERVER_ID};
+
+    fn entry(at: f64, incoming: bool, message: WSMessages) -> Recorded {
+        Recorded {
+            at,
+            incoming,
+            message,
+        }
+    }
+
+    fn fixture(entries: &[Recorded]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(PROTOCOL_VERSION);
+        for entry in entries {
+            bincode::serde::encode_into_std_write(entry, &mut bytes, bincode::config::standard())
+                .unwrap();
+        }
+        bytes
+    }
+
+    fn ping() -> WSMessages {
+        WSMessages::Sync(
+            NetMessage::Ping(PingInfo {
+                id: 3,
+                to: SERVER_ID,
+                seq: 1,
+                sent_at: 0.5,
+            })
+            .encode(),
+        )
+    }
+
+    #[test]
+    fn playback_hands_out_what_the_others_sent_when_they_sent_it() {
+        let bytes = fixture(&[
+            entry(0.0, true, WSMessages::Connected),
+            entry(0.1, false, ping()),
+            entry(0.5, true, WSMessages::Message(String::from("late"))),
+        ]);
+        let playback = PlaybackTransport::read(bytes.as_slice()).unwrap();
+        assert_eq!(playback.recording().len(), 3);
+
+        let mut channels = playback.connect(&NetworkConfig::default());
+        // the first tick starts the clock
+        playback.tick(10.0);
+        assert!(matches!(
+            channels.incomming.try_recv(),
+            Ok(WSMessages::Connected)
+        ));
+        // what we sent back then isn't played to us
+        playback.tick(10.2);
+        assert!(channels.incomming.try_recv().is_err());
+        assert!(!playback.finished());
+
+        playback.tick(10.5);
+        assert!(matches!(
+            channels.incomming.try_recv(),
+            Ok(WSMessages::Message(text)) if text == "late"
+        ));
+        assert!(playback.finished());
+    }
+
+    #[test]
+    fn an_entry_cut_short_is_left_out() {
+        let mut bytes = fixture(&[
+            entry(0.0, true, WSMessages::Connected),
+            entry(0.2, true, ping()),
+        ]);
+        bytes.truncate(bytes.len() - 3);
+        let playback = PlaybackTransport::read(bytes.as_slice()).unwrap();
+        assert_eq!(playback.recording().len(), 1);
+    }
+
+    #[test]
+    fn other_files_and_versions_are_refused() {
+        assert!(PlaybackTransport::read(&b"NOPE\x01"[..]).is_err());
+
+        let mut bytes = fixture(&[entry(0.0, true, WSMessages::Connected)]);
+        bytes[4] = PROTOCOL_VERSION.wrapping_add(1);
+        assert!(PlaybackTransport::read(bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn later_sessions_get_a_file_of_their_own() {
+        let dir = std::env::temp_dir().join(format!("bavytest-recording-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("game.rec");
+
+        let first = Recorder::create(&path).unwrap();
+        let second = Recorder::create(&path).unwrap();
+        assert_eq!(first.path(), path);
+        assert_eq!(second.path(), dir.join("game-2.rec"));
+        assert_eq!(numbered(&dir.join("game"), 3), dir.join("game-3"));
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
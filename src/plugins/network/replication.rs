@@ -0,0 +1,236 @@
+/////////////////////////////////////////////////////////////
+///////////////////////// replication ///////////////////////
+/////////////////////////////////////////////////////////////
+//
+// Anything serde can handle can be networked without touching the protocol:
+// register the component once with `app.replicate::<C>(name, rule)` and put
+// `Replicate` on the entities whose copy should go out. Values travel as
+// bincode under a key derived from the name, so both ends have to
+// register the same components under the same names.
+use super::protocol::{ComponentUpdate, NetMessage, ProtocolError};
+use super::resource::WSMessageChannels;
+use super::synchronizer::{SendKeyframe, Synchronizer};
+use crate::components::entities::DisplayName;
+use crate::components::vitals::{Health, Stamina};
+use bevy::ecs::system::EntityCommands;
+use bevy::prelude::*;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+
+////////////////////////////////////////////////////////
+//////////////////////// Define ////////////////////////
+////////////////////////////////////////////////////////
+/// Registered components on this entity are sent to the others
+#[derive(Component, Debug, Default)]
+pub struct Replicate;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Authority {
+    // the player it belongs to says what it is
+    Owner,
+    // only an authoritative server may change it, clients never send it
+    Server,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ReplicationRule {
+    pub authority: Authority,
+    // most updates per second, unchanged values aren't sent at all
+    pub send_rate: f64,
+}
+
+impl ReplicationRule {
+    pub fn owner(send_rate: f64) -> Self {
+        Self {
+            authority: Authority::Owner,
+            send_rate,
+        }
+    }
+
+    pub fn server(send_rate: f64) -> Self {
+        Self {
+            authority: Authority::Server,
+            send_rate,
+        }
+    }
+}
+
+/// One registered component, with its type erased so plain systems can handle all of them
+pub struct ReplicatedComponent {
+    pub name: &'static str,
+    pub rule: ReplicationRule,
+    // the component's current value, if the entity has one
+    pub encode: fn(&EntityRef) -> Option<Vec<u8>>,
+    pub apply: fn(&mut EntityCommands, &[u8]) -> Result<(), ProtocolError>,
+}
+
+#[derive(Resource, Default)]
+pub struct ReplicationRegistry {
+    components: HashMap<u32, ReplicatedComponent>,
+}
+
+impl ReplicationRegistry {
+    pub fn get(&self, key: u32) -> Option<&ReplicatedComponent> {
+        self.components.get(&key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &ReplicatedComponent)> {
+        self.components
+            .iter()
+            .map(|(key, component)| (*key, component))
+    }
+
+    fn register<C>(&mut self, name: &'static str, rule: ReplicationRule)
+    where
+        C: Component + Serialize + DeserializeOwned,
+    {
+        let key = component_key(name);
+        if let Some(existing) = self.components.get(&key) {
+            panic!(
+                "Replicated component '{}' clashes with '{}'",
+                name, existing.name
+            );
+        }
+
+        self.components.insert(
+            key,
+            ReplicatedComponent {
+                name,
+                rule,
+                encode: |entity| {
+                    let component = entity.get::<C>()?;
+                    bincode::serde::encode_to_vec(component, bincode::config::standard()).ok()
+                },
+                apply: |entity, bytes| {
+                    let (component, _): (C, _) =
+                        bincode::serde::decode_from_slice(bytes, bincode::config::standard())
+                            .map_err(|e| ProtocolError::Malformed(e.to_string()))?;
+                    entity.insert(component);
+                    Ok(())
+                },
+            },
+        );
+    }
+}
+
+/// FNV-1a of the name, stable across builds unlike `TypeId`
+pub fn component_key(name: &str) -> u32 {
+    name.bytes().fold(0x811c9dc5, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+pub trait ReplicationAppExt {
+    fn replicate<C>(&mut self, name: &'static str, rule: ReplicationRule) -> &mut Self
+    where
+        C: Component + Serialize + DeserializeOwned;
+}
+
+impl ReplicationAppExt for App {
+    fn replicate<C>(&mut self, name: &'static str, rule: ReplicationRule) -> &mut Self
+    where
+        C: Component + Serialize + DeserializeOwned,
+    {
+        self.init_resource::<ReplicationRegistry>();
+        self.world_mut()
+            .resource_mut::<ReplicationRegistry>()
+            .register::<C>(name, rule);
+        self
+    }
+}
+
+pub const DISPLAY_NAME: &str = "display_name";
+
+/// Everything the game replicates. Clients and the server both call this, so they agree on the keys
+pub fn register_replicated_components(app: &mut App) {
+    app.replicate::<Health>("health", ReplicationRule::owner(2.0))
+        .replicate::<Stamina>("stamina", ReplicationRule::owner(5.0))
+        // Join carries it too, this is for renames and whoever joins after us
+        .replicate::<DisplayName>(DISPLAY_NAME, ReplicationRule::owner(1.0));
+}
+
+////////////////////////////////////////////////////////
+//////////////////////// Systems ///////////////////////
+////////////////////////////////////////////////////////
+/// Last value of one component on one entity that went out
+#[derive(Default)]
+pub(crate) struct SentComponent {
+    bytes: Vec<u8>,
+    at: f64,
+}
+
+impl SentComponent {
+    // true when `bytes` should go out now, and remembers them as sent
+    pub(crate) fn should_send(
+        &mut self,
+        bytes: &[u8],
+        rule: &ReplicationRule,
+        now: f64,
+        force: bool,
+    ) -> bool {
+        let due = now - self.at >= 1.0 / rule.send_rate;
+        if !force && (!due || self.bytes == bytes) {
+            return false;
+        }
+        self.bytes = bytes.to_vec();
+        self.at = now;
+        true
+    }
+}
+
+pub(crate) fn send_replicated(
+    query: Query<(EntityRef, &Synchronizer), With<Replicate>>,
+    registry: Res<ReplicationRegistry>,
+    channels: Res<WSMessageChannels>,
+    time: Res<Time>,
+    mut requests: MessageReader<SendKeyframe>,
+    mut sent: Local<HashMap<(Entity, u32), SentComponent>>,
+) {
+    let now = time.elapsed_secs_f64();
+    // somebody new showed up, they need everything once
+    let requested = requests.read().count() > 0;
+
+    for (entity, synchronizer) in &query {
+        for (key, component) in registry.iter() {
+            if component.rule.authority != Authority::Owner {
+                continue;
+            }
+            let Some(bytes) = (component.encode)(&entity) else {
+                continue;
+            };
+
+            let last = sent.entry((entity.id(), key)).or_default();
+            if last.should_send(&bytes, &component.rule, now, requested) {
+                channels.send(&NetMessage::Component(ComponentUpdate {
+                    id: synchronizer.id,
+                    key,
+                    data: bytes,
+                }));
+            }
+        }
+    }
+}
+
+/// Puts a received value on `entity`, if whoever sent it was allowed to
+pub(crate) fn receive_replicated(
+    update: &ComponentUpdate,
+    entity: Entity,
+    is_local: bool,
+    registry: &ReplicationRegistry,
+    commands: &mut Commands,
+) {
+    let Some(component) = registry.get(update.key) else {
+        warn!("Ignoring unregistered component {:#x}", update.key);
+        return;
+    };
+
+    // nobody else gets to tell us what our own stats are
+    if is_local && component.rule.authority == Authority::Owner {
+        return;
+    }
+
+    if let Err(e) = (component.apply)(&mut commands.entity(entity), &update.data) {
+        eprintln!("Failed to apply replicated '{}': {}", component.name, e);
+    }
+}
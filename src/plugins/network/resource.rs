@@ -1,29 +1,130 @@
 /////////////////////////////////////////////////////////
 ///////////////////////// resource ////////////////////////
 /////////////////////////////////////////////////////////
-use std::collections::HashMap;
+use super::protocol::{NetMessage, SERVER_ID};
+use super::synchronizer::Synchronizer;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
 use bevy::prelude::*;
-use tokio::{sync::mpsc::{UnboundedReceiver as Receiver, UnboundedSender as Sender}};
+use serde::{Deserialize, Serialize};
+use super::queue::{Receiver, Sender};
 
 
-#[derive(Debug, Resource)]
+// serde only so sessions can be recorded, see `recording`
+#[derive(Debug, Clone, Resource, Serialize, Deserialize)]
 pub enum WSMessages {
     Message(String),
-    Connected(Vec<u8>),
-    Disconnected(Vec<u8>),
+    // the socket opened / closed, not other players
+    Connected,
+    Disconnected(String),
+    // stopped retrying, nothing more will come through
+    Offline,
     Sync(Vec<u8>),
 }
 
 
 #[derive(Resource, Debug)]
 pub struct WSMessageChannels {
-    pub incomming: Receiver<WSMessages>,
-    pub outgoing: Sender<WSMessages>
+    pub incomming: Receiver,
+    pub outgoing: Sender,
+    // counted here since sending only needs a shared borrow
+    bytes_sent: AtomicU64,
+}
+
+impl WSMessageChannels {
+    pub fn new(incomming: Receiver, outgoing: Sender) -> Self {
+        Self {
+            incomming,
+            outgoing,
+            bytes_sent: AtomicU64::new(0),
+        }
+    }
+
+    pub fn send(&self, msg: &NetMessage) {
+        let bytes = msg.encode();
+        self.bytes_sent.fetch_add(bytes.len() as u64, Ordering::Relaxed);
+        if let Err(e) = self.outgoing.send(WSMessages::Sync(bytes)) {
+            eprintln!("Failed to send {:?} message: {:?}", msg.kind(), e);
+        }
+    }
+
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent.load(Ordering::Relaxed)
+    }
+
+    /// State updates thrown away because a queue was full, both ways
+    pub fn dropped(&self) -> u64 {
+        self.incomming.dropped() + self.outgoing.dropped()
+    }
+
+    /// State updates replaced by a newer one before they went anywhere, both ways
+    pub fn coalesced(&self) -> u64 {
+        self.incomming.coalesced() + self.outgoing.coalesced()
+    }
+}
+
+
+/// How the connection is doing, filled in by pings. Times are seconds
+#[derive(Resource, Debug, Default)]
+pub struct NetworkStats {
+    // smoothed round trip
+    pub rtt: f64,
+    // how much the round trip wobbles around `rtt`
+    pub jitter: f64,
+    // share of pings nobody answered, 0..1
+    pub packet_loss: f32,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    // messages lost to full queues, and state replaced by newer state before it was read
+    pub dropped: u64,
+    pub coalesced: u64,
+    // messages left waiting after this frame's budget
+    pub backlog: usize,
+    // their clock minus ours, for everyone who answered (`SERVER_ID` for the server)
+    pub clock_offsets: HashMap<i64, f64>,
+    // smoothed round trip to each of them, on a relay that is everyone's ping
+    pub round_trips: HashMap<i64, f64>,
+    // pings still waiting for an answer, who they went to and when
+    pub(crate) pending: VecDeque<(u32, i64, f64)>,
+    pub(crate) next_ping: u32,
+}
+
+impl NetworkStats {
+    /// What `id`'s clock reads right now, by our estimate
+    pub fn remote_time(&self, id: i64, now: f64) -> Option<f64> {
+        self.clock_offsets.get(&id).map(|offset| now + offset)
+    }
+
+    /// The authoritative server's clock, which stamps everyone's state
+    pub fn server_time(&self, now: f64) -> Option<f64> {
+        self.remote_time(SERVER_ID, now)
+    }
 }
 
 
 #[derive(Resource, Debug, Default)]
 pub struct LobbyInfo {
-    pub players: HashMap<i64, Entity>
+    pub players: HashMap<i64, Entity>,
+    // elapsed seconds when we last heard from each player
+    pub last_seen: HashMap<i64, f32>,
+    // latest full state of each player, their deltas build on it
+    pub keyframes: HashMap<i64, Synchronizer>,
+}
+
+impl LobbyInfo {
+    pub fn seen(&mut self, id: i64, now: f32) {
+        self.last_seen.insert(id, now);
+    }
+
+    pub fn remove(&mut self, id: i64, commands: &mut Commands) {
+        if let Some(entity) = self.players.remove(&id) {
+            commands.entity(entity).despawn();
+        }
+        self.last_seen.remove(&id);
+        self.keyframes.remove(&id);
+    }
 }
 
+/// The other side turned us away for good, with the reason it gave
+#[derive(Resource, Debug, Clone)]
+pub struct Rejected(pub String);
@@ -0,0 +1,102 @@
+///////////////////////////////////////////////////////
+///////////////////////// rooms ///////////////////////
+///////////////////////////////////////////////////////
+//
+// The room list is a connection of its own: we dial in with `list_rooms`,
+// the other side answers with one json text frame and hangs up. Relays
+// that don't know about it never answer, which we give up on after a bit.
+use super::config::NetworkConfig;
+use super::protocol::RoomInfo;
+use super::resource::{WSMessageChannels, WSMessages};
+use super::transport::Transport;
+use bevy::prelude::*;
+
+const ROOM_LIST_TIMEOUT: f64 = 3.0;
+
+/// Asks for a fresh room list, the answer ends up in `RoomList`
+#[derive(Message, Debug, Default)]
+pub struct RefreshRooms;
+
+#[derive(Resource, Default)]
+pub struct RoomList {
+    pub rooms: Vec<RoomInfo>,
+    // why the last request failed, if it did
+    pub error: Option<String>,
+    // the request still waiting for an answer, and when it went out
+    request: Option<(WSMessageChannels, f64)>,
+}
+
+impl RoomList {
+    pub fn loading(&self) -> bool {
+        self.request.is_some()
+    }
+
+    // dropping the channels hangs up
+    pub(crate) fn cancel(&mut self) {
+        self.request = None;
+    }
+}
+
+pub(crate) fn request_room_list<T: Transport>(
+    mut refresh: MessageReader<RefreshRooms>,
+    mut list: ResMut<RoomList>,
+    transport: Res<T>,
+    config: Res<NetworkConfig>,
+    time: Res<Time>,
+) {
+    if refresh.read().count() == 0 {
+        return;
+    }
+    let channels = transport.connect(&config.room_list());
+    list.request = Some((channels, time.elapsed_secs_f64()));
+}
+
+pub(crate) fn receive_room_list(mut list: ResMut<RoomList>, time: Res<Time>) {
+    let Some((channels, sent_at)) = &mut list.request else {
+        return;
+    };
+
+    let mut answer = None;
+    while let Ok(msg) = channels.incomming.try_recv() {
+        match msg {
+            WSMessages::Message(text) => {
+                answer = Some(
+                    serde_json::from_str::<Vec<RoomInfo>>(&text)
+                        .map_err(|e| format!("Got a broken room list: {}", e)),
+                );
+                break;
+            }
+            WSMessages::Disconnected(reason) => {
+                answer = Some(Err(format!("Can't reach the server: {}", reason)));
+                break;
+            }
+            // somebody else's game traffic, this relay doesn't do room lists
+            WSMessages::Sync(_) | WSMessages::Connected | WSMessages::Offline => {}
+        }
+    }
+    if answer.is_none() && time.elapsed_secs_f64() - *sent_at > ROOM_LIST_TIMEOUT {
+        answer = Some(Err(String::from(
+            "This server doesn't list rooms, join one by name",
+        )));
+    }
+
+    let Some(answer) = answer else {
+        return;
+    };
+    list.cancel();
+    match answer {
+        Ok(rooms) => {
+            list.rooms = rooms;
+            list.error = None;
+        }
+        Err(e) => {
+            warn!("Room list: {}", e);
+            list.rooms.clear();
+            list.error = Some(e);
+        }
+    }
+}
+
+pub(crate) fn hang_up_room_list(mut list: ResMut<RoomList>) {
+    list.cancel();
+}
@@ -2,14 +2,25 @@
 //////////////////////// Synchronizer ////////////////////////
 //////////////////////////////////////////////////////////////
 use super::Recieved;
+use super::chat::ChatReceived;
+use super::connection::ConnectionState;
+use super::map_sync::MapReceived;
+use super::identity::PlayerIdentity;
+use super::interpolation::{Snapshot, SnapshotBuffer};
+use super::ping::answer_ping;
+use super::prediction::ServerCorrection;
+use super::presence::announce_join;
+use super::protocol::{NetMessage, REJECTION_PREFIX, StateDelta};
+use super::replication::{ReplicationRegistry, receive_replicated};
 use crate::plugins::GameLayer;
-use super::resource::{LobbyInfo, WSMessageChannels, WSMessages};
-use crate::components::entities::{LocalPlayer, PlayerBody};
+use super::resource::{LobbyInfo, NetworkStats, Rejected, WSMessageChannels, WSMessages};
+use crate::components::entities::{DisplayName, LocalPlayer, PlayerBody};
 use crate::components::vitals::Movement;
 use crate::plugins::player::GLTF_PATH;
 use crate::plugins::player::PLAYER_SCALE;
 use crate::plugins::player::bundle::SimplePlayerBundle;
 use avian3d::prelude::*;
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
 use rand;
 use serde::{Deserialize, Serialize};
@@ -18,15 +29,14 @@ use std::time::Duration;
 
 const THRESHOLD: f32 = 0.1;
 
-const POSITION_THRESHOLD: f32 = 0.5; // Start correcting at 0.5 units drift
-const TELEPORT_THRESHOLD: f32 = 5.0; // Teleport if more than 5 units off
-const CORRECTION_SPEED: f32 = 0.15; // How fast to lerp (0.0-1.0)
-const IDLE_UPDATE_TIME: f32 = 0.2; // Time between idle updates
+const IDLE_UPDATE_TIME: f32 = 0.2; // Time between idle updates (full keyframes)
+
+const MESSAGES_PER_FRAME: usize = 256; // incoming messages handled per frame at most
 
 ////////////////////////////////////////////////////////
 //////////////////////// Define ////////////////////////
 ////////////////////////////////////////////////////////
-#[derive(Component, Serialize, Deserialize, Debug, Clone)]
+#[derive(Component, Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Synchronizer {
     pub id: i64,
     pub pos: Vec3,
@@ -35,10 +45,17 @@ pub struct Synchronizer {
     pub speed: f32,
     pub jump: bool,
     pub animation_playing: AnimationNodeIndex,
+    // sender's clock in seconds, lets the receiver order and space out snapshots
+    pub timestamp: f64,
+    // last input the sender applied, the server echoes it back so we can reconcile
+    pub input_seq: u32,
+    pub grounded: bool,
+    // number of this full state, deltas say which one they build on
+    pub keyframe: u16,
 }
 
-impl Synchronizer {
-    pub fn default() -> Self {
+impl Default for Synchronizer {
+    fn default() -> Self {
         Synchronizer {
             id: rand::random::<i64>(),
             pos: Vec3::default(),
@@ -47,112 +64,132 @@ impl Synchronizer {
             speed: f32::default(),
             jump: false,
             animation_playing: AnimationNodeIndex::default(),
+            timestamp: 0.0,
+            input_seq: 0,
+            grounded: false,
+            keyframe: 0,
         }
     }
+}
 
+impl Synchronizer {
     fn sync(&self, channels: &WSMessageChannels) {
-        let msg = WSMessages::Sync(self.to_bytes());
-
-        if let Err(e) = channels.outgoing.send(msg) {
-            eprintln!("Failed to send sync message: {:?}", e);
-        }
+        channels.send(&NetMessage::StateSync(self.clone()));
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::with_capacity(53);
-        // id 8 bytes
-        bytes.extend_from_slice(&self.id.to_le_bytes());
-
-        // pos 12 bytes
-        bytes.extend_from_slice(&self.pos.x.to_le_bytes());
-        bytes.extend_from_slice(&self.pos.y.to_le_bytes());
-        bytes.extend_from_slice(&self.pos.z.to_le_bytes());
-
-        // rot 16 bytes
-        bytes.extend_from_slice(&self.rot.x.to_le_bytes());
-        bytes.extend_from_slice(&self.rot.y.to_le_bytes());
-        bytes.extend_from_slice(&self.rot.z.to_le_bytes());
-        bytes.extend_from_slice(&self.rot.w.to_le_bytes());
-
-        // vel 12 bytes
-        bytes.extend_from_slice(&self.vel.x.to_le_bytes());
-        bytes.extend_from_slice(&self.vel.y.to_le_bytes());
-        bytes.extend_from_slice(&self.vel.z.to_le_bytes());
-
-        // speed 4 bytes
-        bytes.extend_from_slice(&self.speed.to_le_bytes());
-
-        // jump 1 byte
-        bytes.push(self.jump as u8);
+    /// The fields that moved past `THRESHOLD` since keyframe `previous`, `None` if nothing did
+    pub fn delta_from(&self, previous: &Synchronizer) -> Option<StateDelta> {
+        let delta = StateDelta {
+            id: self.id,
+            timestamp: self.timestamp,
+            input_seq: self.input_seq,
+            base: previous.keyframe,
+            pos: (self.pos.distance(previous.pos) > THRESHOLD).then_some(self.pos),
+            rot: (self.rot.angle_between(previous.rot).abs() > THRESHOLD).then_some(self.rot),
+            vel: (self.vel.distance(previous.vel) > THRESHOLD).then_some(self.vel),
+            speed: (self.speed != previous.speed).then_some(self.speed),
+            animation_playing: (self.animation_playing != previous.animation_playing)
+                .then_some(self.animation_playing),
+            jump: self.jump,
+            grounded: self.grounded,
+        };
 
-        // animation 4 bytes
-        bytes.extend_from_slice(&self.animation_playing.index().to_le_bytes());
-
-        bytes
+        let changed = delta.pos.is_some()
+            || delta.rot.is_some()
+            || delta.vel.is_some()
+            || delta.speed.is_some()
+            || delta.animation_playing.is_some()
+            || delta.jump != previous.jump
+            || delta.grounded != previous.grounded;
+        changed.then_some(delta)
     }
 
-    fn from_bytes(bytes: &[u8]) -> Result<Self, &'static str> {
-        if bytes.len() < 36 {
-            return Err("Not enough bytes");
+    pub fn apply_delta(&mut self, delta: &StateDelta) {
+        self.timestamp = delta.timestamp;
+        self.input_seq = delta.input_seq;
+        self.jump = delta.jump;
+        self.grounded = delta.grounded;
+        if let Some(pos) = delta.pos {
+            self.pos = pos;
+        }
+        if let Some(rot) = delta.rot {
+            self.rot = rot;
+        }
+        if let Some(vel) = delta.vel {
+            self.vel = vel;
         }
+        if let Some(speed) = delta.speed {
+            self.speed = speed;
+        }
+        if let Some(animation_playing) = delta.animation_playing {
+            self.animation_playing = animation_playing;
+        }
+    }
 
-        Ok(Synchronizer {
-            id: i64::from_le_bytes(bytes[0..8].try_into().unwrap()),
-            pos: Vec3::new(
-                f32::from_le_bytes(bytes[8..12].try_into().unwrap()),
-                f32::from_le_bytes(bytes[12..16].try_into().unwrap()),
-                f32::from_le_bytes(bytes[16..20].try_into().unwrap()),
-            ),
-            rot: Quat::from_xyzw(
-                f32::from_le_bytes(bytes[20..24].try_into().unwrap()),
-                f32::from_le_bytes(bytes[24..28].try_into().unwrap()),
-                f32::from_le_bytes(bytes[28..32].try_into().unwrap()),
-                f32::from_le_bytes(bytes[32..36].try_into().unwrap()),
-            ),
-            vel: Vec3::new(
-                f32::from_le_bytes(bytes[36..40].try_into().unwrap()),
-                f32::from_le_bytes(bytes[40..44].try_into().unwrap()),
-                f32::from_le_bytes(bytes[44..48].try_into().unwrap()),
-            ),
-            speed: f32::from_le_bytes(bytes[48..52].try_into().unwrap()),
-            jump: bytes[48] != 0,
-            animation_playing: AnimationNodeIndex::new(u32::from_le_bytes(
-                bytes[53..57].try_into().unwrap(),
-            ) as usize),
-        })
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            time: self.timestamp,
+            pos: self.pos,
+            rot: self.rot,
+            vel: self.vel,
+        }
     }
 }
 
 ///////////////////////////////////////////////////////////
 ///////////////// Handle outgoing traffic /////////////////
 ///////////////////////////////////////////////////////////
+/// Asks the sender for a full state on its next tick, e.g. for a newcomer
+#[derive(Message, Debug, Default)]
+pub struct SendKeyframe;
+
+/// The last keyframe the others were sent, deltas are taken against it.
+/// Nobody acks keyframes, a relay has no way back to the sender, so a peer
+/// that lost this one drops every delta until the next keyframe arrives,
+/// `IDLE_UPDATE_TIME` at most
+#[derive(Default)]
+pub(crate) struct LastSent {
+    keyframe: Option<Synchronizer>,
+    keyframe_at: f32,
+}
+
+// runs on the network tick, not every frame
 pub(crate) fn multiplayer_sender(
     query: Query<(&Transform, &Movement, &LinearVelocity, &mut Synchronizer), Without<Recieved>>,
     body: Single<&Transform, (With<PlayerBody>, Without<Recieved>)>,
     channels: Res<WSMessageChannels>,
+    time: Res<Time>,
+    mut requests: MessageReader<SendKeyframe>,
+    mut last_sent: Local<LastSent>,
 ) {
-    for (transform, movement, velocity, mut syncronizer) in query {
-        let mut changed = false;
-
-        if syncronizer.pos.distance(transform.translation) > THRESHOLD {
-            syncronizer.pos = transform.translation;
-            changed = true;
-        }
-
-        if syncronizer.rot.angle_between(body.rotation).abs() > THRESHOLD {
-            syncronizer.rot = body.rotation;
-            changed = true;
-        }
-
-        if syncronizer.vel.distance(velocity.0) > THRESHOLD {
-            syncronizer.vel = velocity.0;
-            changed = true;
-        }
+    let now = time.elapsed_secs();
+    let requested = requests.read().count() > 0;
 
+    for (transform, movement, velocity, mut syncronizer) in query {
+        syncronizer.timestamp = time.elapsed_secs_f64();
+        syncronizer.pos = transform.translation;
+        syncronizer.rot = body.rotation;
+        syncronizer.vel = velocity.0;
         syncronizer.speed = movement.speed;
+        syncronizer.grounded = movement.is_grounded;
 
-        if changed {
-            syncronizer.sync(&channels);
+        // full state now and then, so idle players stay alive and lost deltas heal
+        let keyframe_due = requested || now - last_sent.keyframe_at >= IDLE_UPDATE_TIME;
+        match &last_sent.keyframe {
+            Some(keyframe) if !keyframe_due => {
+                // always against the keyframe, so a lost delta never spoils the next one
+                if let Some(delta) = syncronizer.delta_from(keyframe) {
+                    channels.send(&NetMessage::StateDelta(delta));
+                }
+            }
+            previous => {
+                syncronizer.keyframe = previous
+                    .as_ref()
+                    .map_or(0, |previous| previous.keyframe.wrapping_add(1));
+                syncronizer.sync(&channels);
+                last_sent.keyframe = Some(syncronizer.clone());
+                last_sent.keyframe_at = now;
+            }
         }
     }
 }
@@ -160,54 +197,141 @@ pub(crate) fn multiplayer_sender(
 ////////////////////////////////////////////////////////////
 ///////////////// Handle incomming traffic /////////////////
 ////////////////////////////////////////////////////////////
+/// Where `handle_sync` hands off what other systems take care of
+#[derive(SystemParam)]
+pub(crate) struct Forward<'w> {
+    corrections: MessageWriter<'w, ServerCorrection>,
+    keyframes: MessageWriter<'w, SendKeyframe>,
+    chat: MessageWriter<'w, ChatReceived>,
+    map: MessageWriter<'w, MapReceived>,
+}
+
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn handle_sync(
     mut channels: ResMut<WSMessageChannels>,
     mut lobby: ResMut<LobbyInfo>,
     mut commands: Commands,
-    mut query: Query<(&mut Transform, &mut LinearVelocity, &mut Synchronizer), With<Recieved>>,
+    mut query: Query<(&mut SnapshotBuffer, &mut Synchronizer, &mut Visibility), With<Recieved>>,
+    local: Query<(Entity, &Synchronizer), Without<Recieved>>,
     mut ap: Query<(&mut AnimationPlayer, &mut AnimationTransitions), Without<LocalPlayer>>,
     children_query: Query<&Children>,
     ass: Res<AssetServer>,
+    time: Res<Time>,
+    mut connection: ResMut<NextState<ConnectionState>>,
+    mut forward: Forward,
+    mut stats: ResMut<NetworkStats>,
+    registry: Res<ReplicationRegistry>,
+    identity: Res<PlayerIdentity>,
 ) {
-    while let Ok(msg) = channels.incomming.try_recv() {
-        match msg {
+    let (local_entity, local) = local.single().ok().unzip();
+    let now = time.elapsed_secs();
+    stats.bytes_out = channels.bytes_sent();
+    stats.dropped = channels.dropped();
+    stats.coalesced = channels.coalesced();
+
+    // a flood waits for the next frames instead of stalling this one
+    let mut budget = MESSAGES_PER_FRAME;
+    while budget > 0
+        && let Ok(msg) = channels.incomming.try_recv()
+    {
+        budget -= 1;
+        let inc_bytes = match msg {
             WSMessages::Sync(inc_bytes) => {
-                let Ok(inc_sync) = Synchronizer::from_bytes(&inc_bytes) else {
-                    eprintln!("Failed to decode sync message");
+                stats.bytes_in += inc_bytes.len() as u64;
+                inc_bytes
+            }
+            WSMessages::Connected => {
+                info!("Connected to multiplayer");
+                connection.set(ConnectionState::Connected);
+                // after a reconnect nobody knows about us anymore, say hello again
+                if let Some(local) = local {
+                    announce_join(&channels, local, &identity.name);
+                    forward.keyframes.write(SendKeyframe);
+                }
+                continue;
+            }
+            WSMessages::Disconnected(reason) => {
+                warn!("Disconnected from multiplayer: {}", reason);
+                if let Some(reason) = reason.strip_prefix(REJECTION_PREFIX) {
+                    commands.insert_resource(Rejected(reason.to_string()));
+                }
+                connection.set(ConnectionState::Reconnecting);
+                let ids: Vec<i64> = lobby.players.keys().copied().collect();
+                for id in ids {
+                    lobby.remove(id, &mut commands);
+                }
+                continue;
+            }
+            WSMessages::Offline => {
+                warn!("Giving up on multiplayer, playing offline");
+                connection.set(ConnectionState::Offline);
+                continue;
+            }
+            WSMessages::Message(_) => continue,
+        };
+
+        let inc = match NetMessage::decode(&inc_bytes) {
+            Ok(inc) => inc,
+            Err(e) => {
+                eprintln!("Failed to decode sync message: {}", e);
+                continue;
+            }
+        };
+
+        // fill a delta in on top of its keyframe, without one we wait for the next
+        let inc = match inc {
+            NetMessage::StateSync(inc_sync) => {
+                if local.is_none_or(|local| local.id != inc_sync.id) {
+                    lobby.keyframes.insert(inc_sync.id, inc_sync.clone());
+                }
+                NetMessage::StateSync(inc_sync)
+            }
+            NetMessage::StateDelta(delta) => {
+                let Some(mut inc_sync) = lobby
+                    .keyframes
+                    .get(&delta.id)
+                    .filter(|keyframe| keyframe.keyframe == delta.base)
+                    .cloned()
+                else {
                     continue;
                 };
+                inc_sync.apply_delta(&delta);
+                NetMessage::StateSync(inc_sync)
+            }
+            inc => inc,
+        };
+
+        match inc {
+            NetMessage::StateSync(inc_sync) => {
+                // only an authoritative server talks about us, relays never echo
+                if local.is_some_and(|local| local.id == inc_sync.id) {
+                    forward.corrections.write(ServerCorrection(inc_sync));
+                    continue;
+                }
+                lobby.seen(inc_sync.id, now);
 
                 // spawn if new
                 if let Vacant(e) = lobby.players.entry(inc_sync.id) {
-                    let entity = spawn_online_player(&inc_sync, &mut commands, &ass);
+                    let mut buffer = SnapshotBuffer::default();
+                    buffer.push(inc_sync.snapshot(), time.elapsed_secs_f64());
+                    let entity = spawn_online_player(&inc_sync, buffer, &mut commands, &ass);
                     e.insert(entity);
                 }
                 // update if exists
                 else if let Some(&entity) = lobby.players.get(&inc_sync.id)
-                    && let Ok((mut transform, mut velocity, mut synchronizer)) =
+                    && let Ok((mut buffer, mut synchronizer, mut visibility)) =
                         query.get_mut(entity)
                 {
-                    // Calculate position difference
-                    let position_error = inc_sync.pos.distance(transform.translation);
-
-                    // Position sync strategy
-                    if position_error > TELEPORT_THRESHOLD {
-                        // Large error: teleport immediately
-                        transform.translation = inc_sync.pos;
-                    } else if position_error > POSITION_THRESHOLD {
-                        // Small error: smooth lerp correction
-                        transform.translation =
-                            transform.translation.lerp(inc_sync.pos, CORRECTION_SPEED);
-                    }
-                    // else: position is close enough, rely on velocity
-
-                    // Always update rotation and velocity
-                    transform.rotation = inc_sync.rot;
-                    velocity.0 = inc_sync.vel;
+                    // back in range
+                    visibility.set_if_neq(Visibility::Inherited);
+                    // drawn later by interpolate_remote_players
+                    buffer.push(inc_sync.snapshot(), time.elapsed_secs_f64());
+                    let animation_changed =
+                        synchronizer.animation_playing != inc_sync.animation_playing;
+                    *synchronizer = inc_sync.clone();
 
                     // Update animation if changed
-                    if synchronizer.animation_playing != inc_sync.animation_playing {
-                        synchronizer.animation_playing = inc_sync.animation_playing;
+                    if animation_changed {
                         find_and_play_animation(
                             entity,
                             inc_sync.animation_playing,
@@ -218,11 +342,98 @@ pub(crate) fn handle_sync(
                 }
             }
 
-            WSMessages::Connected(_inc_sync) => {}
-            WSMessages::Disconnected(_inc_sync) => {}
-            _ => {}
+            NetMessage::Join(join) => {
+                if local.is_some_and(|local| local.id == join.id) {
+                    continue;
+                }
+                info!("{} joined ({})", join.name, join.id);
+                lobby.seen(join.id, now);
+
+                let entity = announced_player(join.id, &mut lobby, &mut commands, &ass);
+                commands.entity(entity).insert(DisplayName(join.name));
+
+                // let the newcomer know about us, a keyframe is enough to get spawned
+                forward.keyframes.write(SendKeyframe);
+            }
+
+            NetMessage::Leave(leave) => {
+                info!("Player {} left", leave.id);
+                lobby.remove(leave.id, &mut commands);
+            }
+
+            NetMessage::Heartbeat(heartbeat) => {
+                if let Some(&entity) = lobby.players.get(&heartbeat.id) {
+                    lobby.seen(heartbeat.id, now);
+                    // no more state is coming, better gone than frozen in place
+                    if heartbeat.out_of_range
+                        && let Ok((mut buffer, _, mut visibility)) = query.get_mut(entity)
+                    {
+                        buffer.clear();
+                        visibility.set_if_neq(Visibility::Hidden);
+                    }
+                }
+            }
+
+            NetMessage::Ping(ping) => {
+                if let Some(local) = local
+                    && local.id == ping.to
+                {
+                    answer_ping(&channels, local, ping, time.elapsed_secs_f64());
+                }
+            }
+
+            NetMessage::Pong(pong) => {
+                if local.is_some_and(|local| local.id == pong.to) {
+                    stats.record_pong(&pong, time.elapsed_secs_f64());
+                }
+            }
+
+            NetMessage::Component(update) => {
+                let is_local = local.is_some_and(|local| local.id == update.id);
+                // only players announced by a join or their state, anyone else is dropped
+                let entity = if is_local {
+                    local_entity
+                } else {
+                    let known = lobby.players.get(&update.id).copied();
+                    if known.is_some() {
+                        lobby.seen(update.id, now);
+                    }
+                    known
+                };
+                if let Some(entity) = entity {
+                    receive_replicated(&update, entity, is_local, &registry, &mut commands);
+                }
+            }
+
+            NetMessage::Chat(message) => {
+                if local.is_none_or(|local| local.id != message.id) {
+                    lobby.seen(message.id, now);
+                    forward.chat.write(ChatReceived(message));
+                }
+            }
+
+            NetMessage::Map(map) => {
+                forward.map.write(MapReceived(map));
+            }
+
+            // already turned into a StateSync above
+            NetMessage::StateDelta(_) => {}
         }
     }
+    stats.backlog = channels.incomming.len();
+}
+
+// a player we heard of before getting their state, they show up once it arrives
+fn announced_player(
+    id: i64,
+    lobby: &mut LobbyInfo,
+    commands: &mut Commands,
+    ass: &Res<AssetServer>,
+) -> Entity {
+    *lobby.players.entry(id).or_insert_with(|| {
+        let inc_sync = Synchronizer { id, ..default() };
+        spawn_online_player(&inc_sync, SnapshotBuffer::default(), commands, ass)
+    })
 }
 
 fn find_and_play_animation(
@@ -252,6 +463,7 @@ fn find_and_play_animation(
 
 fn spawn_online_player(
     inc: &Synchronizer,
+    buffer: SnapshotBuffer,
     commands: &mut Commands,
     ass: &Res<AssetServer>,
 ) -> Entity {
@@ -259,18 +471,13 @@ fn spawn_online_player(
         .spawn((
             Name::new("OnlinePlayer"),
             SimplePlayerBundle::new(),
-            Synchronizer {
-                id: inc.id,
-                pos: inc.pos,
-                rot: inc.rot,
-                vel: inc.vel,
-                speed: inc.speed,
-                jump: inc.jump,
-                animation_playing: inc.animation_playing,
-            },
+            inc.clone(),
+            buffer,
             Recieved,
             Visibility::default(),
         ))
+        // moved by interpolation, physics would only fight it
+        .insert(RigidBody::Kinematic)
         .with_children(|parent| {
             parent
                 .spawn((
@@ -0,0 +1,229 @@
+//////////////////////////////////////////////////////////
+///////////////////////// token //////////////////////////
+//////////////////////////////////////////////////////////
+//
+// Who a client is allowed to be on the authoritative server. The server's
+// operator hands out tokens (`bin/token.rs`), each one says which id and
+// name its holder plays as and until when, signed with a secret only the
+// server knows. The claims themselves are plain base64 json, anyone can read
+// them, nobody can change them without the signature breaking.
+//
+//     base64url(json claims) "." base64url(hmac-sha1(secret, first part))
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct JoinClaims {
+    pub id: i64,
+    pub name: String,
+    // unix seconds
+    pub expires: u64,
+}
+
+impl JoinClaims {
+    /// What a token claims, without checking it. Clients use this to play as
+    /// whoever the server will make them anyway
+    pub fn peek(token: &str) -> Option<Self> {
+        let (claims, _) = token.split_once('.')?;
+        let json = URL_SAFE_NO_PAD.decode(claims).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenError {
+    Missing,
+    Malformed,
+    BadSignature,
+    Expired,
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing => write!(f, "this server needs a join token"),
+            Self::Malformed => write!(f, "join token is malformed"),
+            Self::BadSignature => write!(f, "join token is not signed by this server"),
+            Self::Expired => write!(f, "join token has expired"),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////
+//////////////////////// Signing ///////////////////////
+////////////////////////////////////////////////////////
+// only the server and whoever issues its tokens hold the secret
+#[cfg(not(target_arch = "wasm32"))]
+pub fn issue(claims: &JoinClaims, secret: &[u8]) -> String {
+    let json = serde_json::to_vec(claims).expect("claims are always valid json");
+    let claims = URL_SAFE_NO_PAD.encode(json);
+    let signature = URL_SAFE_NO_PAD.encode(hmac_sha1(secret, claims.as_bytes()));
+    format!("{}.{}", claims, signature)
+}
+
+/// The claims of a token this server signed and that is still good at `now` (unix seconds)
+#[cfg(not(target_arch = "wasm32"))]
+pub fn verify(token: &str, secret: &[u8], now: u64) -> Result<JoinClaims, TokenError> {
+    if token.is_empty() {
+        return Err(TokenError::Missing);
+    }
+    let (claims, signature) = token.split_once('.').ok_or(TokenError::Malformed)?;
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_err(|_| TokenError::Malformed)?;
+
+    // checked before the claims are even parsed, nothing unsigned gets looked at
+    let expected = hmac_sha1(secret, claims.as_bytes());
+    if !same_bytes(&expected, &signature) {
+        return Err(TokenError::BadSignature);
+    }
+
+    let json = URL_SAFE_NO_PAD
+        .decode(claims)
+        .map_err(|_| TokenError::Malformed)?;
+    let claims: JoinClaims = serde_json::from_slice(&json).map_err(|_| TokenError::Malformed)?;
+    if claims.expires <= now {
+        return Err(TokenError::Expired);
+    }
+    Ok(claims)
+}
+
+/// Seconds since the unix epoch, what `expires` is measured in
+#[cfg(not(target_arch = "wasm32"))]
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+// RFC 2104, sha1 is what tungstenite already pulls in and is fine for a mac
+#[cfg(not(target_arch = "wasm32"))]
+fn hmac_sha1(key: &[u8], message: &[u8]) -> [u8; 20] {
+    use sha1::{Digest, Sha1};
+    const BLOCK: usize = 64;
+
+    let mut block = [0u8; BLOCK];
+    if key.len() > BLOCK {
+        block[..20].copy_from_slice(&Sha1::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let inner = Sha1::new()
+        .chain_update(block.map(|b| b ^ 0x36))
+        .chain_update(message)
+        .finalize();
+    Sha1::new()
+        .chain_update(block.map(|b| b ^ 0x5c))
+        .chain_update(inner)
+        .finalize()
+        .into()
+}
+
+// takes as long whatever the first wrong byte is, so guessing doesn't get easier
+#[cfg(not(target_arch = "wasm32"))]
+fn same_bytes(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"correct horse battery staple";
+    const NOW: u64 = 1_000_000;
+
+    fn hex(digest: &str) -> Vec<u8> {
+        (0..digest.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&digest[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn claims() -> JoinClaims {
+        JoinClaims {
+            id: 42,
+            name: String::from("Alice"),
+            expires: NOW + 60,
+        }
+    }
+
+    #[test]
+    fn hmac_matches_rfc_2202() {
+        let cases: [(&[u8], &[u8], &str); 5] = [
+            (
+                &[0x0b; 20],
+                b"Hi There",
+                "b617318655057264e28bc0b6fb378c8ef146be00",
+            ),
+            (
+                b"Jefe",
+                b"what do ya want for nothing?",
+                "effcdf6ae5eb2fa2d27416d5f184df9c259a7c79",
+            ),
+            (
+                &[0xaa; 20],
+                &[0xdd; 50],
+                "125d7342b9ac11cd91a39af48aa17b4f63f175d3",
+            ),
+            (
+                &[0xaa; 80],
+                b"Test Using Larger Than Block-Size Key - Hash Key First",
+                "aa4ae5e15272d00e95705637ce8a3b55ed402112",
+            ),
+            (
+                &[0xaa; 80],
+                b"Test Using Larger Than Block-Size Key and Larger Than One Block-Size Data",
+                "e8e99d0f45237d786d6bbaa7965c7808bbff1a91",
+            ),
+        ];
+        for (key, data, digest) in cases {
+            assert_eq!(hmac_sha1(key, data).to_vec(), hex(digest));
+        }
+    }
+
+    #[test]
+    fn a_good_token_gives_its_claims() {
+        let token = issue(&claims(), SECRET);
+        assert_eq!(verify(&token, SECRET, NOW), Ok(claims()));
+        assert_eq!(JoinClaims::peek(&token), Some(claims()));
+    }
+
+    #[test]
+    fn changed_claims_break_the_signature() {
+        let token = issue(&claims(), SECRET);
+        let (_, signature) = token.split_once('.').unwrap();
+        let forged = JoinClaims { id: 1, ..claims() };
+        let forged = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&forged).unwrap());
+        let token = format!("{}.{}", forged, signature);
+        assert_eq!(verify(&token, SECRET, NOW), Err(TokenError::BadSignature));
+    }
+
+    #[test]
+    fn a_changed_signature_or_secret_is_refused() {
+        let token = issue(&claims(), SECRET);
+        let (claims_part, signature) = token.split_once('.').unwrap();
+        let mut signature = URL_SAFE_NO_PAD.decode(signature).unwrap();
+        signature[0] ^= 1;
+        let tampered = format!("{}.{}", claims_part, URL_SAFE_NO_PAD.encode(signature));
+        assert_eq!(
+            verify(&tampered, SECRET, NOW),
+            Err(TokenError::BadSignature)
+        );
+        assert_eq!(
+            verify(&token, b"another secret", NOW),
+            Err(TokenError::BadSignature)
+        );
+    }
+
+    #[test]
+    fn expired_missing_and_malformed_tokens_are_refused() {
+        let token = issue(&claims(), SECRET);
+        assert_eq!(verify(&token, SECRET, NOW + 60), Err(TokenError::Expired));
+        assert_eq!(verify("", SECRET, NOW), Err(TokenError::Missing));
+        assert_eq!(verify("no-dot", SECRET, NOW), Err(TokenError::Malformed));
+        assert_eq!(verify("a.!!", SECRET, NOW), Err(TokenError::Malformed));
+    }
+}
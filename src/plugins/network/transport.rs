@@ -0,0 +1,186 @@
+///////////////////////////////////////////////////////////
+///////////////////////// transport ///////////////////////
+///////////////////////////////////////////////////////////
+//
+// Anything that can move bytes between us and the others. Gameplay code
+// only ever sees `WSMessageChannels`, how they are fed is up to the transport.
+use super::config::NetworkConfig;
+use super::connection::{Backoff, ConnectionState};
+use super::protocol::REJECTION_PREFIX;
+use super::queue::{Receiver, Sender};
+use super::resource::{LobbyInfo, NetworkStats, WSMessageChannels, WSMessages};
+use bevy::prelude::*;
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration;
+
+pub trait Transport: Resource + Clone {
+    /// Starts connecting in the background. The channels stay valid across
+    /// reconnects and the transport stops once `outgoing` is dropped.
+    fn connect(&self, config: &NetworkConfig) -> WSMessageChannels;
+
+    /// Called every frame while playing, for transports that run on the
+    /// game clock instead of a socket
+    fn tick(&self, _now: f64) {}
+}
+
+pub(crate) fn tick_transport<T: Transport>(transport: Res<T>, time: Res<Time>) {
+    transport.tick(time.elapsed_secs_f64());
+}
+
+pub(crate) fn connect_multiplayer<T: Transport>(
+    mut commands: Commands,
+    transport: Res<T>,
+    config: Res<NetworkConfig>,
+    mut connection: ResMut<NextState<ConnectionState>>,
+) {
+    let channels = transport.connect(&config);
+    #[cfg(not(target_arch = "wasm32"))]
+    let channels = if config.conditions.is_perfect() {
+        channels
+    } else {
+        info!("Simulating network conditions: {:?}", config.conditions);
+        super::conditioner::condition(channels, config.conditions)
+    };
+    #[cfg(not(target_arch = "wasm32"))]
+    let channels = match &config.record {
+        // the lobby asking for rooms is not a session worth a file
+        Some(path) if !config.list_rooms => match super::recording::Recorder::create(path) {
+            Ok(recorder) => {
+                info!("Recording network traffic to {}", recorder.path().display());
+                recorder.tap(channels)
+            }
+            Err(e) => {
+                warn!("Can't record to {}: {}", path, e);
+                channels
+            }
+        },
+        _ => channels,
+    };
+    commands.insert_resource(channels);
+    commands.init_resource::<LobbyInfo>();
+    commands.insert_resource(NetworkStats::default());
+    connection.set(ConnectionState::Connecting);
+}
+
+/////////////////////////////////////////////////////////
+//////////////////////// Sockets ////////////////////////
+/////////////////////////////////////////////////////////
+/// What a socket hands us, already stripped of backend specific framing
+pub(crate) enum Frame {
+    Data(Vec<u8>),
+    Text(String),
+    Closed(String),
+}
+
+enum SenderExit {
+    // the game dropped its channel, we are done for good
+    Left,
+    Failed(String),
+}
+
+/// Keeps a socket alive until we leave or run out of retries.
+/// `open` dials a fresh socket, `wait` sleeps with whatever timer the platform has.
+pub(crate) async fn run_connection<Open, OpenFut, Out, In, Wait, WaitFut>(
+    open: Open,
+    wait: Wait,
+    mut to_others: Receiver,
+    to_us: Sender,
+) where
+    Open: Fn() -> OpenFut,
+    OpenFut: Future<Output = Result<(Out, In), String>>,
+    Out: Sink<Vec<u8>> + Unpin,
+    Out::Error: Display,
+    In: Stream<Item = Frame> + Unpin,
+    Wait: Fn(Duration) -> WaitFut,
+    WaitFut: Future<Output = ()>,
+{
+    let mut backoff = Backoff::default();
+
+    loop {
+        match open().await {
+            Ok((sender, receiver)) => {
+                backoff.reset();
+                to_us.send(WSMessages::Connected).ok();
+
+                let reason = tokio::select! {
+                    left = pump_outgoing(sender, &mut to_others) => match left {
+                        SenderExit::Left => return,
+                        SenderExit::Failed(reason) => reason,
+                    },
+                    reason = pump_incomming(receiver, &to_us) => reason,
+                };
+                // turned away, asking again gets the same answer
+                let rejected = reason.starts_with(REJECTION_PREFIX);
+                to_us.send(WSMessages::Disconnected(reason)).ok();
+                if rejected {
+                    to_us.send(WSMessages::Offline).ok();
+                    return;
+                }
+            }
+            Err(e) => {
+                error!("Failed to connect to WebSocket: {}", e);
+                to_us.send(WSMessages::Disconnected(e)).ok();
+            }
+        }
+
+        let Some(delay) = backoff.next_delay() else {
+            to_us.send(WSMessages::Offline).ok();
+            return;
+        };
+        info!("Reconnecting in {:.1?} (attempt {})", delay, backoff.attempt());
+
+        // whatever the game queues while we are down is stale by the time we are back
+        let wait = wait(delay);
+        futures_util::pin_mut!(wait);
+        loop {
+            tokio::select! {
+                _ = &mut wait => break,
+                msg = to_others.recv() => if msg.is_none() {
+                    return;
+                },
+            }
+        }
+    }
+}
+
+async fn pump_outgoing<Out>(mut sender: Out, to_others: &mut Receiver) -> SenderExit
+where
+    Out: Sink<Vec<u8>> + Unpin,
+    Out::Error: Display,
+{
+    while let Some(msg) = to_others.recv().await {
+        // the game only ever queues packets, the rest is socket bookkeeping
+        let WSMessages::Sync(bytes) = msg else {
+            continue;
+        };
+        if let Err(e) = sender.send(bytes).await {
+            eprintln!("Failed to send message: {}", e);
+            return SenderExit::Failed(e.to_string());
+        }
+    }
+
+    // channels dropped: we left, say so properly instead of just vanishing
+    sender.close().await.ok();
+    SenderExit::Left
+}
+
+async fn pump_incomming<In>(mut receiver: In, to_us: &Sender) -> String
+where
+    In: Stream<Item = Frame> + Unpin,
+{
+    while let Some(frame) = receiver.next().await {
+        match frame {
+            Frame::Data(bytes) => {
+                to_us.send(WSMessages::Sync(bytes)).ok();
+            }
+            Frame::Text(text) => {
+                to_us.send(WSMessages::Message(text)).ok();
+            }
+            Frame::Closed(reason) => return reason,
+        }
+    }
+
+    String::from("connection closed")
+}
@@ -2,89 +2,77 @@
 ///////////////////////// wasm ////////////////////////
 ///////////////////////////////////////////////////////
 
-use super::resource::{WSMessages, WSMessageChannels, LobbyInfo};
+use super::config::NetworkConfig;
+use super::queue::{self, QUEUE_CAPACITY};
+use super::resource::WSMessageChannels;
+use super::transport::{Frame, Transport, run_connection};
 use bevy::prelude::*;
-use futures_util::{SinkExt, StreamExt};
-use std::collections::HashMap;
-use tokio::sync::mpsc::{self, UnboundedSender as Sender, UnboundedReceiver as Receiver};
-use tokio_tungstenite_wasm::WebSocketStream;
+use futures_util::{SinkExt, StreamExt, future};
+use std::time::Duration;
 use tokio_tungstenite_wasm::Message;
 
-const APP_ID: &str = "67";
-const URL: &str = "wss://broadcast.dogfetus.no";
-
 ///////////////////////////////////////////////////////////////
 //////////////////////// Initial setup ////////////////////////
 ///////////////////////////////////////////////////////////////
-pub fn connect_multiplayer(
-    mut commands: Commands,
-) {
-    let (to_others_tx, to_others_rx) = mpsc::unbounded_channel();
-    let (to_us_tx, to_us_rx) = mpsc::unbounded_channel();
- 
-    spawn_ws_tasks(to_others_rx, to_us_tx);
+#[derive(Resource, Clone, Default)]
+pub struct WebSocketTransport;
 
-    commands.insert_resource(WSMessageChannels {
-        incomming: to_us_rx,
-        outgoing: to_others_tx,
-    });
- 
-    commands.init_resource::<LobbyInfo>();
+impl Transport for WebSocketTransport {
+    fn connect(&self, config: &NetworkConfig) -> WSMessageChannels {
+        let (to_others_tx, to_others_rx) = queue::channel(QUEUE_CAPACITY);
+        let (to_us_tx, to_us_rx) = queue::channel(QUEUE_CAPACITY);
+
+        let url = config.url_with_query();
+        let open = move || open_socket(url.clone());
+        wasm_bindgen_futures::spawn_local(run_connection(open, sleep, to_others_rx, to_us_tx));
+
+        WSMessageChannels::new(to_us_rx, to_others_tx)
+    }
 }
 
-fn spawn_ws_tasks(
-    to_others: Receiver<WSMessages>,
-    to_us: Sender<WSMessages>,
-) {
-    wasm_bindgen_futures::spawn_local(async move {
-        let url_with_app_id = format!("{}?app_id={}", URL, APP_ID);
-        let ws = tokio_tungstenite_wasm::connect(url_with_app_id);
+async fn open_socket(
+    url: String,
+) -> Result<
+    (
+        impl futures_util::Sink<Vec<u8>, Error = tokio_tungstenite_wasm::Error> + Unpin,
+        impl futures_util::Stream<Item = Frame> + Unpin,
+    ),
+    String,
+> {
+    let socket = tokio_tungstenite_wasm::connect(url)
+        .await
+        .map_err(|e| e.to_string())?;
+    let (sender, receiver) = socket.split();
 
-        match ws.await {
-            Ok(socket) => {
-                // info!("WebSocket connected successfully");
-                let (sender, receiver) = socket.split();
-                wasm_bindgen_futures::spawn_local(ws_sender(sender, to_others));
-                wasm_bindgen_futures::spawn_local(ws_receiver(receiver, to_us));
-            }
+    let sender = sender.with(|bytes: Vec<u8>| future::ready(Ok(Message::Binary(bytes.into()))));
+    let receiver = receiver.filter_map(|message| {
+        future::ready(match message {
+            Ok(Message::Binary(bytes)) => Some(Frame::Data(bytes.to_vec())),
+            Ok(Message::Text(text)) => Some(Frame::Text(text.to_string())),
+            Ok(Message::Close(frame)) => Some(Frame::Closed(
+                frame.map_or_else(|| "connection closed".to_string(), |f| f.reason.to_string()),
+            )),
             Err(e) => {
-                error!("Failed to connect to WebSocket: {:?}", e);
+                eprintln!("WebSocket error: {:?}", e);
+                Some(Frame::Closed(e.to_string()))
             }
-        }
+        })
     });
-}
 
-async fn ws_sender(
-    mut ws_sender: futures_util::stream::SplitSink<WebSocketStream, Message>,
-    mut to_others: Receiver<WSMessages>,
-) {
-    while let Some(msg) = to_others.recv().await {
-        let to_send = match msg {
-            WSMessages::Sync(s) => {
-                Message::Binary(s.into())
-            }
-            _ => Message::Text("hei".into()),
-        };
-        if let Err(e) = ws_sender.send(to_send).await {
-            eprintln!("Failed to send message: {:?}", e);
-            break;
-        }
-    }
+    Ok((sender, receiver))
 }
 
-async fn ws_receiver(
-    mut ws_receiver: futures_util::stream::SplitStream<WebSocketStream>,
-    to_us: Sender<WSMessages>,
-) {
-    while let Some(message) = ws_receiver.next().await {
-        match message {
-            Ok(msg) => {
-                let bytes = msg.into_data();
-                to_us.send(WSMessages::Sync(bytes.to_vec())).ok();
-            }
-            Err(e) => {
-                eprintln!("WebSocket error: {:?}", e);
-            }
-        };
-    }
+// no tokio timers in the browser, borrow setTimeout instead
+async fn sleep(delay: Duration) {
+    let promise = js_sys::Promise::new(&mut |resolve, _reject| {
+        if let Some(window) = web_sys::window() {
+            window
+                .set_timeout_with_callback_and_timeout_and_arguments_0(
+                    &resolve,
+                    delay.as_millis() as i32,
+                )
+                .ok();
+        }
+    });
+    wasm_bindgen_futures::JsFuture::from(promise).await.ok();
 }
@@ -3,7 +3,7 @@
 /////////////////////////////////
 use bevy::prelude::*;
 use avian3d::prelude::*;
-use crate::components::vitals::Movement;
+use crate::components::vitals::{Health, Movement, Stamina};
 
 
 /////////////////////////////////////////////////
@@ -63,6 +63,8 @@ pub struct SimplePlayerBundle {
     // friction: Friction,
     locked_axes: LockedAxes,
     movement: Movement,
+    health: Health,
+    stamina: Stamina,
 }
 
 impl SimplePlayerBundle {
@@ -82,6 +84,20 @@ impl SimplePlayerBundle {
                 extra_jumps: 2,
                 current_jumps: 0,
             },
+            health: Health {
+                current: 100.0,
+                max: 100.0,
+            },
+            stamina: Stamina {
+                current: 100.0,
+                max: 100.0,
+            },
         }
     }
 }
+
+impl Default for SimplePlayerBundle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
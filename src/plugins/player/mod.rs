@@ -7,6 +7,7 @@ pub mod camera;
 
 use std::time::Duration;
 
+use crate::components::entities::DisplayName;
 use crate::components::entities::LocalPlayer;
 use crate::components::entities::Player;
 use crate::components::entities::PlayerAnimation;
@@ -16,6 +17,9 @@ use crate::components::vitals::Movement;
 use crate::plugins::GameLayer;
 use crate::plugins::menu::{GameState, HasPlayed};
 use crate::plugins::network::Recieved;
+use crate::plugins::network::identity::PlayerIdentity;
+use crate::plugins::network::prediction::PredictionHistory;
+use crate::plugins::network::replication::Replicate;
 use crate::plugins::network::synchronizer::Synchronizer;
 use animation::animate_player_meshes;
 use animation::load_animation;
@@ -45,12 +49,24 @@ impl Plugin for PlayerPlugin {
 /////////////////////////////////
 //////////// Startup ////////////
 /////////////////////////////////
-fn spawn_player(mut commands: Commands, ass: Res<AssetServer>) {
+fn spawn_player(
+    mut commands: Commands,
+    ass: Res<AssetServer>,
+    identity: Option<Res<PlayerIdentity>>,
+) {
     commands.insert_resource(HasPlayed);
-    commands
+    // offline there is no identity, a random id and no name will do
+    let mut synchronizer = Synchronizer::default();
+    if let Some(identity) = &identity {
+        synchronizer.id = identity.id;
+    }
+
+    let player = commands
         .spawn((
             Name::new("LocalPlayer"),
-            Synchronizer::default(),
+            synchronizer,
+            PredictionHistory::default(),
+            Replicate,
             SimplePlayerBundle::new(),
             Player,
             Visibility::default(),
@@ -86,7 +102,14 @@ fn spawn_player(mut commands: Commands, ass: Res<AssetServer>) {
                 Camera3d::default(),
                 Transform::from_xyz(0.0, 2.0, 5.0).looking_at(Vec3::ZERO, Vec3::Y),
             ));
-        });
+        })
+        .id();
+
+    if let Some(identity) = identity {
+        commands
+            .entity(player)
+            .insert(DisplayName(identity.name.clone()));
+    }
 }
 
 ///////////////////////////////////////////
@@ -115,6 +138,7 @@ type PlayerQuery<'w, 's> = Single<
         &'static mut LinearVelocity,
         &'static mut Movement,
         &'static mut Synchronizer,
+        &'static mut PredictionHistory,
     ),
     With<Player>,
 >;
@@ -130,7 +154,8 @@ fn move_player(
     mut current_animation: Local<AnimationNodeIndex>,
     animations: Res<animation::PlayerAnimations>,
 ) {
-    let (mut transform, mut velocity, mut player, mut syncronizer) = player.into_inner();
+    let (mut transform, mut velocity, mut player, mut syncronizer, mut history) =
+        player.into_inner();
     for (mut a_player, mut transitions) in &mut local_ap {
         let mut speed = player.speed;
         let mut direction = Vec3::ZERO;
@@ -146,7 +171,8 @@ fn move_player(
             velocity.y = 0.0;
         }
 
-        if keyboard.any_just_pressed([KeyCode::Space]) && player.can_jump() {
+        let jumped = keyboard.any_just_pressed([KeyCode::Space]) && player.can_jump();
+        if jumped {
             player.current_jumps += !player.is_grounded as u32;
             velocity.y = player.jump_strength;
             player.is_grounded = false;
@@ -178,6 +204,9 @@ fn move_player(
         velocity.x = direction.x * speed * time.delta_secs();
         velocity.z = direction.z * speed * time.delta_secs();
 
+        // predicted locally, the server gets the final say on this input later
+        syncronizer.input_seq = history.record(velocity.0, jumped, transform.translation);
+
         rotate_body_by_movement(&mut body, direction, &time);
 
         if direction.length_squared() > 0.0 {
@@ -0,0 +1,159 @@
+//////////////////////////////////////////////////////////////
+///////////////////////// interest ///////////////////////////
+//////////////////////////////////////////////////////////////
+//
+// Nobody needs to hear about players on the other side of the map every
+// tick. Players are bucketed into grid cells so finding who is close is
+// cheap, close ones go out every tick, those further out every few ticks
+// and anyone past the radius not at all, just a heartbeat now and then so
+// they don't time out.
+use bevy::prelude::*;
+use std::collections::HashMap;
+
+pub const DEFAULT_INTEREST_RADIUS: f32 = 40.0;
+const FULL_RATE_SHARE: f32 = 0.5; // inside this share of the radius, every tick
+const FAR_INTERVAL: u64 = 3; // ticks between updates further out than that
+
+/// How much a client cares about another player
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Relevance {
+    // every tick
+    Near,
+    // every `FAR_INTERVAL` ticks
+    Far,
+    // past the radius, heartbeats only
+    Hidden,
+}
+
+impl Relevance {
+    pub fn of(distance: f32, radius: f32) -> Self {
+        if distance <= radius * FULL_RATE_SHARE {
+            Self::Near
+        } else if distance <= radius {
+            Self::Far
+        } else {
+            Self::Hidden
+        }
+    }
+
+    /// Whether an update goes out this tick, `phase` spreads the far ones over ticks
+    pub fn due(self, tick: u64, phase: u64) -> bool {
+        match self {
+            Self::Near => true,
+            Self::Far => (tick + phase).is_multiple_of(FAR_INTERVAL),
+            Self::Hidden => false,
+        }
+    }
+}
+
+/// Players by grid cell, cells are as wide as the interest radius so
+/// everyone relevant is in the 3x3 cells around the client
+#[derive(Resource, Debug, Default)]
+pub struct InterestGrid {
+    cell_size: f32,
+    cells: HashMap<IVec2, Vec<(Entity, Vec3)>>,
+}
+
+impl InterestGrid {
+    pub fn rebuild(&mut self, radius: f32, players: impl Iterator<Item = (Entity, Vec3)>) {
+        self.cell_size = radius.max(1.0);
+        self.cells.clear();
+        for (entity, pos) in players {
+            let cell = self.cell(pos);
+            self.cells.entry(cell).or_default().push((entity, pos));
+        }
+    }
+
+    fn cell(&self, pos: Vec3) -> IVec2 {
+        IVec2::new(
+            (pos.x / self.cell_size).floor() as i32,
+            (pos.z / self.cell_size).floor() as i32,
+        )
+    }
+
+    /// Everyone in the cells around `pos`, with how far away they are
+    pub fn around(&self, pos: Vec3) -> impl Iterator<Item = (Entity, f32)> + '_ {
+        let center = self.cell(pos);
+        (-1..=1)
+            .flat_map(move |x| (-1..=1).map(move |z| center + IVec2::new(x, z)))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .map(move |(entity, other)| (*entity, other.distance(pos)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RADIUS: f32 = 40.0;
+
+    fn entities(n: usize) -> Vec<Entity> {
+        let mut world = World::new();
+        (0..n).map(|_| world.spawn_empty().id()).collect()
+    }
+
+    #[test]
+    fn distance_picks_the_relevance() {
+        assert_eq!(Relevance::of(0.0, RADIUS), Relevance::Near);
+        assert_eq!(
+            Relevance::of(RADIUS * FULL_RATE_SHARE, RADIUS),
+            Relevance::Near
+        );
+        assert_eq!(Relevance::of(RADIUS * 0.75, RADIUS), Relevance::Far);
+        assert_eq!(Relevance::of(RADIUS, RADIUS), Relevance::Far);
+        assert_eq!(Relevance::of(RADIUS + 0.1, RADIUS), Relevance::Hidden);
+    }
+
+    #[test]
+    fn far_players_go_out_every_few_ticks() {
+        let ticks = 0..FAR_INTERVAL * 4;
+        let due = |relevance: Relevance, phase| {
+            ticks
+                .clone()
+                .filter(|tick| relevance.due(*tick, phase))
+                .count() as u64
+        };
+        assert_eq!(due(Relevance::Near, 0), FAR_INTERVAL * 4);
+        assert_eq!(due(Relevance::Far, 0), 4);
+        assert_eq!(due(Relevance::Far, 1), 4);
+        assert_eq!(due(Relevance::Hidden, 0), 0);
+
+        // spread out, not all on the same tick
+        assert_ne!(Relevance::Far.due(0, 0), Relevance::Far.due(0, 1),);
+    }
+
+    #[test]
+    fn neighbours_across_a_cell_border_are_found() {
+        let [me, neighbour] = entities(2)[..] else {
+            unreachable!()
+        };
+        let mut grid = InterestGrid::default();
+        // either side of the border at x = RADIUS
+        let here = Vec3::new(RADIUS - 1.0, 0.0, 5.0);
+        let there = Vec3::new(RADIUS + 1.0, 0.0, 5.0);
+        grid.rebuild(RADIUS, [(me, here), (neighbour, there)].into_iter());
+
+        let found: Vec<_> = grid.around(here).collect();
+        assert_eq!(found.len(), 2);
+        let (_, distance) = found
+            .iter()
+            .find(|(entity, _)| *entity == neighbour)
+            .unwrap();
+        assert!((distance - 2.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn players_two_cells_away_are_left_out() {
+        let [me, far_away] = entities(2)[..] else {
+            unreachable!()
+        };
+        let mut grid = InterestGrid::default();
+        let here = Vec3::new(5.0, 0.0, 5.0);
+        let there = Vec3::new(5.0 + RADIUS * 2.0, 0.0, 5.0);
+        grid.rebuild(RADIUS, [(me, here), (far_away, there)].into_iter());
+
+        let found: Vec<_> = grid.around(here).map(|(entity, _)| entity).collect();
+        assert_eq!(found, [me]);
+    }
+}
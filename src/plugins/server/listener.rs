@@ -0,0 +1,236 @@
+/////////////////////////////////////////////////////////
+///////////////////////// listener ////////////////////////
+/////////////////////////////////////////////////////////
+use super::resource::{BanList, Banned, ClientId, ServerChannels, ServerConfig, ServerEvent};
+use crate::plugins::network::config::DEFAULT_ROOM;
+use crate::plugins::network::native::MultiplayerRuntime;
+use crate::plugins::network::protocol::{REJECTION_PREFIX, RoomInfo};
+use crate::plugins::network::resource::WSMessages;
+use crate::plugins::network::token;
+use crate::relay::param_of;
+use bevy::prelude::*;
+use futures_util::{SinkExt, StreamExt};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{self, UnboundedSender as Sender};
+use tokio_tungstenite::{
+    accept_hdr_async,
+    tungstenite::Message,
+    tungstenite::handshake::server::{Request, Response},
+    tungstenite::protocol::{CloseFrame, frame::coding::CloseCode},
+};
+
+// everyone shares one world here, so that is the only room there is
+const ROOM_NAME: &str = "server";
+
+///////////////////////////////////////////////////////////////
+//////////////////////// Initial setup ////////////////////////
+///////////////////////////////////////////////////////////////
+pub(crate) fn start_server(
+    mut commands: Commands,
+    mp_runtime: Res<MultiplayerRuntime>,
+    config: Res<ServerConfig>,
+    bans: Res<BanList>,
+) {
+    let (events_tx, events_rx) = mpsc::unbounded_channel();
+
+    let listener = mp_runtime
+        .0
+        .block_on(TcpListener::bind(&config.bind))
+        .expect("Failed to bind server address");
+    info!("Server listening on {}", config.bind);
+
+    let secret = config.token_secret.as_deref().map(Arc::<str>::from);
+    if secret.is_some() {
+        info!("Clients need a join token");
+    }
+    mp_runtime
+        .0
+        .spawn(accept_clients(listener, events_tx, secret, bans.clone()));
+
+    commands.insert_resource(ServerChannels {
+        incomming: events_rx,
+    });
+}
+
+async fn accept_clients(
+    listener: TcpListener,
+    events: Sender<ServerEvent>,
+    secret: Option<Arc<str>>,
+    bans: BanList,
+) {
+    let mut next_client: ClientId = 0;
+    let players = Arc::new(AtomicUsize::new(0));
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                next_client += 1;
+                info!("Client {} connecting from {}", next_client, addr);
+                tokio::spawn(handle_client(
+                    stream,
+                    next_client,
+                    addr.ip(),
+                    events.clone(),
+                    players.clone(),
+                    secret.clone(),
+                    bans.clone(),
+                ));
+            }
+            Err(e) => {
+                error!("Failed to accept connection: {:?}", e);
+            }
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////
+//////////////////////// Per client ///////////////////////////
+///////////////////////////////////////////////////////////////
+// a room or password meant for a relay would only land in the same world unasked,
+// clients that never picked a room get the only one
+fn check_room(room: &str, password: &str) -> Result<(), String> {
+    if !room.is_empty() && room != ROOM_NAME && room != DEFAULT_ROOM {
+        return Err(format!("no room '{}' here, only '{}'", room, ROOM_NAME));
+    }
+    if !password.is_empty() {
+        return Err(String::from("rooms here have no password"));
+    }
+    Ok(())
+}
+
+fn check_ban(bans: &BanList, addr: IpAddr) -> Result<(), String> {
+    if bans.is_banned(Banned::Address(addr)) {
+        return Err(String::from("kicked for cheating, try again later"));
+    }
+    Ok(())
+}
+
+// the handshake callback signature is dictated by tungstenite
+#[allow(clippy::result_large_err)]
+async fn handle_client(
+    stream: TcpStream,
+    client: ClientId,
+    addr: IpAddr,
+    events: Sender<ServerEvent>,
+    players: Arc<AtomicUsize>,
+    secret: Option<Arc<str>>,
+    bans: BanList,
+) {
+    let mut list_rooms = false;
+    let mut token = String::new();
+    let mut room = String::new();
+    let mut password = String::new();
+    let socket = accept_hdr_async(stream, |request: &Request, response: Response| {
+        list_rooms = !param_of(request, "list_rooms").is_empty();
+        token = param_of(request, "token");
+        room = param_of(request, "room");
+        password = param_of(request, "password");
+        Ok(response)
+    })
+    .await;
+    let socket = match socket {
+        Ok(socket) => socket,
+        Err(e) => {
+            error!("WebSocket handshake with client {} failed: {:?}", client, e);
+            return;
+        }
+    };
+
+    let (mut ws_sender, mut ws_receiver) = socket.split();
+
+    if list_rooms {
+        let list = [RoomInfo {
+            name: ROOM_NAME.to_string(),
+            players: players.load(Ordering::Relaxed),
+            locked: false,
+        }];
+        if let Ok(json) = serde_json::to_string(&list) {
+            ws_sender.send(Message::Text(json.into())).await.ok();
+        }
+        ws_sender.send(Message::Close(None)).await.ok();
+        return;
+    }
+
+    // checked once here, what the token says holds for the whole connection
+    let admitted = check_room(&room, &password)
+        .and_then(|()| check_ban(&bans, addr))
+        .and_then(|()| match secret {
+            Some(secret) => token::verify(&token, secret.as_bytes(), token::unix_now())
+                .map(Some)
+                .map_err(|reason| reason.to_string()),
+            None => Ok(None),
+        });
+    let claims = match admitted {
+        Ok(claims) => claims,
+        Err(reason) => {
+            info!("Client {} rejected: {}", client, reason);
+            let frame = CloseFrame {
+                code: CloseCode::Policy,
+                reason: format!("{}{}", REJECTION_PREFIX, reason).into(),
+            };
+            ws_sender.send(Message::Close(Some(frame))).await.ok();
+            return;
+        }
+    };
+    let (outgoing_tx, mut outgoing_rx) = mpsc::unbounded_channel();
+
+    if events
+        .send(ServerEvent::Connected {
+            client,
+            addr,
+            outgoing: outgoing_tx,
+            claims,
+        })
+        .is_err()
+    {
+        return;
+    }
+    players.fetch_add(1, Ordering::Relaxed);
+
+    // forward everything the simulation wants to send to this client
+    let sender = tokio::spawn(async move {
+        while let Some(msg) = outgoing_rx.recv().await {
+            let to_send = match msg {
+                WSMessages::Sync(s) => Message::Binary(s.into()),
+                WSMessages::Message(text) => Message::Text(text.into()),
+                WSMessages::Disconnected(reason) => {
+                    let frame = CloseFrame {
+                        code: CloseCode::Policy,
+                        reason: reason.into(),
+                    };
+                    ws_sender.send(Message::Close(Some(frame))).await.ok();
+                    break;
+                }
+                WSMessages::Connected | WSMessages::Offline => continue,
+            };
+            if let Err(e) = ws_sender.send(to_send).await {
+                eprintln!("Failed to send to client {}: {:?}", client, e);
+                break;
+            }
+        }
+    });
+
+    while let Some(message) = ws_receiver.next().await {
+        match message {
+            Ok(Message::Binary(bytes)) => {
+                events
+                    .send(ServerEvent::Message(client, WSMessages::Sync(bytes.to_vec())))
+                    .ok();
+            }
+            Ok(Message::Close(_)) => break,
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("WebSocket error from client {}: {:?}", client, e);
+                break;
+            }
+        }
+    }
+
+    sender.abort();
+    players.fetch_sub(1, Ordering::Relaxed);
+    events.send(ServerEvent::Disconnected(client)).ok();
+    info!("Client {} disconnected", client);
+}
@@ -0,0 +1,69 @@
+/////////////////////////////////////////////////////////
+///////////////////////// Server mod ////////////////////////
+/////////////////////////////////////////////////////////
+pub mod interest;
+pub mod listener;
+pub mod resource;
+pub mod simulation;
+pub mod validation;
+
+use crate::plugins::menu::GameState;
+use crate::plugins::network::native::MultiplayerRuntime;
+use bevy::prelude::*;
+use listener::start_server;
+use resource::{BanList, ConnectedClients};
+use crate::plugins::network::replication::register_replicated_components;
+use crate::plugins::network::presence::HEARTBEAT_INTERVAL;
+use bevy::time::common_conditions::on_timer;
+use interest::InterestGrid;
+use simulation::{
+    apply_movement_intents, broadcast_replicated, broadcast_state, drop_silent_clients,
+    handle_server_events, send_hidden_heartbeats,
+};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Builder;
+
+/// Headless authoritative server: clients only tell us what they want to do,
+/// the physics here decides where everybody actually is.
+pub struct ServerPlugin;
+
+impl Plugin for ServerPlugin {
+    fn build(&self, app: &mut App) {
+        let mp_runtime = Arc::new(
+            Builder::new_multi_thread()
+                .worker_threads(2)
+                .thread_name("server-workers")
+                .enable_all()
+                .build()
+                .expect("Failed to create Tokio runtime for server"),
+        );
+
+        // same list as the clients, or keys won't match up
+        register_replicated_components(app);
+
+        app.insert_resource(MultiplayerRuntime(mp_runtime))
+            .init_resource::<ConnectedClients>()
+            .init_resource::<BanList>()
+            .init_resource::<InterestGrid>()
+            .add_systems(Startup, start_server)
+            .add_systems(
+                Update,
+                (
+                    handle_server_events,
+                    drop_silent_clients,
+                    apply_movement_intents,
+                    broadcast_state,
+                    broadcast_replicated,
+                )
+                    .chain()
+                    .run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(
+                Update,
+                send_hidden_heartbeats
+                    .run_if(in_state(GameState::Playing))
+                    .run_if(on_timer(Duration::from_secs_f32(HEARTBEAT_INTERVAL))),
+            );
+    }
+}
@@ -0,0 +1,223 @@
+/////////////////////////////////////////////////////////
+///////////////////////// resource ////////////////////////
+/////////////////////////////////////////////////////////
+use super::interest::DEFAULT_INTEREST_RADIUS;
+use super::validation::{Violation, ViolationCounter};
+use crate::plugins::network::chat::ChatLimiter;
+use crate::plugins::network::config::command_line;
+use crate::plugins::network::identity::sanitize_name;
+use crate::plugins::network::protocol::NetMessage;
+use crate::plugins::network::resource::WSMessages;
+use crate::plugins::network::token::JoinClaims;
+use bevy::prelude::*;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{UnboundedReceiver as Receiver, UnboundedSender as Sender};
+
+pub type ClientId = u64;
+
+const DEFAULT_BIND: &str = "0.0.0.0:9001";
+const DEFAULT_TICK_RATE: f64 = 30.0;
+const BAN_TIME: Duration = Duration::from_secs(300); // how long a kicked cheater stays out
+
+#[derive(Resource, Debug, Clone)]
+pub struct ServerConfig {
+    pub bind: String,
+    pub tick_rate: f64,
+    // map everyone plays on, a random one unless given
+    pub seed: Option<u64>,
+    // how far away other players still get sent to a client
+    pub interest_radius: f32,
+    // when set, clients need a join token signed with it (see `network::token`)
+    pub token_secret: Option<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: DEFAULT_BIND.to_string(),
+            tick_rate: DEFAULT_TICK_RATE,
+            seed: None,
+            interest_radius: DEFAULT_INTEREST_RADIUS,
+            token_secret: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// reads `--bind <addr>`, `--tick-rate <hz>`, `--seed <n>`, `--interest-radius <m>` and
+    /// `--token-secret <secret>`, falling back to the `SERVER_BIND` and `SERVER_TOKEN_SECRET`
+    /// env variables and then the defaults
+    pub fn from_args() -> Self {
+        let mut config = Self::default();
+
+        if let Ok(bind) = std::env::var("SERVER_BIND") {
+            config.bind = bind;
+        }
+        if let Ok(secret) = std::env::var("SERVER_TOKEN_SECRET") {
+            config.token_secret = Some(secret);
+        }
+
+        for (key, value) in command_line() {
+            match key.as_str() {
+                "bind" => config.bind = value,
+                "tick_rate" => match value.parse::<f64>() {
+                    Ok(rate) if rate > 0.0 => config.tick_rate = rate,
+                    _ => warn!("Ignoring invalid tick rate: {}", value),
+                },
+                "seed" => match value.parse() {
+                    Ok(seed) => config.seed = Some(seed),
+                    Err(_) => warn!("Ignoring invalid map seed: {}", value),
+                },
+                "interest_radius" => match value.parse::<f32>() {
+                    Ok(radius) if radius > 0.0 => config.interest_radius = radius,
+                    _ => warn!("Ignoring invalid interest radius"),
+                },
+                "token_secret" => config.token_secret = Some(value),
+                _ => warn!("Unknown server argument: --{}", key.replace('_', "-")),
+            }
+        }
+
+        // anyone could sign tokens for an empty secret, that's worse than asking for none
+        if config.token_secret.as_deref() == Some("") {
+            eprintln!("--token-secret (or SERVER_TOKEN_SECRET) is empty, refusing to start");
+            std::process::exit(1);
+        }
+
+        config
+    }
+}
+
+#[derive(Debug)]
+pub enum ServerEvent {
+    Connected {
+        client: ClientId,
+        addr: IpAddr,
+        outgoing: Sender<WSMessages>,
+        // who their join token says they are, if the server asks for one
+        claims: Option<JoinClaims>,
+    },
+    Message(ClientId, WSMessages),
+    Disconnected(ClientId),
+}
+
+#[derive(Resource, Debug)]
+pub struct ServerChannels {
+    pub incomming: Receiver<ServerEvent>,
+}
+
+#[derive(Debug)]
+pub struct ClientSlot {
+    pub outgoing: Sender<WSMessages>,
+    pub addr: IpAddr,
+    pub entity: Option<Entity>,
+    // the id they joined as, set right away unlike the entity's components
+    pub player: Option<i64>,
+    // elapsed seconds when we last heard from this client
+    pub last_seen: f32,
+    pub violations: ViolationCounter,
+    pub chat: ChatLimiter,
+    // a token holder can only ever join as this
+    pub claims: Option<JoinClaims>,
+}
+
+impl ClientSlot {
+    pub fn send(&self, msg: &NetMessage) {
+        self.send_encoded(msg.encode());
+    }
+
+    /// For messages that go to many clients, so they are only encoded once
+    pub fn send_encoded(&self, bytes: Vec<u8>) {
+        self.outgoing.send(WSMessages::Sync(bytes)).ok();
+    }
+
+    /// The id and name the client joins as, what it asked for unless its token says otherwise
+    pub fn identity(&self, id: i64, name: &str) -> (i64, String) {
+        match &self.claims {
+            Some(claims) => (claims.id, sanitize_name(&claims.name, claims.id)),
+            None => (id, sanitize_name(name, id)),
+        }
+    }
+
+    /// Counts what the client got caught doing, true once it has done enough to be kicked
+    pub fn punish(&mut self, client: ClientId, violations: &[Violation], now: f64) -> bool {
+        let mut kick = false;
+        for &violation in violations {
+            warn!("Client {}: {}", client, violation);
+            kick |= self.violations.record(violation, now);
+        }
+        kick
+    }
+
+    /// Closes the socket with a reason the client can show
+    pub fn kick(&self, reason: &str) {
+        self.outgoing
+            .send(WSMessages::Disconnected(reason.to_string()))
+            .ok();
+    }
+}
+
+/// Who got kicked for cheating, by player id and by address. A cheating client won't
+/// honour the close reason, so the listener and the simulation both check here
+#[derive(Resource, Debug, Clone, Default)]
+pub struct BanList(Arc<Mutex<HashMap<Banned, Instant>>>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Banned {
+    Player(i64),
+    Address(IpAddr),
+}
+
+impl BanList {
+    pub fn ban(&self, who: impl IntoIterator<Item = Banned>) {
+        let until = Instant::now() + BAN_TIME;
+        let mut bans = self.0.lock().unwrap();
+        for banned in who {
+            bans.insert(banned, until);
+        }
+    }
+
+    pub fn is_banned(&self, who: Banned) -> bool {
+        let now = Instant::now();
+        let mut bans = self.0.lock().unwrap();
+        bans.retain(|_, until| *until > now);
+        bans.contains_key(&who)
+    }
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct ConnectedClients {
+    pub clients: HashMap<ClientId, ClientSlot>,
+}
+
+impl ConnectedClients {
+    pub fn broadcast(&self, msg: &NetMessage, except: ClientId) {
+        let bytes = msg.encode();
+        for (&client, slot) in &self.clients {
+            if client != except {
+                slot.outgoing.send(WSMessages::Sync(bytes.clone())).ok();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bans_keep_out_the_player_and_their_address() {
+        let bans = BanList::default();
+        let addr: IpAddr = [10, 0, 0, 7].into();
+        bans.ban([Banned::Player(42), Banned::Address(addr)]);
+
+        assert!(bans.is_banned(Banned::Player(42)));
+        assert!(bans.is_banned(Banned::Address(addr)));
+        assert!(!bans.is_banned(Banned::Player(43)));
+        assert!(!bans.is_banned(Banned::Address([10, 0, 0, 8].into())));
+        // the listener's copy sees the same list
+        assert!(bans.clone().is_banned(Banned::Player(42)));
+    }
+}
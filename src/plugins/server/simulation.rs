@@ -0,0 +1,808 @@
+//////////////////////////////////////////////////////////////
+///////////////////////// simulation /////////////////////////
+//////////////////////////////////////////////////////////////
+use super::interest::{InterestGrid, Relevance};
+use super::resource::{
+    BanList, Banned, ClientId, ClientSlot, ConnectedClients, ServerChannels, ServerConfig,
+    ServerEvent,
+};
+use super::validation::{LastReport, Violation, max_horizontal_speed, validate};
+use crate::components::entities::DisplayName;
+use crate::components::vitals::Movement;
+use crate::plugins::GameLayer;
+use crate::plugins::map::MapSettings;
+use crate::plugins::network::chat::clamp_chat;
+use crate::plugins::network::identity::sanitize_name;
+use crate::plugins::network::presence::PEER_TIMEOUT;
+use crate::plugins::network::protocol::{
+    ChatMessage, ComponentUpdate, HeartbeatInfo, JoinInfo, LeaveInfo, MapInfo, NetMessage,
+    PongInfo, ProtocolError, REJECTION_PREFIX, SERVER_ID,
+};
+use crate::plugins::network::replication::{
+    Authority, DISPLAY_NAME, ReplicationRegistry, SentComponent, component_key,
+};
+use crate::plugins::network::resource::WSMessages;
+use crate::plugins::network::synchronizer::Synchronizer;
+use crate::plugins::player::PLAYER_SCALE;
+use crate::plugins::player::bundle::SimplePlayerBundle;
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+const GROUND_CHECK_DISTANCE: f32 = 1.4 * PLAYER_SCALE.y + 0.1;
+
+////////////////////////////////////////////////////////
+//////////////////////// Define ////////////////////////
+////////////////////////////////////////////////////////
+#[derive(Component)]
+pub struct ServerPlayer {
+    pub client: ClientId,
+}
+
+/// What the client asked for, the server decides what actually happens
+#[derive(Component, Default)]
+pub struct MovementIntent {
+    pub velocity: Vec3,
+    pub jump: bool,
+    last: Option<LastReport>,
+    // the client's last full report of itself, deltas build on top of it
+    keyframe: Synchronizer,
+}
+
+impl MovementIntent {
+    // takes what we trust from the latest report, the rest is up to the simulation
+    fn apply(
+        &mut self,
+        mut reported: Synchronizer,
+        synchronizer: &mut Synchronizer,
+        movement: &Movement,
+        now: f64,
+    ) -> Vec<Violation> {
+        let violations = validate(
+            &mut reported,
+            self.last.as_ref(),
+            synchronizer.pos,
+            movement,
+            now,
+        );
+
+        self.update(&reported, movement);
+        self.last = Some(LastReport {
+            pos: reported.pos,
+            vertical: reported.vel.y,
+            at: now,
+        });
+        synchronizer.rot = reported.rot;
+        synchronizer.speed = reported.speed;
+        synchronizer.animation_playing = reported.animation_playing;
+        synchronizer.input_seq = reported.input_seq;
+
+        violations
+    }
+
+    fn update(&mut self, inc: &Synchronizer, movement: &Movement) {
+        let max_speed = max_horizontal_speed(movement);
+        self.velocity = Vec3::new(inc.vel.x, 0.0, inc.vel.z).clamp_length_max(max_speed);
+
+        // a jump shows up as a sudden upwards velocity from the client
+        let jump_threshold = movement.jump_strength * 0.5;
+        let last_vertical = self.last.map_or(0.0, |last| last.vertical);
+        if inc.vel.y > jump_threshold && last_vertical <= jump_threshold {
+            self.jump = true;
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////
+///////////////// Handle incomming traffic /////////////////
+////////////////////////////////////////////////////////////
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn handle_server_events(
+    mut channels: ResMut<ServerChannels>,
+    mut clients: ResMut<ConnectedClients>,
+    mut commands: Commands,
+    mut query: Query<(&mut Synchronizer, &mut MovementIntent, &Movement)>,
+    names: Query<&DisplayName>,
+    registry: Res<ReplicationRegistry>,
+    map: Res<MapSettings>,
+    bans: Res<BanList>,
+    time: Res<Time>,
+) {
+    let now = time.elapsed_secs();
+    let mut cheaters = Vec::new();
+
+    while let Ok(event) = channels.incomming.try_recv() {
+        match event {
+            ServerEvent::Connected {
+                client,
+                addr,
+                outgoing,
+                claims,
+            } => {
+                clients.clients.insert(
+                    client,
+                    ClientSlot {
+                        outgoing,
+                        addr,
+                        entity: None,
+                        player: None,
+                        last_seen: now,
+                        violations: default(),
+                        chat: default(),
+                        claims,
+                    },
+                );
+            }
+
+            ServerEvent::Message(client, WSMessages::Sync(inc_bytes)) => {
+                let Some(slot) = clients.clients.get_mut(&client) else {
+                    continue;
+                };
+                slot.last_seen = now;
+
+                let message = match NetMessage::decode(&inc_bytes) {
+                    Ok(message) => message,
+                    Err(e) => {
+                        eprintln!("Bad packet from client {}: {}", client, e);
+                        continue;
+                    }
+                };
+
+                match message {
+                    NetMessage::Join(join) => {
+                        if slot.entity.is_none() {
+                            let (id, name) = slot.identity(join.id, &join.name);
+                            let inc_sync = Synchronizer { id, ..default() };
+                            join_player(
+                                &inc_sync,
+                                name,
+                                client,
+                                &mut clients,
+                                &mut commands,
+                                &query,
+                                &names,
+                                &map,
+                                &bans,
+                            );
+                        }
+                    }
+
+                    NetMessage::StateSync(inc_sync) => {
+                        // older clients skip the hello and go straight to state
+                        let Some(entity) = slot.entity else {
+                            let (id, name) = slot.identity(inc_sync.id, "");
+                            let inc_sync = Synchronizer { id, ..inc_sync };
+                            join_player(
+                                &inc_sync,
+                                name,
+                                client,
+                                &mut clients,
+                                &mut commands,
+                                &query,
+                                &names,
+                                &map,
+                                &bans,
+                            );
+                            continue;
+                        };
+
+                        if let Ok((mut synchronizer, mut intent, movement)) =
+                            query.get_mut(entity)
+                        {
+                            intent.keyframe = inc_sync.clone();
+                            let violations = intent.apply(
+                                inc_sync,
+                                &mut synchronizer,
+                                movement,
+                                time.elapsed_secs_f64(),
+                            );
+                            if slot.punish(client, &violations, time.elapsed_secs_f64()) {
+                                cheaters.push(client);
+                            }
+                        }
+                    }
+
+                    NetMessage::StateDelta(delta) => {
+                        // deltas for a keyframe we never got are useless, wait for the next
+                        if let Some(entity) = slot.entity
+                            && let Ok((mut synchronizer, mut intent, movement)) =
+                                query.get_mut(entity)
+                            && intent.keyframe.keyframe == delta.base
+                        {
+                            let mut reported = intent.keyframe.clone();
+                            reported.apply_delta(&delta);
+                            let violations = intent.apply(
+                                reported,
+                                &mut synchronizer,
+                                movement,
+                                time.elapsed_secs_f64(),
+                            );
+                            if slot.punish(client, &violations, time.elapsed_secs_f64()) {
+                                cheaters.push(client);
+                            }
+                        }
+                    }
+
+                    NetMessage::Leave(_) => {
+                        leave_player(client, &mut clients, &mut commands, &query);
+                    }
+
+                    // the server's clock is the one stamped on everyone's state
+                    NetMessage::Ping(ping) => {
+                        slot.send(&NetMessage::Pong(PongInfo {
+                            id: SERVER_ID,
+                            to: ping.id,
+                            seq: ping.seq,
+                            sent_at: ping.sent_at,
+                            time: time.elapsed_secs_f64(),
+                        }));
+                    }
+
+                    NetMessage::Component(mut update) => {
+                        let owner = slot.entity.and_then(|entity| {
+                            query.get(entity).ok().map(|(sync, _, _)| (entity, sync.id))
+                        });
+                        let named_by_token = slot.claims.is_some();
+                        if accept_component(
+                            client,
+                            &mut update,
+                            owner,
+                            named_by_token,
+                            &registry,
+                            &mut commands,
+                        ) {
+                            clients.broadcast(&NetMessage::Component(update), client);
+                        }
+                    }
+
+                    NetMessage::Chat(message) => {
+                        // only players speak, and only for themselves
+                        let speaker = slot
+                            .entity
+                            .and_then(|entity| query.get(entity).ok())
+                            .map(|(sync, _, _)| sync.id);
+                        if speaker != Some(message.id) {
+                            continue;
+                        }
+                        if !slot.chat.allow(time.elapsed_secs_f64()) {
+                            warn!("Client {} is chatting too fast", client);
+                            continue;
+                        }
+                        if let Some(text) = clamp_chat(&message.text) {
+                            let message = ChatMessage { id: message.id, text };
+                            clients.broadcast(&NetMessage::Chat(message), client);
+                        }
+                    }
+
+                    // the map is ours to pick
+                    NetMessage::Heartbeat(_) | NetMessage::Pong(_) | NetMessage::Map(_) => {}
+                }
+            }
+
+            ServerEvent::Disconnected(client) => {
+                leave_player(client, &mut clients, &mut commands, &query);
+            }
+
+            _ => {}
+        }
+    }
+
+    for client in cheaters {
+        if let Some(slot) = clients.clients.get(&client) {
+            info!("Client {} kicked for cheating", client);
+            // a reconnect would start over with a clean record
+            let player = slot.player.map(Banned::Player);
+            bans.ban(player.into_iter().chain([Banned::Address(slot.addr)]));
+            slot.kick(&format!(
+                "{}kicked: too many movement violations",
+                REJECTION_PREFIX
+            ));
+            leave_player(client, &mut clients, &mut commands, &query);
+        }
+    }
+}
+
+// clients only speak for their own player, and only about what is theirs to say
+fn accept_component(
+    client: ClientId,
+    update: &mut ComponentUpdate,
+    owner: Option<(Entity, i64)>,
+    named_by_token: bool,
+    registry: &ReplicationRegistry,
+    commands: &mut Commands,
+) -> bool {
+    let Some(component) = registry.get(update.key) else {
+        warn!(
+            "Client {} sent unregistered component {:#x}",
+            client, update.key
+        );
+        return false;
+    };
+    let Some((entity, _)) = owner.filter(|(_, id)| *id == update.id) else {
+        warn!(
+            "Client {} may not set '{}' of player {}",
+            client, component.name, update.id
+        );
+        return false;
+    };
+    if component.rule.authority != Authority::Owner {
+        warn!(
+            "Client {} may not set '{}', the server owns it",
+            client, component.name
+        );
+        return false;
+    }
+
+    // whoever holds a token plays under its name, everyone else gets theirs cleaned up
+    if update.key == component_key(DISPLAY_NAME) {
+        if named_by_token {
+            warn!(
+                "Client {} may not rename player {}, their token names them",
+                client, update.id
+            );
+            return false;
+        }
+        match clean_display_name(&update.data, update.id) {
+            Ok(data) => update.data = data,
+            Err(e) => {
+                eprintln!("Bad '{}' from client {}: {}", component.name, client, e);
+                return false;
+            }
+        }
+    }
+
+    // we keep a copy too, it's what later arrivals see
+    match (component.apply)(&mut commands.entity(entity), &update.data) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("Bad '{}' from client {}: {}", component.name, client, e);
+            false
+        }
+    }
+}
+
+fn clean_display_name(data: &[u8], id: i64) -> Result<Vec<u8>, ProtocolError> {
+    let config = bincode::config::standard();
+    let (DisplayName(name), _): (DisplayName, _) = bincode::serde::decode_from_slice(data, config)
+        .map_err(|e| ProtocolError::Malformed(e.to_string()))?;
+    bincode::serde::encode_to_vec(DisplayName(sanitize_name(&name, id)), config)
+        .map_err(|e| ProtocolError::Malformed(e.to_string()))
+}
+
+pub(crate) fn drop_silent_clients(
+    mut clients: ResMut<ConnectedClients>,
+    mut commands: Commands,
+    query: Query<(&mut Synchronizer, &mut MovementIntent, &Movement)>,
+    time: Res<Time>,
+) {
+    let now = time.elapsed_secs();
+    let silent: Vec<ClientId> = clients
+        .clients
+        .iter()
+        .filter(|(_, slot)| now - slot.last_seen > PEER_TIMEOUT)
+        .map(|(client, _)| *client)
+        .collect();
+
+    for client in silent {
+        info!("Client {} timed out", client);
+        if let Some(slot) = clients.clients.get(&client) {
+            slot.kick("timed out");
+        }
+        leave_player(client, &mut clients, &mut commands, &query);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn join_player(
+    inc: &Synchronizer,
+    name: String,
+    client: ClientId,
+    clients: &mut ConnectedClients,
+    commands: &mut Commands,
+    query: &Query<(&mut Synchronizer, &mut MovementIntent, &Movement)>,
+    names: &Query<&DisplayName>,
+    map: &MapSettings,
+    bans: &BanList,
+) {
+    if bans.is_banned(Banned::Player(inc.id)) {
+        info!(
+            "Client {} rejected: player {} was kicked for cheating",
+            client, inc.id
+        );
+        if let Some(slot) = clients.clients.remove(&client) {
+            slot.kick(&format!(
+                "{}kicked for cheating, try again later",
+                REJECTION_PREFIX
+            ));
+        }
+        return;
+    }
+
+    // a second tab or a shared token would fight the first one over the same player,
+    // the slots know even when both join on the same tick
+    if clients
+        .clients
+        .values()
+        .any(|slot| slot.player == Some(inc.id))
+    {
+        info!(
+            "Client {} rejected: player {} is already connected",
+            client, inc.id
+        );
+        if let Some(slot) = clients.clients.remove(&client) {
+            slot.kick(&format!("{}already connected", REJECTION_PREFIX));
+        }
+        return;
+    }
+
+    let entity = spawn_server_player(inc, client, commands);
+    commands.entity(entity).insert(DisplayName(name.clone()));
+
+    // tell the newcomer where they are and who is already here, and everyone else about the newcomer
+    if let Some(slot) = clients.clients.get(&client) {
+        slot.send(&NetMessage::Map(MapInfo {
+            host: SERVER_ID,
+            settings: map.clone(),
+        }));
+        for other in clients.clients.values() {
+            if let Some(other_entity) = other.entity
+                && let Ok((other_sync, _, _)) = query.get(other_entity)
+            {
+                let other_name = names.get(other_entity).map(|name| name.0.clone());
+                slot.send(&NetMessage::Join(JoinInfo {
+                    id: other_sync.id,
+                    name: other_name.unwrap_or_else(|_| sanitize_name("", other_sync.id)),
+                }));
+                slot.send(&NetMessage::StateSync(other_sync.clone()));
+            }
+        }
+    }
+    clients.broadcast(&NetMessage::Join(JoinInfo { id: inc.id, name }), client);
+
+    if let Some(slot) = clients.clients.get_mut(&client) {
+        slot.entity = Some(entity);
+        slot.player = Some(inc.id);
+    }
+}
+
+fn leave_player(
+    client: ClientId,
+    clients: &mut ConnectedClients,
+    commands: &mut Commands,
+    query: &Query<(&mut Synchronizer, &mut MovementIntent, &Movement)>,
+) {
+    let Some(slot) = clients.clients.remove(&client) else {
+        return;
+    };
+    let Some(entity) = slot.entity else {
+        return;
+    };
+
+    if let Ok((synchronizer, _, _)) = query.get(entity) {
+        info!("Player {} left", synchronizer.id);
+        clients.broadcast(&NetMessage::Leave(LeaveInfo { id: synchronizer.id }), client);
+    }
+    commands.entity(entity).despawn();
+}
+
+///////////////////////////////////////////////////////////
+//////////////////////// Simulate /////////////////////////
+///////////////////////////////////////////////////////////
+pub(crate) fn apply_movement_intents(
+    mut query: Query<(
+        &mut Transform,
+        &mut LinearVelocity,
+        &mut Movement,
+        &mut MovementIntent,
+    )>,
+    spatial_query: SpatialQuery,
+) {
+    let ground_filter = SpatialQueryFilter::from_mask(GameLayer::Environment);
+
+    for (mut transform, mut velocity, mut movement, mut intent) in &mut query {
+        if transform.translation.y < 0.0 {
+            transform.translation.y = 0.2;
+            velocity.y = 0.0;
+        }
+
+        let grounded = spatial_query
+            .cast_ray(
+                transform.translation,
+                Dir3::NEG_Y,
+                GROUND_CHECK_DISTANCE,
+                true,
+                &ground_filter,
+            )
+            .is_some();
+
+        // only count landing while falling, not on the way up through a platform
+        if grounded && velocity.y <= 0.0 {
+            movement.is_grounded = true;
+            movement.current_jumps = 0;
+        } else if !grounded {
+            movement.is_grounded = false;
+        }
+
+        if intent.jump && movement.can_jump() {
+            movement.current_jumps += !movement.is_grounded as u32;
+            velocity.y = movement.jump_strength;
+            movement.is_grounded = false;
+        }
+        intent.jump = false;
+
+        velocity.x = intent.velocity.x;
+        velocity.z = intent.velocity.z;
+    }
+}
+
+///////////////////////////////////////////////////////////
+///////////////// Handle outgoing traffic /////////////////
+///////////////////////////////////////////////////////////
+#[allow(clippy::type_complexity)]
+pub(crate) fn broadcast_state(
+    clients: Res<ConnectedClients>,
+    config: Res<ServerConfig>,
+    mut grid: ResMut<InterestGrid>,
+    mut query: Query<(
+        Entity,
+        &ServerPlayer,
+        &Transform,
+        &LinearVelocity,
+        &mut Synchronizer,
+    )>,
+    time: Res<Time>,
+    mut tick: Local<u64>,
+) {
+    *tick += 1;
+    let mut states = HashMap::new();
+
+    for (entity, _, transform, velocity, mut synchronizer) in &mut query {
+        synchronizer.pos = transform.translation;
+        synchronizer.vel = velocity.0;
+        // one clock for every player, so clients can line them up
+        synchronizer.timestamp = time.elapsed_secs_f64();
+        states.insert(entity, NetMessage::StateSync(synchronizer.clone()).encode());
+    }
+    grid.rebuild(
+        config.interest_radius,
+        query
+            .iter()
+            .map(|(entity, _, transform, _, _)| (entity, transform.translation)),
+    );
+
+    for (entity, player, transform, _, _) in &query {
+        let Some(slot) = clients.clients.get(&player.client) else {
+            continue;
+        };
+        // the owner gets theirs every tick, acknowledging their last input
+        if let Some(state) = states.get(&entity) {
+            slot.send_encoded(state.clone());
+        }
+
+        for (other, distance) in grid.around(transform.translation) {
+            let relevance = Relevance::of(distance, config.interest_radius);
+            if other != entity
+                && relevance.due(*tick, other.index() as u64)
+                && let Some(state) = states.get(&other)
+            {
+                slot.send_encoded(state.clone());
+            }
+        }
+    }
+}
+
+/// Players out of someone's range get no state. They hear that they are out of range
+/// instead, so the client hides them and doesn't time them out
+pub(crate) fn send_hidden_heartbeats(
+    clients: Res<ConnectedClients>,
+    config: Res<ServerConfig>,
+    grid: Res<InterestGrid>,
+    query: Query<(Entity, &ServerPlayer, &Transform, &Synchronizer)>,
+) {
+    for (entity, player, transform, _) in &query {
+        let Some(slot) = clients.clients.get(&player.client) else {
+            continue;
+        };
+        // anyone in range is in the cells around, the rest are out of range without asking
+        let in_range: HashSet<Entity> = grid
+            .around(transform.translation)
+            .filter(|(_, distance)| {
+                Relevance::of(*distance, config.interest_radius) != Relevance::Hidden
+            })
+            .map(|(other, _)| other)
+            .collect();
+
+        for (other, _, _, other_sync) in &query {
+            if other != entity && !in_range.contains(&other) {
+                slot.send(&NetMessage::Heartbeat(HeartbeatInfo {
+                    id: other_sync.id,
+                    out_of_range: true,
+                }));
+            }
+        }
+    }
+}
+
+/// Components only we get to change, sent to everyone including their owner
+pub(crate) fn broadcast_replicated(
+    clients: Res<ConnectedClients>,
+    registry: Res<ReplicationRegistry>,
+    query: Query<(EntityRef, &Synchronizer), With<ServerPlayer>>,
+    joined: Query<(), Added<ServerPlayer>>,
+    time: Res<Time>,
+    mut sent: Local<HashMap<(Entity, u32), SentComponent>>,
+) {
+    let now = time.elapsed_secs_f64();
+    // a newcomer has none of it yet
+    let force = !joined.is_empty();
+
+    for (entity, synchronizer) in &query {
+        for (key, component) in registry.iter() {
+            if component.rule.authority != Authority::Server {
+                continue;
+            }
+            let Some(bytes) = (component.encode)(&entity) else {
+                continue;
+            };
+
+            let last = sent.entry((entity.id(), key)).or_default();
+            if last.should_send(&bytes, &component.rule, now, force) {
+                let update = NetMessage::Component(ComponentUpdate {
+                    id: synchronizer.id,
+                    key,
+                    data: bytes,
+                });
+                for slot in clients.clients.values() {
+                    slot.send(&update);
+                }
+            }
+        }
+    }
+}
+
+fn spawn_server_player(inc: &Synchronizer, client: ClientId, commands: &mut Commands) -> Entity {
+    info!("Client {} joined as player {}", client, inc.id);
+
+    let mut synchronizer = inc.clone();
+    synchronizer.vel = Vec3::ZERO;
+
+    commands
+        .spawn((
+            Name::new(format!("ServerPlayer_{}", client)),
+            SimplePlayerBundle::new(),
+            ServerPlayer { client },
+            MovementIntent {
+                keyframe: inc.clone(),
+                ..default()
+            },
+            synchronizer,
+            Collider::cuboid(
+                1.75 * PLAYER_SCALE.x,
+                2.8 * PLAYER_SCALE.y,
+                1.0 * PLAYER_SCALE.z,
+            ),
+            CollisionLayers::new([GameLayer::OnlinePlayer], [GameLayer::Environment]),
+        ))
+        .id()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::network::replication::register_replicated_components;
+    use bevy::ecs::world::CommandQueue;
+
+    fn rename(id: i64, name: &str) -> ComponentUpdate {
+        ComponentUpdate {
+            id,
+            key: component_key(DISPLAY_NAME),
+            data: bincode::serde::encode_to_vec(
+                DisplayName(name.to_string()),
+                bincode::config::standard(),
+            )
+            .unwrap(),
+        }
+    }
+
+    // what the server ends up calling the player, if it took the update at all
+    fn accept(update: &mut ComponentUpdate, owner_id: i64, named_by_token: bool) -> Option<String> {
+        let mut app = App::new();
+        register_replicated_components(&mut app);
+        let registry = app
+            .world_mut()
+            .remove_resource::<ReplicationRegistry>()
+            .unwrap();
+
+        let mut world = World::new();
+        let entity = world.spawn(DisplayName(String::from("Before"))).id();
+        let mut queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut queue, &world);
+        let accepted = accept_component(
+            1,
+            update,
+            Some((entity, owner_id)),
+            named_by_token,
+            &registry,
+            &mut commands,
+        );
+        queue.apply(&mut world);
+        accepted.then(|| world.get::<DisplayName>(entity).unwrap().0.clone())
+    }
+
+    #[test]
+    fn names_are_cleaned_before_anyone_sees_them() {
+        let typed = "  Mallory\u{7}\u{7} the very long named  ";
+        let mut update = rename(7, typed);
+        let stored = accept(&mut update, 7, false).unwrap();
+        assert_eq!(stored, sanitize_name(typed, 7));
+        assert!(!stored.contains('\u{7}'));
+        // and what goes out to the others is the cleaned one too
+        assert_eq!(update.data, rename(7, &stored).data);
+    }
+
+    #[test]
+    fn a_token_name_stays() {
+        assert_eq!(accept(&mut rename(7, "Someone else"), 7, true), None);
+    }
+
+    #[test]
+    fn only_the_owner_renames() {
+        assert_eq!(accept(&mut rename(8, "Mallory"), 7, false), None);
+    }
+
+    #[test]
+    fn two_connections_for_one_player_on_the_same_tick_let_one_in() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins);
+        register_replicated_components(&mut app);
+        let (events, incomming) = tokio::sync::mpsc::unbounded_channel();
+        app.insert_resource(ServerChannels { incomming })
+            .init_resource::<ConnectedClients>()
+            .init_resource::<BanList>()
+            .insert_resource(MapSettings::default())
+            .add_systems(Update, handle_server_events);
+
+        let mut outgoing = Vec::new();
+        for client in [1, 2] {
+            let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+            outgoing.push(rx);
+            events
+                .send(ServerEvent::Connected {
+                    client,
+                    addr: [127, 0, 0, 1].into(),
+                    outgoing: tx,
+                    claims: None,
+                })
+                .unwrap();
+        }
+        for client in [1, 2] {
+            let join = NetMessage::Join(JoinInfo {
+                id: 42,
+                name: String::from("Alice"),
+            });
+            events
+                .send(ServerEvent::Message(
+                    client,
+                    WSMessages::Sync(join.encode()),
+                ))
+                .unwrap();
+        }
+        app.update();
+
+        let clients = app.world().resource::<ConnectedClients>();
+        assert_eq!(clients.clients.len(), 1);
+        assert_eq!(clients.clients[&1].player, Some(42));
+        // the second one is told why, so it doesn't keep reconnecting
+        let rejected = std::iter::from_fn(|| outgoing[1].try_recv().ok()).any(|msg| match msg {
+            WSMessages::Disconnected(reason) => reason.starts_with(REJECTION_PREFIX),
+            _ => false,
+        });
+        assert!(rejected);
+        let players = app
+            .world_mut()
+            .query::<&ServerPlayer>()
+            .iter(app.world())
+            .count();
+        assert_eq!(players, 1);
+    }
+}
@@ -0,0 +1,188 @@
+//////////////////////////////////////////////////////////////
+///////////////////////// validation /////////////////////////
+//////////////////////////////////////////////////////////////
+//
+// Clients only tell us what they want to do, but nothing stops them from
+// lying about it. Reports that break the movement rules are clamped back
+// to what is possible and counted, whoever keeps at it gets kicked.
+use crate::components::vitals::Movement;
+use crate::plugins::network::synchronizer::Synchronizer;
+use bevy::prelude::*;
+use std::fmt;
+
+// clients scale their velocity by frame time, this is the slowest frame we honour
+const MAX_CLIENT_FRAME_TIME: f32 = 1.0 / 30.0;
+const TOLERANCE: f32 = 1.5; // frame hiccups and float noise, honest clients stay well below
+const TELEPORT_SLACK: f32 = 2.0; // a few bunched up packets worth of walking
+const TELEPORT_DISTANCE: f32 = 6.0; // clients snap to us past 3, twice that isn't lag anymore
+pub const MAX_VIOLATIONS: f32 = 10.0;
+const FORGIVE_RATE: f32 = 0.2; // violations forgotten per second
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    // claims a higher base speed than it has
+    SpeedStat,
+    // going up faster than a jump
+    Launch,
+    // jumping with no jumps left
+    AirJump,
+    // moved further between two reports than it could have walked
+    Teleport,
+}
+
+impl Violation {
+    fn weight(self) -> f32 {
+        match self {
+            // lag makes us think they are in the air when they just landed
+            Self::AirJump => 0.5,
+            Self::Teleport => 2.0,
+            _ => 1.0,
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SpeedStat => write!(f, "tampered speed"),
+            Self::Launch => write!(f, "launched upwards"),
+            Self::AirJump => write!(f, "jumped without jumps left"),
+            Self::Teleport => write!(f, "teleported"),
+        }
+    }
+}
+
+/// What the previous report from a client looked like, and when we got it
+#[derive(Debug, Clone, Copy)]
+pub struct LastReport {
+    pub pos: Vec3,
+    pub vertical: f32,
+    pub at: f64,
+}
+
+/// Slowly forgets, so the odd lag spike never adds up to a kick
+#[derive(Debug, Default)]
+pub struct ViolationCounter {
+    score: f32,
+    updated_at: f64,
+}
+
+impl ViolationCounter {
+    /// Counts `violation` and returns true once the client should be kicked
+    pub fn record(&mut self, violation: Violation, now: f64) -> bool {
+        let forgiven = (now - self.updated_at) as f32 * FORGIVE_RATE;
+        self.score = (self.score - forgiven).max(0.0) + violation.weight();
+        self.updated_at = now;
+        self.score >= MAX_VIOLATIONS
+    }
+}
+
+pub fn max_horizontal_speed(movement: &Movement) -> f32 {
+    movement.speed * movement.sprint_aplifier * std::f32::consts::SQRT_2 * MAX_CLIENT_FRAME_TIME
+}
+
+/// Checks a report against what `movement` allows and clamps whatever it
+/// overstates. `server_pos` is where our own simulation has the player
+pub fn validate(
+    reported: &mut Synchronizer,
+    last: Option<&LastReport>,
+    server_pos: Vec3,
+    movement: &Movement,
+    now: f64,
+) -> Vec<Violation> {
+    let mut violations = Vec::new();
+
+    if reported.speed > movement.speed * TOLERANCE {
+        violations.push(Violation::SpeedStat);
+        reported.speed = movement.speed;
+    }
+
+    if reported.vel.y > movement.jump_strength * TOLERANCE {
+        violations.push(Violation::Launch);
+        reported.vel.y = movement.jump_strength;
+    }
+
+    if let Some(last) = last {
+        // same rising edge the simulation treats as a jump
+        let threshold = movement.jump_strength * 0.5;
+        if reported.vel.y > threshold && last.vertical <= threshold && !movement.can_jump() {
+            violations.push(Violation::AirJump);
+        }
+
+        // velocity follows the client's frame time, so how fast they go is only
+        // told by how far they got. corrections move them a lot too, but always towards us
+        let walked = Vec2::new(reported.pos.x - last.pos.x, reported.pos.z - last.pos.z).length();
+        let max_speed = max_horizontal_speed(movement);
+        let allowed = max_speed * (now - last.at) as f32 * TOLERANCE + TELEPORT_SLACK;
+        if walked > allowed && reported.pos.distance(server_pos) > TELEPORT_DISTANCE {
+            violations.push(Violation::Teleport);
+        }
+    }
+
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn movement() -> Movement {
+        Movement {
+            speed: 100.0,
+            sprint_aplifier: 3.0,
+            jump_strength: 6.0,
+            is_grounded: true,
+            extra_jumps: 2,
+            current_jumps: 0,
+        }
+    }
+
+    // a client sprinting along x for `secs` at `fps`, `times` as fast as it should
+    fn report(secs: f32, fps: f32, times: f32) -> (Synchronizer, LastReport) {
+        let movement = movement();
+        let vel = Vec3::X * movement.speed * movement.sprint_aplifier / fps;
+        let reported = Synchronizer {
+            pos: vel * secs * times,
+            vel,
+            speed: movement.speed,
+            ..default()
+        };
+        let last = LastReport {
+            pos: Vec3::ZERO,
+            vertical: 0.0,
+            at: 0.0,
+        };
+        (reported, last)
+    }
+
+    #[test]
+    fn slow_frames_are_not_speeding() {
+        for fps in [15.0, 30.0, 144.0] {
+            let (mut reported, last) = report(0.1, fps, 1.0);
+            let server_pos = reported.pos;
+            let violations = validate(&mut reported, Some(&last), server_pos, &movement(), 0.1);
+            assert!(violations.is_empty(), "{:?} at {} fps", violations, fps);
+        }
+    }
+
+    #[test]
+    fn walking_for_a_while_is_not_teleporting() {
+        // server far behind, as after a lag spike
+        let (mut reported, last) = report(1.0, 30.0, 1.0);
+        let violations = validate(&mut reported, Some(&last), Vec3::ZERO, &movement(), 1.0);
+        assert!(violations.is_empty(), "{:?}", violations);
+    }
+
+    #[test]
+    fn covering_too_much_ground_is_teleporting() {
+        let (mut reported, last) = report(1.0, 30.0, 3.0);
+        let violations = validate(&mut reported, Some(&last), Vec3::ZERO, &movement(), 1.0);
+        assert_eq!(violations, [Violation::Teleport]);
+
+        // unless it is us pulling them back
+        let (mut reported, last) = report(1.0, 30.0, 3.0);
+        let server_pos = reported.pos;
+        let violations = validate(&mut reported, Some(&last), server_pos, &movement(), 1.0);
+        assert!(violations.is_empty());
+    }
+}
@@ -0,0 +1,241 @@
+/////////////////////////////////////////////////////////
+///////////////////////// relay /////////////////////////
+/////////////////////////////////////////////////////////
+//
+// Stand-in for the public broadcast service: every binary frame a peer sends
+// is forwarded to all the other peers connected with the same app id and room.
+// The first peer in a room sets its password, everyone after has to match it.
+// Connecting with `list_rooms` gets the app's rooms back instead.
+use crate::plugins::network::config::{percent_decode, query_param};
+use crate::plugins::network::protocol::{REJECTION_PREFIX, RoomInfo};
+use futures_util::{SinkExt, StreamExt};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{self, UnboundedSender as Sender};
+use tokio_tungstenite::{
+    accept_hdr_async,
+    tungstenite::Message,
+    tungstenite::handshake::server::{Request, Response},
+    tungstenite::protocol::{CloseFrame, frame::coding::CloseCode},
+};
+
+pub const DEFAULT_BIND: &str = "127.0.0.1:9000";
+
+type PeerId = u64;
+type Rooms = Arc<Mutex<HashMap<String, Room>>>;
+
+#[derive(Default)]
+struct Room {
+    password: String,
+    peers: HashMap<PeerId, Sender<Message>>,
+}
+
+/// Accepts peers on `bind` until the listener fails
+pub async fn run(bind: &str) -> std::io::Result<()> {
+    let listener = TcpListener::bind(bind).await?;
+    println!("Relay listening on {}", bind);
+    serve(listener).await
+}
+
+/// Runs the relay on an already bound listener, handy when the port is picked by the OS
+pub async fn serve(listener: TcpListener) -> std::io::Result<()> {
+    let rooms: Rooms = Arc::default();
+    let mut next_peer: PeerId = 0;
+
+    loop {
+        let (stream, _addr) = listener.accept().await?;
+        next_peer += 1;
+        tokio::spawn(handle_peer(stream, next_peer, rooms.clone()));
+    }
+}
+
+// the handshake callback signature is dictated by tungstenite
+#[allow(clippy::result_large_err)]
+async fn handle_peer(stream: TcpStream, peer: PeerId, rooms: Rooms) {
+    let mut app_id = String::new();
+    let mut room = String::new();
+    let mut password = String::new();
+    let mut list_rooms = false;
+    let socket = accept_hdr_async(stream, |request: &Request, response: Response| {
+        app_id = param_of(request, "app_id");
+        room = format!("{}/{}", app_id, param_of(request, "room"));
+        password = param_of(request, "password");
+        list_rooms = !param_of(request, "list_rooms").is_empty();
+        Ok(response)
+    })
+    .await;
+
+    let socket = match socket {
+        Ok(socket) => socket,
+        Err(e) => {
+            eprintln!("Handshake with peer {} failed: {:?}", peer, e);
+            return;
+        }
+    };
+
+    let (mut ws_sender, mut ws_receiver) = socket.split();
+
+    if list_rooms {
+        let list = room_list(&rooms, &app_id);
+        if let Ok(json) = serde_json::to_string(&list) {
+            ws_sender.send(Message::Text(json.into())).await.ok();
+        }
+        ws_sender.send(Message::Close(None)).await.ok();
+        return;
+    }
+
+    let (to_peer_tx, mut to_peer_rx) = mpsc::unbounded_channel::<Message>();
+    let admitted = {
+        let mut rooms = rooms.lock().unwrap();
+        let entry = rooms.entry(room.clone()).or_insert_with(|| Room {
+            password: password.clone(),
+            peers: HashMap::new(),
+        });
+        if entry.peers.is_empty() || entry.password == password {
+            entry.peers.insert(peer, to_peer_tx);
+            true
+        } else {
+            false
+        }
+    };
+    if !admitted {
+        println!("Peer {} gave the wrong password for room '{}'", peer, room);
+        let frame = CloseFrame {
+            code: CloseCode::Policy,
+            reason: format!("{}wrong room password", REJECTION_PREFIX).into(),
+        };
+        ws_sender.send(Message::Close(Some(frame))).await.ok();
+        return;
+    }
+    println!("Peer {} joined room '{}'", peer, room);
+
+    let sender = tokio::spawn(async move {
+        while let Some(msg) = to_peer_rx.recv().await {
+            if ws_sender.send(msg).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    while let Some(message) = ws_receiver.next().await {
+        match message {
+            Ok(Message::Binary(bytes)) => {
+                let rooms = rooms.lock().unwrap();
+                let Some(entry) = rooms.get(&room) else {
+                    continue;
+                };
+                for (&other, to_other) in &entry.peers {
+                    if other != peer {
+                        to_other.send(Message::Binary(bytes.clone())).ok();
+                    }
+                }
+            }
+            Ok(Message::Close(_)) => break,
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("WebSocket error from peer {}: {:?}", peer, e);
+                break;
+            }
+        }
+    }
+
+    sender.abort();
+
+    let mut rooms = rooms.lock().unwrap();
+    if let Some(entry) = rooms.get_mut(&room) {
+        entry.peers.remove(&peer);
+        if entry.peers.is_empty() {
+            rooms.remove(&room);
+        }
+    }
+    println!("Peer {} left room '{}'", peer, room);
+}
+
+fn room_list(rooms: &Rooms, app_id: &str) -> Vec<RoomInfo> {
+    let prefix = format!("{}/", app_id);
+    rooms
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|(key, room)| {
+            Some(RoomInfo {
+                name: key.strip_prefix(&prefix)?.to_string(),
+                players: room.peers.len(),
+                locked: !room.password.is_empty(),
+            })
+        })
+        .collect()
+}
+
+/// Native clients send headers, the browser can only use the query string.
+/// Both are percent encoded
+pub(crate) fn param_of(request: &Request, key: &str) -> String {
+    if let Some(header) = request.headers().get(key)
+        && let Ok(value) = header.to_str()
+    {
+        return percent_decode(value);
+    }
+
+    request
+        .uri()
+        .query()
+        .and_then(|query| query_param(query, key))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async};
+
+    type Peer = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    async fn relay() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener));
+        format!("ws://{}", addr)
+    }
+
+    async fn join(url: &str, room: &str) -> Peer {
+        let (peer, _) = connect_async(format!("{}/?app_id=test&room={}", url, room))
+            .await
+            .unwrap();
+        peer
+    }
+
+    async fn next_binary(peer: &mut Peer) -> Option<Vec<u8>> {
+        let next = tokio::time::timeout(Duration::from_millis(200), peer.next()).await;
+        match next {
+            Ok(Some(Ok(Message::Binary(bytes)))) => Some(bytes.to_vec()),
+            _ => None,
+        }
+    }
+
+    // the handshake answers before the room is joined, so keep sending until it arrives
+    async fn relayed(from: &mut Peer, to: &mut Peer, frame: &[u8]) -> bool {
+        for _ in 0..10 {
+            from.send(Message::Binary(frame.to_vec().into()))
+                .await
+                .unwrap();
+            if next_binary(to).await.as_deref() == Some(frame) {
+                return true;
+            }
+        }
+        false
+    }
+
+    #[tokio::test]
+    async fn peers_in_a_room_get_each_others_frames() {
+        let url = relay().await;
+        let mut first = join(&url, "lobby").await;
+        let mut second = join(&url, "lobby").await;
+        let mut elsewhere = join(&url, "other").await;
+
+        assert!(relayed(&mut first, &mut second, b"from first").await);
+        assert!(relayed(&mut second, &mut first, b"from second").await);
+        assert_eq!(next_binary(&mut elsewhere).await, None);
+    }
+}